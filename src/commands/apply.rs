@@ -2,10 +2,62 @@ use std::fs;
 use std::path::Path;
 
 use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
 
-use crate::templates;
+use crate::{templates, tokens};
 
-pub fn run() -> Result<()> {
+/// Config files that `--force-only` can target
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFile {
+    /// rustfmt.toml
+    Rustfmt,
+    /// clippy.toml
+    Clippy,
+    /// rust-toolchain.toml
+    Toolchain,
+    /// CLAUDE.md
+    Claude,
+    /// .cursorrules
+    Cursor,
+    /// .gitignore
+    Gitignore,
+}
+
+impl ConfigFile {
+    fn path(self) -> &'static str {
+        match self {
+            Self::Rustfmt => "rustfmt.toml",
+            Self::Clippy => "clippy.toml",
+            Self::Toolchain => "rust-toolchain.toml",
+            Self::Claude => "CLAUDE.md",
+            Self::Cursor => ".cursorrules",
+            Self::Gitignore => ".gitignore",
+        }
+    }
+
+    fn template(self) -> &'static str {
+        match self {
+            Self::Rustfmt => templates::RUSTFMT_TOML,
+            Self::Clippy => templates::CLIPPY_TOML,
+            Self::Toolchain => templates::RUST_TOOLCHAIN_TOML,
+            Self::Claude => templates::CLAUDE_MD,
+            Self::Cursor => templates::CURSOR_RULES,
+            Self::Gitignore => templates::GITIGNORE,
+        }
+    }
+}
+
+/// `force` overwrites every config file, `force_only` just the listed ones; `yes` skips the prompt.
+/// With `preview`, every change is printed as `+`/`-` lines and nothing is written.
+/// `cursor` writes `.cursorrules` instead of `CLAUDE.md`; `no_claude` skips `CLAUDE.md` alone.
+pub fn run(
+    force: bool,
+    force_only: &[ConfigFile],
+    yes: bool,
+    preview: bool,
+    no_claude: bool,
+    cursor: bool,
+) -> Result<()> {
     let cargo_toml = Path::new("Cargo.toml");
     if !cargo_toml.exists() {
         bail!("no Cargo.toml found — run this from a Rust project root");
@@ -14,6 +66,8 @@ pub fn run() -> Result<()> {
     let content = fs::read_to_string(cargo_toml)?;
     if content.contains("[lints.clippy]") {
         println!("Cargo.toml already has [lints.clippy] — skipping lints.");
+    } else if preview {
+        print_added("Cargo.toml", templates::CARGO_LINTS);
     } else {
         let mut content = content;
         content.push_str(templates::CARGO_LINTS);
@@ -21,41 +75,240 @@ pub fn run() -> Result<()> {
         println!("Added clippy lints to Cargo.toml");
     }
 
-    write_if_missing("rustfmt.toml", templates::RUSTFMT_TOML)?;
-    write_if_missing("clippy.toml", templates::CLIPPY_TOML)?;
-    write_if_missing("rust-toolchain.toml", templates::RUST_TOOLCHAIN_TOML)?;
-    write_if_missing("CLAUDE.md", templates::CLAUDE_MD)?;
+    let forced = |file: ConfigFile| force || force_only.contains(&file);
 
-    let gitignore = Path::new(".gitignore");
-    if gitignore.exists() {
-        let existing = fs::read_to_string(gitignore)?;
-        if !existing.contains("**/target") {
-            let mut merged = existing;
-            merged.push_str("\n# Added by cargo-syntax\n");
-            merged.push_str(templates::GITIGNORE);
-            fs::write(gitignore, merged)?;
-            println!("Appended to .gitignore");
+    let instructions =
+        if cursor { Some(ConfigFile::Cursor) } else { (!no_claude).then_some(ConfigFile::Claude) };
+    for file in [ConfigFile::Rustfmt, ConfigFile::Clippy, ConfigFile::Toolchain]
+        .into_iter()
+        .chain(instructions)
+    {
+        if forced(file) {
+            overwrite(file.path(), file.template(), yes, preview)?;
         } else {
-            println!(".gitignore already covers target/ — skipping.");
+            write_if_missing(file.path(), file.template(), preview)?;
         }
+    }
+
+    let workflow = ".github/workflows/token-budget.yml";
+    if force {
+        overwrite(workflow, templates::GITHUB_ACTIONS_CI, yes, preview)?;
+    } else {
+        write_if_missing(workflow, templates::GITHUB_ACTIONS_CI, preview)?;
+    }
+
+    if forced(ConfigFile::Gitignore) {
+        overwrite(".gitignore", templates::GITIGNORE, yes, preview)?;
     } else {
-        fs::write(gitignore, templates::GITIGNORE)?;
-        println!("Created .gitignore");
+        merge_gitignore(preview)?;
     }
 
     println!();
-    println!("Done! Run `cargo syntax check` to verify.");
+    if preview {
+        println!("Preview only — nothing was written. Run without --preview to apply.");
+    } else {
+        println!("Done! Run `cargo syntax check` to verify.");
+    }
 
     Ok(())
 }
 
-fn write_if_missing(name: &str, content: &str) -> Result<()> {
+/// Compare rustfmt.toml, clippy.toml and rust-toolchain.toml byte-for-byte with the templates
+/// and check that Cargo.toml has every template lint; exit 1 if anything is out of date
+pub fn check() -> Result<()> {
+    let Ok(cargo_toml) = fs::read_to_string("Cargo.toml") else {
+        bail!("no Cargo.toml found — run this from a Rust project root");
+    };
+
+    let mut outdated = 0;
+    let missing = missing_lints(&cargo_toml);
+    if missing.is_empty() {
+        println!("✓ Cargo.toml has all {} template lints", template_lints().count());
+    } else {
+        outdated += 1;
+        println!("✗ Cargo.toml is missing {} lint(s):", missing.len());
+        for lint in missing {
+            println!("+ {lint}");
+        }
+    }
+
+    for file in [ConfigFile::Rustfmt, ConfigFile::Clippy, ConfigFile::Toolchain] {
+        let name = file.path();
+        match fs::read_to_string(name) {
+            Ok(existing) if existing == file.template() => {
+                println!("✓ {name} matches the template");
+            }
+            Ok(existing) => {
+                outdated += 1;
+                println!("✗ {name} differs from the template:");
+                super::rewrite::print_diff(&existing, file.template());
+            }
+            Err(_) => {
+                outdated += 1;
+                println!("✗ {name} is missing");
+            }
+        }
+    }
+
+    println!();
+    if outdated == 0 {
+        println!("All config files match the canonical templates.");
+        return Ok(());
+    }
+    println!("{outdated} file(s) out of date — run `cargo syntax apply --force` to update them.");
+    std::process::exit(1);
+}
+
+/// Show how `apply` would change the existing Cargo.toml and .gitignore, as diffs; write nothing
+pub fn diff() -> Result<()> {
+    let Ok(cargo_toml) = fs::read_to_string("Cargo.toml") else {
+        bail!("no Cargo.toml found — run this from a Rust project root");
+    };
+
+    if cargo_toml.contains("[lints.clippy]") {
+        println!("Cargo.toml already has [lints.clippy] — no change.");
+    } else {
+        println!("Cargo.toml:");
+        super::rewrite::print_diff(&cargo_toml, &(cargo_toml.clone() + templates::CARGO_LINTS));
+    }
+
+    match fs::read_to_string(".gitignore") {
+        Err(_) => println!(".gitignore doesn't exist — it would be created from the template."),
+        Ok(existing) => match gitignore_addition(&existing) {
+            Some(addition) => {
+                println!(".gitignore:");
+                super::rewrite::print_diff(&existing, &(existing.clone() + &addition));
+            }
+            None => println!(".gitignore already covers target/ — no change."),
+        },
+    }
+
+    println!();
+    println!(
+        "Nothing was written. Run without --diff to apply, or --preview to see new files too."
+    );
+    Ok(())
+}
+
+/// `name = "level"` lines from `templates::CARGO_LINTS`
+fn template_lints() -> impl Iterator<Item = &'static str> {
+    templates::CARGO_LINTS.lines().map(str::trim).filter(|l| l.contains(" = "))
+}
+
+/// Template lints with no matching `name = "level"` line in `cargo_toml` (whitespace-insensitive)
+fn missing_lints(cargo_toml: &str) -> Vec<&'static str> {
+    let squash = |l: &str| l.split_whitespace().collect::<String>();
+    let present: Vec<String> = cargo_toml.lines().map(squash).collect();
+    template_lints().filter(|lint| !present.contains(&squash(lint))).collect()
+}
+
+fn merge_gitignore(preview: bool) -> Result<()> {
+    let gitignore = Path::new(".gitignore");
+    if !gitignore.exists() {
+        return write_if_missing(".gitignore", templates::GITIGNORE, preview);
+    }
+
+    let existing = fs::read_to_string(gitignore)?;
+    let Some(addition) = gitignore_addition(&existing) else {
+        println!(".gitignore already covers target/ — skipping.");
+        return Ok(());
+    };
+
+    if preview {
+        print_added(".gitignore", &addition);
+    } else {
+        fs::write(gitignore, existing + &addition)?;
+        println!("Appended to .gitignore");
+    }
+    Ok(())
+}
+
+/// Text to append to an existing .gitignore, or `None` if it already covers target/ and backups
+fn gitignore_addition(existing: &str) -> Option<String> {
+    if !existing.contains("**/target") {
+        Some(format!("\n# Added by cargo-syntax\n{}", templates::GITIGNORE))
+    } else if !existing.contains("*.orig") {
+        Some("\n# Added by cargo-syntax (rewrite backups)\n*.orig\n".to_string())
+    } else {
+        None
+    }
+}
+
+/// Show text that would be appended to (or make up) `name`, one `+` line each
+fn print_added(name: &str, added: &str) {
+    println!("{name}:");
+    tokens::separator(70);
+    for line in added.trim_start_matches('\n').lines() {
+        println!("+ {line}");
+    }
+    tokens::separator(70);
+}
+
+/// Replace `name` with the template, showing a diff and asking first unless `yes`
+fn overwrite(name: &str, content: &str, yes: bool, preview: bool) -> Result<()> {
+    let Ok(existing) = fs::read_to_string(name) else {
+        return write_if_missing(name, content, preview);
+    };
+    if existing == content {
+        println!("{name} already matches the template — skipping.");
+        return Ok(());
+    }
+
+    println!("{name}:");
+    super::rewrite::print_diff(&existing, content);
+    if preview {
+        return Ok(());
+    }
+    if !yes
+        && !matches!(tokens::ask_accept(&format!("Overwrite {name}? [y/n]"))?.as_str(), "y" | "Y")
+    {
+        println!("Kept {name}");
+        return Ok(());
+    }
+
+    fs::write(name, content).with_context(|| format!("failed to write {name}"))?;
+    println!("Overwrote {name}");
+    Ok(())
+}
+
+fn write_if_missing(name: &str, content: &str, preview: bool) -> Result<()> {
     let path = Path::new(name);
     if path.exists() {
         println!("{name} already exists — skipping.");
+    } else if preview {
+        print_added(&format!("{name} (new file)"), content);
     } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
         fs::write(path, content).with_context(|| format!("failed to write {name}"))?;
         println!("Created {name}");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_lints() {
+        assert!(missing_lints(templates::CARGO_LINTS).is_empty());
+
+        let partial = templates::CARGO_LINTS
+            .replace("todo = \"deny\"\n", "")
+            .replace("dbg_macro = \"deny\"", "dbg_macro=\"deny\"")
+            .replace("use_self = \"warn\"", "use_self = \"allow\"");
+        assert_eq!(missing_lints(&partial), ["todo = \"deny\"", "use_self = \"warn\""]);
+    }
+
+    #[test]
+    fn test_gitignore_addition() {
+        assert!(gitignore_addition("/target\n").unwrap().contains(templates::GITIGNORE));
+        assert_eq!(
+            gitignore_addition("**/target\n").unwrap().trim_end(),
+            "\n# Added by cargo-syntax (rewrite backups)\n*.orig"
+        );
+        assert_eq!(gitignore_addition("**/target\n*.orig\n"), None);
+    }
+}
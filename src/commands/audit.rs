@@ -1,24 +1,220 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
 
-use crate::tokens;
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use serde::Serialize;
 
-pub fn run() -> Result<()> {
-    let stats = tokens::scan_project()?;
+use crate::tokens::{self, CodeScope, Distribution, GradeBy, LineBreakdown};
 
-    println!("{:<60} {:>6} {:>8} {:>6}", "File", "Lines", "Tokens", "T/L");
-    println!("{}", "-".repeat(83));
+/// Comment tokens per code token above which a file is flagged as a rewrite target
+const HIGH_COMMENT_OVERHEAD: f64 = 0.5;
 
-    for f in &stats.files {
-        println!("{:<60} {:>6} {:>8} {:>5.1}", f.path, f.lines, f.tokens, f.ratio);
+/// Output style for `audit --format`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned table with totals and the efficiency grade
+    Table,
+    /// One `path lines tokens ratio` line per file, without headers
+    Compact,
+    /// Comma-separated values with a header row, for spreadsheets
+    Csv,
+    /// Everything, including totals, groups and distribution, as one JSON object
+    Json,
+}
+
+/// How `audit --group-by` aggregates files
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GroupBy {
+    /// Top-level module under the crate root (`src/commands/*.rs` → `commands`)
+    Module,
+    /// Immediate parent directory (`src/commands`)
+    Directory,
+    /// Most frequent commit author email of each file
+    Author,
+}
+
+impl GroupBy {
+    fn key(self, path: &str) -> String {
+        match self {
+            Self::Module => module_of(path),
+            Self::Directory => Path::new(path)
+                .parent()
+                .map(|p| p.display().to_string())
+                .filter(|p| !p.is_empty())
+                .unwrap_or_else(|| ".".to_string()),
+            Self::Author => primary_author(path).unwrap_or_else(|| "(untracked)".to_string()),
+        }
     }
+}
+
+#[derive(Serialize)]
+struct AuditOutput<'a> {
+    files: Vec<FileOutput<'a>>,
+    total_lines: usize,
+    total_tokens: usize,
+    ratio: f64,
+    grade: &'a str,
+    effective_tokens: usize,
+    code_lines: usize,
+    comment_lines: usize,
+    blank_lines: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distribution: Option<Distribution>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<Group<'a>>>,
+    /// Tokens including all code, when `total_tokens` covers only production or test code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    full_tokens: Option<usize>,
+    /// Set with `--ignore-generated`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated_files_excluded: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct Group<'a> {
+    name: String,
+    files: usize,
+    lines: usize,
+    tokens: usize,
+    ratio: f64,
+    top_file: &'a str,
+    top_file_tokens: usize,
+    /// Author owning the most tokens in the group (not set with `--group-by author`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_author: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FileOutput<'a> {
+    path: &'a str,
+    lines: usize,
+    tokens: usize,
+    ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_breakdown: Option<LineBreakdown>,
+}
 
+/// Which code `audit` counts
+#[derive(Clone, Copy)]
+pub struct Selection {
+    /// Only production or only test code; everything when `None`
+    pub scope: Option<CodeScope>,
+    /// Leave out files marked as generated
+    pub ignore_generated: bool,
+}
+
+/// `selection.scope` restricts every count to production or test code; the full total is shown
+/// alongside. `selection.ignore_generated` drops generated files before anything is counted.
+/// `grade_by` picks the token count the grade is computed from.
+pub fn run(
+    tokei: bool,
+    format: Format,
+    percentiles: bool,
+    group_by: Option<GroupBy>,
+    selection: Selection,
+    top: usize,
+    grade_by: GradeBy,
+) -> Result<()> {
+    let Selection { scope, ignore_generated } = selection;
+    if top > 0 && format != Format::Table {
+        bail!("--top only works with --format table");
+    }
+    let (full, generated) = if ignore_generated {
+        let (stats, generated) = tokens::scan_project()?.without_generated();
+        (stats, Some(generated))
+    } else {
+        (tokens::scan_project()?, None)
+    };
+    let full_tokens = scope.map(|_| full.total_tokens);
+    let mut stats = match scope {
+        Some(scope) => full.scoped(scope)?,
+        None => full,
+    };
     let avg_ratio = tokens::ratio(stats.total_tokens, stats.total_lines);
+    let grade_ratio = stats.grade_ratio(grade_by);
+    let (_, _, grade) = tokens::efficiency_grade(grade_ratio);
+    let distribution = percentiles
+        .then(|| tokens::distribution(&stats.files.iter().map(|f| f.tokens).collect::<Vec<_>>()));
 
-    println!("{}", "-".repeat(83));
-    println!(
-        "{:<60} {:>6} {:>8} {:>5.1}",
-        "Total", stats.total_lines, stats.total_tokens, avg_ratio
-    );
+    let breakdowns: Vec<Option<LineBreakdown>> = stats
+        .files
+        .iter()
+        .map(|f| if tokei { tokens::line_breakdown(&f.content).map(Some) } else { Ok(None) })
+        .collect::<Result<_>>()?;
+    let groups = group_by.map(|g| group_files(&stats, g));
+
+    if matches!(format, Format::Compact | Format::Csv) {
+        print_file_lines(&stats, &breakdowns, format);
+        return Ok(());
+    }
+
+    if format == Format::Json {
+        let output = AuditOutput {
+            files: stats
+                .files
+                .iter()
+                .zip(breakdowns)
+                .map(|(f, line_breakdown)| FileOutput {
+                    path: &f.path,
+                    lines: f.lines,
+                    tokens: f.tokens,
+                    ratio: (f.ratio * 100.0).round() / 100.0,
+                    line_breakdown,
+                })
+                .collect(),
+            total_lines: stats.total_lines,
+            total_tokens: stats.total_tokens,
+            ratio: (avg_ratio * 100.0).round() / 100.0,
+            grade,
+            effective_tokens: stats.effective_tokens,
+            code_lines: stats.code_lines,
+            comment_lines: stats.comment_lines,
+            blank_lines: stats.blank_lines,
+            distribution,
+            groups,
+            full_tokens,
+            generated_files_excluded: generated,
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    if tokei {
+        print_breakdown_table(&stats, &breakdowns, avg_ratio);
+    } else {
+        println!("{:<60} {:>6} {:>8} {:>6}", "File", "Lines", "Tokens", "T/L");
+        println!("{}", "-".repeat(83));
+
+        for f in &stats.files {
+            println!("{:<60} {:>6} {:>8} {:>5.1}", f.path, f.lines, f.tokens, f.ratio);
+        }
+
+        println!("{}", "-".repeat(83));
+        println!(
+            "{:<60} {:>6} {:>8} {:>5.1}",
+            "Total", stats.total_lines, stats.total_tokens, avg_ratio
+        );
+    }
+
+    if let (Some(scope), Some(full)) = (scope, full_tokens) {
+        let label = if scope == CodeScope::Tests { "Tests only" } else { "Production only" };
+        let pct = tokens::pct_delta(stats.total_tokens as isize, full);
+        println!("{label}: {} of {full} tokens ({pct:.1}%)", stats.total_tokens);
+    }
+
+    if let Some(generated) = generated.as_ref().filter(|g| !g.is_empty()) {
+        println!("Ignored {} generated file(s): {}", generated.len(), generated.join(", "));
+    }
+
+    if let Some(groups) = &groups {
+        print_groups(groups);
+    }
+
+    if let Some(d) = &distribution {
+        println!("Tokens/file: {}", d.summary());
+    }
 
     println!();
     println!(
@@ -27,7 +223,6 @@ pub fn run() -> Result<()> {
     );
 
     println!();
-    let (_, _, grade) = tokens::efficiency_grade(avg_ratio);
     let msg = match grade {
         "A+" => "Excellent — extremely token-efficient",
         "A" => "Great — lean and concise code",
@@ -35,8 +230,236 @@ pub fn run() -> Result<()> {
         "C" => "Fair — consider running `cargo syntax fix`",
         _ => "Verbose — run `cargo syntax fix` to reduce tokens",
     };
-    println!("Token efficiency: {grade} ({avg_ratio:.1} tokens/line)");
+    let label =
+        if grade_by == GradeBy::Effective { "effective tokens/line" } else { "tokens/line" };
+    println!("Token efficiency: {grade} ({grade_ratio:.1} {label})");
     println!("{msg}");
 
+    if top > 0 {
+        println!();
+        tokens::separator(83);
+        println!();
+        stats.files.sort_by(tokens::FileStats::cmp_by_tokens);
+        super::top::print_table(&stats, top, false);
+    }
+
     Ok(())
 }
+
+/// `compact` and `csv`: one line per file and nothing else, with the `--tokei` columns when present
+fn print_file_lines(
+    stats: &tokens::ProjectStats,
+    breakdowns: &[Option<LineBreakdown>],
+    format: Format,
+) {
+    if format == Format::Csv {
+        let mut header = vec!["path", "lines", "tokens", "ratio"];
+        if breakdowns.iter().any(Option::is_some) {
+            header.extend(["code_lines", "comment_lines", "blank_lines", "comment_overhead"]);
+        }
+        println!("{}", header.join(","));
+    }
+    for (f, b) in stats.files.iter().zip(breakdowns) {
+        let mut fields = vec![
+            f.path.clone(),
+            f.lines.to_string(),
+            f.tokens.to_string(),
+            format!("{:.2}", f.ratio),
+        ];
+        if let Some(b) = b {
+            fields.extend([
+                b.code.to_string(),
+                b.comments.to_string(),
+                b.blanks.to_string(),
+                format!("{:.2}", b.comment_overhead()),
+            ]);
+        }
+        if format == Format::Csv {
+            println!("{}", fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        } else {
+            println!("{}", fields.join(" "));
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a comma, quote or newline (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn print_breakdown_table(
+    stats: &tokens::ProjectStats,
+    breakdowns: &[Option<LineBreakdown>],
+    avg_ratio: f64,
+) {
+    println!(
+        "{:<50} {:>6} {:>8} {:>6} {:>6} {:>6} {:>6} {:>8}",
+        "File", "Lines", "Tokens", "T/L", "Code", "Cmts", "Blank", "Cmt/Code"
+    );
+    println!("{}", "-".repeat(104));
+
+    let mut heavy = Vec::new();
+    for (f, b) in stats.files.iter().zip(breakdowns).filter_map(|(f, b)| Some((f, b.as_ref()?))) {
+        let overhead = b.comment_overhead();
+        println!(
+            "{:<50} {:>6} {:>8} {:>5.1} {:>6} {:>6} {:>6} {:>8.2}",
+            f.path, f.lines, f.tokens, f.ratio, b.code, b.comments, b.blanks, overhead
+        );
+        if overhead > HIGH_COMMENT_OVERHEAD {
+            heavy.push(f.path.as_str());
+        }
+    }
+
+    println!("{}", "-".repeat(104));
+    println!(
+        "{:<50} {:>6} {:>8} {:>5.1} {:>6} {:>6} {:>6}",
+        "Total",
+        stats.total_lines,
+        stats.total_tokens,
+        avg_ratio,
+        stats.code_lines,
+        stats.comment_lines,
+        stats.blank_lines
+    );
+
+    if !heavy.is_empty() {
+        println!();
+        println!(
+            "High comment overhead (> {HIGH_COMMENT_OVERHEAD} comment tokens per code token):"
+        );
+        for path in heavy {
+            println!("  {path}");
+        }
+    }
+}
+
+/// Subtotals per group, heaviest group first; ties keep alphabetical order
+fn group_files(stats: &tokens::ProjectStats, group_by: GroupBy) -> Vec<Group<'_>> {
+    let mut by_key: HashMap<String, Vec<&tokens::FileStats>> = HashMap::new();
+    for f in &stats.files {
+        by_key.entry(group_by.key(&f.path)).or_default().push(f);
+    }
+
+    let mut groups: Vec<Group> = by_key
+        .into_iter()
+        .filter_map(|(name, files)| {
+            let top = files.iter().max_by_key(|f| f.tokens)?;
+            let lines = files.iter().map(|f| f.lines).sum();
+            let tokens = files.iter().map(|f| f.tokens).sum();
+            let top_author = match group_by {
+                GroupBy::Author => None,
+                _ => top_author(&files),
+            };
+            Some(Group {
+                files: files.len(),
+                lines,
+                tokens,
+                ratio: (tokens::ratio(tokens, lines) * 100.0).round() / 100.0,
+                top_file: &top.path,
+                top_file_tokens: top.tokens,
+                top_author,
+                name,
+            })
+        })
+        .collect();
+    groups.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+/// Primary author whose files add up to the most tokens; untracked files are not counted
+fn top_author(files: &[&tokens::FileStats]) -> Option<String> {
+    let mut by_author: HashMap<String, usize> = HashMap::new();
+    for f in files {
+        if let Some(author) = primary_author(&f.path) {
+            *by_author.entry(author).or_default() += f.tokens;
+        }
+    }
+    max_count(by_author)
+}
+
+fn print_groups(groups: &[Group]) {
+    println!();
+    println!(
+        "{:<30} {:>5} {:>6} {:>8} {:>6}  Top file / author",
+        "Group", "Files", "Lines", "Tokens", "T/L"
+    );
+    println!("{}", "-".repeat(100));
+    for g in groups {
+        println!(
+            "{:<30} {:>5} {:>6} {:>8} {:>5.1}  {} ({} tokens)",
+            g.name, g.files, g.lines, g.tokens, g.ratio, g.top_file, g.top_file_tokens
+        );
+        if let Some(author) = &g.top_author {
+            println!("{:<58}  {author}", "");
+        }
+    }
+}
+
+/// Top-level module a file belongs to; files directly in `src/` belong to `crate`
+fn module_of(path: &str) -> String {
+    let rel = path.strip_prefix("src/").unwrap_or(path);
+    match rel.split_once('/') {
+        Some((module, _)) => module.to_string(),
+        None if matches!(rel, "lib.rs" | "main.rs") => "crate".to_string(),
+        None => rel.trim_end_matches(".rs").to_string(),
+    }
+}
+
+/// Email that appears most often in the file's history, following renames
+fn primary_author(path: &str) -> Option<String> {
+    let output =
+        Command::new("git").args(["log", "--follow", "--format=%ae", "--", path]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for email in String::from_utf8_lossy(&output.stdout).lines().filter(|l| !l.is_empty()) {
+        *counts.entry(email.to_string()).or_default() += 1;
+    }
+    max_count(counts)
+}
+
+/// Key with the highest count; ties go to the alphabetically first key
+fn max_count(counts: HashMap<String, usize>) -> Option<String> {
+    counts.into_iter().max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0))).map(|(k, _)| k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_of() {
+        assert_eq!(module_of("src/commands/audit.rs"), "commands");
+        assert_eq!(module_of("src/commands.rs"), "commands");
+        assert_eq!(module_of("src/main.rs"), "crate");
+        assert_eq!(module_of("tests/test_tokens.rs"), "tests");
+        assert_eq!(module_of("build.rs"), "build");
+    }
+
+    #[test]
+    fn test_directory_key() {
+        assert_eq!(GroupBy::Directory.key("src/commands/audit.rs"), "src/commands");
+        assert_eq!(GroupBy::Directory.key("build.rs"), ".");
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("src/tokens.rs"), "src/tokens.rs");
+        assert_eq!(csv_field("src/a,b.rs"), "\"src/a,b.rs\"");
+        assert_eq!(csv_field("say \"hi\".rs"), "\"say \"\"hi\"\".rs\"");
+    }
+
+    #[test]
+    fn test_max_count_breaks_ties_alphabetically() {
+        let counts =
+            HashMap::from([("b@x".to_string(), 3), ("a@x".to_string(), 3), ("c@x".to_string(), 1)]);
+        assert_eq!(max_count(counts).as_deref(), Some("a@x"));
+        assert_eq!(max_count(HashMap::new()), None);
+    }
+}
@@ -1,28 +1,187 @@
-use anyhow::Result;
+use std::path::Path;
 
-use crate::tokens;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use regex::Regex;
+use serde_json::json;
 
-pub fn run() -> Result<()> {
-    let stats = tokens::scan_project()?;
-    let ratio = tokens::ratio(stats.total_tokens, stats.total_lines);
+use crate::tokens::{self, GradeBy};
 
+const LINK: &str = "https://github.com/syntaxai/cargo-syntax";
+
+/// Which snippet `badge` prints
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Markdown,
+    Html,
+    Rst,
+    /// Grade, ratio, color and URLs as JSON
+    Json,
+}
+
+/// Shields.io `style` parameter
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ShieldStyle {
+    Flat,
+    FlatSquare,
+    ForTheBadge,
+}
+
+impl ShieldStyle {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::FlatSquare => "flat-square",
+            Self::ForTheBadge => "for-the-badge",
+        }
+    }
+}
+
+/// Score `src/` only unless `all` is set, in which case tests/ and examples/ count too.
+/// With `readme`, the badge in that file is updated (or inserted) instead of printed.
+/// Without `format`, the Markdown, HTML and RST snippets are all printed.
+pub fn run(
+    all: bool,
+    readme: Option<&str>,
+    format: Option<Format>,
+    style: Option<ShieldStyle>,
+    grade_by: GradeBy,
+) -> Result<()> {
+    let stats =
+        if all { tokens::scan_project()? } else { tokens::scan_project_at(Path::new("src"))? };
+    let ratio = stats.grade_ratio(grade_by);
+
+    let badge_url = badge_url(ratio, style);
+
+    if let Some(path) = readme {
+        return update_readme(Path::new(path), &badge_url);
+    }
+
+    match format {
+        Some(Format::Json) => {
+            let (grade, color, _) = tokens::efficiency_grade(ratio);
+            let output = json!({
+                "grade": grade,
+                "ratio": (ratio * 10.0).round() / 10.0,
+                "color": color,
+                "badge_url": badge_url,
+                "shield_url": shield_url(ratio),
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+        Some(Format::Markdown) => println!("{}", markdown(&badge_url)),
+        Some(Format::Html) => println!("{}", html(&badge_url)),
+        Some(Format::Rst) => println!("{}", rst(&badge_url)),
+        None => {
+            println!("Markdown:\n{}\n", markdown(&badge_url));
+            println!("HTML:\n{}\n", html(&badge_url));
+            println!("reStructuredText:\n{}", rst(&badge_url));
+        }
+    }
+
+    Ok(())
+}
+
+fn markdown(badge_url: &str) -> String {
+    format!("[![Token Efficiency]({badge_url})]({LINK})")
+}
+
+fn html(badge_url: &str) -> String {
+    format!("<a href=\"{LINK}\"><img src=\"{badge_url}\" alt=\"Token Efficiency\"></a>")
+}
+
+fn rst(badge_url: &str) -> String {
+    format!(".. image:: {badge_url}\n   :target: {LINK}\n   :alt: Token Efficiency")
+}
+
+/// Shields.io URL showing the grade and T/L `ratio`, with `?style=` when one is given
+pub fn badge_url(ratio: f64, style: Option<ShieldStyle>) -> String {
+    let url = shield_url(ratio);
+    match style {
+        Some(style) => format!("{url}?style={}", style.as_str()),
+        None => url,
+    }
+}
+
+/// The unstyled Shields.io URL
+fn shield_url(ratio: f64) -> String {
     let (grade, color, _) = tokens::efficiency_grade(ratio);
+    format!("https://img.shields.io/badge/token_efficiency-{grade}%20({ratio:.1}%20T/L)-{color}")
+}
+
+fn update_readme(path: &Path, badge_url: &str) -> Result<()> {
+    let before = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let after = with_badge(&before, badge_url);
 
-    let badge_url = format!(
-        "https://img.shields.io/badge/token_efficiency-{grade}%20({ratio:.1}%20T/L)-{color}"
-    );
-    let link = "https://github.com/syntaxai/cargo-syntax";
-
-    println!("Markdown:");
-    println!("[![Token Efficiency]({badge_url})]({link})");
-    println!();
-    println!("HTML:");
-    println!("<a href=\"{link}\"><img src=\"{badge_url}\" alt=\"Token Efficiency\"></a>");
-    println!();
-    println!("reStructuredText:");
-    println!(".. image:: {badge_url}");
-    println!("   :target: {link}");
-    println!("   :alt: Token Efficiency");
+    if after == before {
+        println!("{} already has the current badge.", path.display());
+        return Ok(());
+    }
 
+    super::rewrite::print_diff(&before, &after);
+
+    std::fs::write(path, after).with_context(|| format!("failed to write {}", path.display()))?;
+    println!("Updated {}", path.display());
     Ok(())
 }
+
+/// Replace the `Token Efficiency` badge URL, keeping its link, or insert a badge below the
+/// title (or at the very top when the file doesn't open with a heading)
+fn with_badge(readme: &str, badge_url: &str) -> String {
+    // The shields.io URL itself contains parentheses, so stop at the first `)](`
+    let re = Regex::new(r"\[!\[Token Efficiency\]\(.*?\)\]\(([^)\s]*)\)").expect("valid regex");
+    if let Some(caps) = re.captures(readme) {
+        let badge = format!("[![Token Efficiency]({badge_url})]({})", &caps[1]);
+        return re.replacen(readme, 1, regex::NoExpand(&badge)).into_owned();
+    }
+
+    let badge = format!("[![Token Efficiency]({badge_url})]({LINK})");
+    match readme.split_once('\n') {
+        Some((title, rest)) if title.starts_with("# ") => format!("{title}\n\n{badge}\n{rest}"),
+        _ => format!("{badge}\n\n{readme}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://img.shields.io/badge/token_efficiency-A%20(6.2%20T/L)-green";
+
+    #[test]
+    fn test_with_badge_replaces_existing() {
+        let readme = "# Crate\n\n[![Token Efficiency](https://img.shields.io/badge/token_efficiency-B%20(7.4%20T/L)-blue)](https://example.com)\n\nText\n";
+        assert_eq!(
+            with_badge(readme, URL),
+            format!("# Crate\n\n[![Token Efficiency]({URL})](https://example.com)\n\nText\n")
+        );
+    }
+
+    #[test]
+    fn test_badge_url_style() {
+        assert_eq!(badge_url(6.2, None), URL);
+        assert_eq!(
+            badge_url(6.2, Some(ShieldStyle::ForTheBadge)),
+            format!("{URL}?style=for-the-badge")
+        );
+        let styled = format!("{URL}?style=flat-square");
+        let readme = format!("[![Token Efficiency]({URL})]({LINK})\n");
+        assert_eq!(
+            with_badge(&readme, &styled),
+            format!("[![Token Efficiency]({styled})]({LINK})\n")
+        );
+    }
+
+    #[test]
+    fn test_with_badge_inserts_below_title() {
+        assert_eq!(
+            with_badge("# Crate\n\nText\n", URL),
+            format!("# Crate\n\n[![Token Efficiency]({URL})]({LINK})\n\nText\n")
+        );
+        assert_eq!(
+            with_badge("Text\n", URL),
+            format!("[![Token Efficiency]({URL})]({LINK})\n\nText\n")
+        );
+    }
+}
@@ -1,16 +1,146 @@
 use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
+use clap::Args;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
+use super::rewrite::SizeLimits;
 use crate::tokens;
 
-pub fn run(n: usize, validate: bool, auto: bool, model: &str) -> Result<()> {
+/// When the last batch run without failures finished
+const LAST_RUN_FILE: &str = ".syntax_batch_last_run.json";
+
+/// Narrows the batch candidates before the top-N cut
+pub struct PathFilter {
+    pub include: Option<Regex>,
+    pub exclude: Option<Regex>,
+    /// Drop files not modified since the last successful batch run
+    pub skip_unchanged: bool,
+    /// Rewrite exactly these files, in this order, instead of the top N
+    pub only_files: Vec<String>,
+}
+
+/// How rewrites are accepted and what the run leaves behind
+#[derive(Args)]
+pub struct BatchOptions {
+    /// Run cargo check + cargo test after each rewrite, rollback on failure
+    #[arg(long)]
+    pub validate: bool,
+    /// Auto-accept rewrites without prompting
+    #[arg(long)]
+    pub auto: bool,
+    /// Write a Markdown summary of the run to this file
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+    /// List the files that will be rewritten and wait for Enter before calling the model
+    #[arg(long)]
+    pub preview_table: bool,
+    /// Skip rewrites that save less than this percentage of the file's tokens
+    #[arg(long, value_name = "N")]
+    pub min_savings_pct: Option<f64>,
+    /// Skip rewrites that save fewer than this many tokens
+    #[arg(long, value_name = "N")]
+    pub min_savings_tokens: Option<usize>,
+}
+
+impl BatchOptions {
+    /// The threshold a rewrite saving `saved` tokens (`pct`% of the file) falls short of, if any
+    fn shortfall(&self, saved: isize, pct: f64) -> Option<String> {
+        if let Some(min) = self.min_savings_tokens
+            && saved < min as isize
+        {
+            return Some(format!("--min-savings-tokens {min}"));
+        }
+        if let Some(min) = self.min_savings_pct
+            && pct < min
+        {
+            return Some(format!("--min-savings-pct {min}"));
+        }
+        None
+    }
+}
+
+/// Table of the files about to be rewritten, then wait for Enter; false if the user answers n/q
+fn confirm_preview(files: &[tokens::FileStats], total_tokens: usize) -> Result<bool> {
+    println!("{:<4} {:<50} {:>6} {:>8} {:>6}", "#", "File", "Lines", "Tokens", "T/L");
+    tokens::separator(78);
+    for (i, f) in files.iter().enumerate() {
+        println!("{:<4} {:<50} {:>6} {:>8} {:>5.1}", i + 1, f.path, f.lines, f.tokens, f.ratio);
+    }
+    tokens::separator(78);
+    let selected: usize = files.iter().map(|f| f.tokens).sum();
+    println!(
+        "{} file(s), {selected} tokens ({:.1}% of the project) will be sent to the model",
+        files.len(),
+        tokens::pct(selected, total_tokens)
+    );
+    println!();
+    let answer = tokens::ask_accept("Press Enter to start, Ctrl-C to abort")?;
+    Ok(!matches!(answer.as_str(), "n" | "N" | "q" | "Q"))
+}
+
+/// One file's line in the `--report` table
+struct ReportRow<'a> {
+    file: &'a tokens::FileStats,
+    tokens_after: Option<usize>,
+    status: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LastRun {
+    /// Seconds since the Unix epoch
+    finished_at: u64,
+}
+
+impl PathFilter {
+    fn matches(&self, path: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(path))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(path))
+    }
+}
+
+pub fn run(
+    n: usize,
+    opts: &BatchOptions,
+    model: &str,
+    fallback: Option<&str>,
+    filter: &PathFilter,
+    limits: SizeLimits,
+) -> Result<()> {
+    let BatchOptions { validate, auto, ref report, preview_table, .. } = *opts;
     let mut stats = tokens::scan_project()?;
-    stats.files.sort_by(|a, b| b.tokens.cmp(&a.tokens));
+    if filter.only_files.is_empty() {
+        stats.files.sort_by(tokens::FileStats::cmp_by_rank);
+        stats.files.retain(|f| filter.matches(&f.path));
+    } else {
+        stats.files = select_files(stats.files, &filter.only_files);
+    }
+    if stats.files.is_empty() {
+        println!("No files match the --file-filter/--exclude-filter/--only-files selection.");
+        return Ok(());
+    }
+    if filter.skip_unchanged
+        && let Some(last_run) = last_run()
+    {
+        let before = stats.files.len();
+        stats.files.retain(|f| modified_since(&f.path, last_run));
+        println!(
+            "Skipping {} file(s) unchanged since the last batch run",
+            before - stats.files.len()
+        );
+        if stats.files.is_empty() {
+            println!("Nothing to do.");
+            return Ok(());
+        }
+    }
 
-    let count = n.min(stats.files.len());
+    let count =
+        if filter.only_files.is_empty() { n.min(stats.files.len()) } else { stats.files.len() };
 
-    println!("Batch rewriting top {count} files via {model}...");
+    let which = if filter.only_files.is_empty() { "top" } else { "selected" };
+    println!("Batch rewriting {which} {count} files via {model}...");
     if validate {
         println!("  Validation: cargo check + cargo test after each rewrite");
     }
@@ -22,12 +152,25 @@ pub fn run(n: usize, validate: bool, auto: bool, model: &str) -> Result<()> {
     }
     println!();
 
+    if preview_table && !confirm_preview(&stats.files[..count], stats.total_tokens)? {
+        println!("Aborted — nothing was sent.");
+        return Ok(());
+    }
+
     let mut rewritten = 0;
     let mut skipped = 0;
     let mut failed = 0;
     let mut total_saved: isize = 0;
+    let mut rows = Vec::with_capacity(count);
 
-    for (i, f) in stats.files.iter().take(count).enumerate() {
+    // One cached system prompt for the whole batch instead of resending it per file
+    eprint!("Rewriting {count} files... ");
+    let paths: Vec<&str> = stats.files.iter().take(count).map(|f| f.path.as_str()).collect();
+    let results = super::rewrite::rewrite_files(&paths, model, fallback);
+    eprintln!("done");
+    println!();
+
+    for ((i, f), result) in stats.files.iter().take(count).enumerate().zip(results) {
         println!(
             "[{}/{}] {}  ({} tokens, {} lines, T/L: {:.1})",
             i + 1,
@@ -38,27 +181,54 @@ pub fn run(n: usize, validate: bool, auto: bool, model: &str) -> Result<()> {
             f.ratio
         );
 
-        eprint!("  rewriting... ");
-        let result = match super::rewrite::rewrite_file(&f.path, model) {
+        let result = match result {
             Ok(r) => {
-                eprintln!("done");
+                if r.model != model {
+                    println!("  (exceeds {model} limit — used {})", r.model);
+                }
                 r
             }
             Err(e) => {
-                eprintln!("failed");
                 println!("  Error: {e}");
                 failed += 1;
+                rows.push(ReportRow {
+                    file: f,
+                    tokens_after: None,
+                    status: format!("failed: {e}"),
+                });
                 println!();
                 continue;
             }
         };
+        let row = |status: &str| ReportRow {
+            file: f,
+            tokens_after: Some(result.tokens_after),
+            status: status.to_string(),
+        };
+
+        if let Some(reason) = limits.violation(&result) {
+            println!("  Rejected: {reason}. Skipping.");
+            skipped += 1;
+            rows.push(row(&format!("rejected: {reason}")));
+            println!();
+            continue;
+        }
 
         let saved = result.saved();
-        let pct = if saved > 0 { tokens::pct(saved as usize, result.tokens_before) } else { 0.0 };
+        let pct = tokens::pct_delta(saved, result.tokens_before);
 
         if saved <= 0 {
             println!("  No improvement ({saved:+} tokens). Skipping.");
             skipped += 1;
+            rows.push(row("no improvement"));
+            println!();
+            continue;
+        }
+
+        if let Some(threshold) = opts.shortfall(saved, pct) {
+            println!("  Saves only {saved} tokens ({pct:.1}%), below {threshold}. Skipping.");
+            skipped += 1;
+            rows.push(row("below threshold"));
             println!();
             continue;
         }
@@ -84,6 +254,7 @@ pub fn run(n: usize, validate: bool, auto: bool, model: &str) -> Result<()> {
                         eprintln!("passed ✓");
                         rewritten += 1;
                         total_saved += saved;
+                        rows.push(row("rewritten"));
                     }
                     Err(e) => {
                         eprintln!("failed ✗");
@@ -91,30 +262,144 @@ pub fn run(n: usize, validate: bool, auto: bool, model: &str) -> Result<()> {
                         println!("  Rolling back...");
                         std::fs::write(&f.path, &result.original)?;
                         failed += 1;
+                        rows.push(row("rolled back"));
                     }
                 }
             } else {
                 println!("  Applied.");
                 rewritten += 1;
                 total_saved += saved;
+                rows.push(row("rewritten"));
             }
         } else {
             println!("  Skipped.");
             skipped += 1;
+            rows.push(row("declined"));
         }
         println!();
     }
 
+    if failed == 0 {
+        record_last_run()?;
+    }
+
     tokens::separator(70);
     println!("Batch complete: {rewritten} rewritten, {skipped} skipped, {failed} failed");
     if total_saved > 0 {
-        let total_pct = tokens::pct(total_saved as usize, stats.total_tokens);
+        let total_pct = tokens::pct_delta(total_saved, stats.total_tokens);
         println!("Total saved: ~{total_saved} tokens ({total_pct:.1}% of project)");
     }
 
+    if let Some(path) = report {
+        let md = markdown_report(&rows, model, total_saved, stats.total_tokens)?;
+        std::fs::write(path, md).with_context(|| format!("failed to write {path}"))?;
+        println!("Wrote Markdown report to {path}");
+    }
+
     Ok(())
 }
 
+fn markdown_report(
+    rows: &[ReportRow],
+    model: &str,
+    total_saved: isize,
+    project_tokens: usize,
+) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut md = String::from("# Batch Rewrite Report\n\n");
+    md.push_str(&format!(
+        "Rewrote {} of {} files via `{model}` on {}.\n\n",
+        rows.iter().filter(|r| r.status == "rewritten").count(),
+        rows.len(),
+        iso_date(now)
+    ));
+
+    md.push_str(
+        "| File | Tokens before | Tokens after | Savings | Status | SHA before | SHA after |\n",
+    );
+    md.push_str(
+        "|------|--------------:|-------------:|--------:|--------|------------|-----------|\n",
+    );
+    for row in rows {
+        let f = row.file;
+        let (after, savings) = match row.tokens_after {
+            Some(after) => (after.to_string(), format!("{:+}", f.tokens as isize - after as isize)),
+            None => ("—".to_string(), "—".to_string()),
+        };
+        let current = std::fs::read_to_string(&f.path).unwrap_or_default();
+        let file = if row.status == "rewritten" {
+            format!("[`{0}`]({0})", f.path)
+        } else {
+            format!("`{}`", f.path)
+        };
+        md.push_str(&format!(
+            "| {file} | {} | {after} | {savings} | {} | `{}` | `{}` |\n",
+            f.tokens,
+            row.status.replace('|', "\\|"),
+            short_sha(&f.content)?,
+            short_sha(&current)?
+        ));
+    }
+
+    let total_pct = tokens::pct_delta(total_saved, project_tokens);
+    md.push_str(&format!(
+        "\n**Total saved: ~{total_saved} tokens ({total_pct:.1}% of project)**\n"
+    ));
+    Ok(md)
+}
+
+fn short_sha(content: &str) -> Result<String> {
+    let mut sha = tokens::git_hash_object(content)?;
+    sha.truncate(12);
+    Ok(sha)
+}
+
+/// `YYYY-MM-DD` (UTC) for seconds since the Unix epoch
+pub fn iso_date(secs: u64) -> String {
+    // Civil-from-days conversion in the proleptic Gregorian calendar
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Pick the scanned files named in `paths`, in the given order, warning about unknown ones
+fn select_files(mut files: Vec<tokens::FileStats>, paths: &[String]) -> Vec<tokens::FileStats> {
+    let mut selected = Vec::new();
+    for path in paths {
+        let path = path.trim_start_matches("./");
+        match files.iter().position(|f| f.path == path) {
+            Some(i) => selected.push(files.swap_remove(i)),
+            None if selected.iter().any(|f: &tokens::FileStats| f.path == path) => {}
+            None => eprintln!("Warning: {path} is not a scanned .rs file, skipping"),
+        }
+    }
+    selected
+}
+
+fn last_run() -> Option<SystemTime> {
+    let record: LastRun =
+        serde_json::from_str(&std::fs::read_to_string(LAST_RUN_FILE).ok()?).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(record.finished_at))
+}
+
+fn record_last_run() -> Result<()> {
+    let finished_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    std::fs::write(LAST_RUN_FILE, serde_json::to_string_pretty(&LastRun { finished_at })?)
+        .with_context(|| format!("failed to write {LAST_RUN_FILE}"))
+}
+
+/// Files whose mtime can't be read count as modified
+fn modified_since(path: &str, since: SystemTime) -> bool {
+    std::fs::metadata(path).and_then(|m| m.modified()).map_or(true, |t| t > since)
+}
+
 fn run_validation() -> Result<()> {
     let check = Command::new("cargo")
         .args(["check", "--quiet"])
@@ -138,3 +423,30 @@ fn run_validation() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso_date() {
+        assert_eq!(iso_date(0), "1970-01-01");
+        assert_eq!(iso_date(951_782_400), "2000-02-29");
+        assert_eq!(iso_date(1_792_108_800), "2026-10-16");
+    }
+
+    #[test]
+    fn test_shortfall() {
+        let opts = BatchOptions {
+            validate: false,
+            auto: true,
+            report: None,
+            preview_table: false,
+            min_savings_pct: Some(5.0),
+            min_savings_tokens: Some(10),
+        };
+        assert_eq!(opts.shortfall(8, 20.0).as_deref(), Some("--min-savings-tokens 10"));
+        assert_eq!(opts.shortfall(40, 2.5).as_deref(), Some("--min-savings-pct 5"));
+        assert_eq!(opts.shortfall(40, 5.0), None);
+    }
+}
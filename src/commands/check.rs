@@ -1,19 +1,145 @@
 use std::process::Command;
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 
-pub fn run() -> Result<()> {
-    println!("Running clippy...");
-    let clippy =
-        Command::new("cargo").args(["clippy", "--all-targets", "--", "-D", "warnings"]).status()?;
+/// The first two steps always run, the rest only with --strict
+const STEPS: &[(&str, &[&str])] = &[
+    ("clippy", &["clippy", "--all-targets", "--", "-D", "warnings"]),
+    ("fmt", &["fmt", "--check"]),
+    ("tests", &["test"]),
+    ("docs", &["doc", "--no-deps"]),
+];
 
-    println!("Running fmt check...");
-    let fmt = Command::new("cargo").args(["fmt", "--check"]).status()?;
+pub struct StepResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub summary: String,
+    /// Captured stdout + stderr; empty when the step streamed to the terminal
+    pub output: String,
+}
+
+/// Exit code in strict mode is a bit mask of failed steps: clippy=1, fmt=2, tests=4, docs=8.
+/// With `fix`, failing checks trigger `clippy --fix` and `fmt` and are then run once more.
+pub fn run(strict: bool, fix: bool) -> Result<()> {
+    let mut steps = run_steps(strict)?;
+
+    if fix && steps.iter().any(|s| !s.passed) {
+        println!("Checks failed — auto-fixing, then checking again...");
+        super::fix::run(false)?;
+        println!();
+        steps = run_steps(strict)?;
+    }
+
+    for s in steps.iter().filter(|s| !s.passed) {
+        eprint!("{}", s.output);
+    }
 
-    if !clippy.success() || !fmt.success() {
-        bail!("check failed — run `cargo syntax fix` to auto-fix");
+    if strict {
+        println!("{}", summary_line(&steps));
+        let code = exit_code(&steps);
+        if code != 0 {
+            std::process::exit(code);
+        }
+    } else if steps.iter().any(|s| !s.passed) {
+        if fix {
+            bail!("check still fails after auto-fixing — the rest needs manual fixes");
+        }
+        bail!("check failed — run `cargo syntax check --fix` to auto-fix");
     }
 
     println!("All checks passed.");
     Ok(())
 }
+
+/// Strict mode captures each step's output to count failed tests; otherwise it streams live
+pub fn run_steps(strict: bool) -> Result<Vec<StepResult>> {
+    let count = if strict { STEPS.len() } else { 2 };
+
+    STEPS
+        .iter()
+        .take(count)
+        .map(|&(name, args)| {
+            eprintln!("Running {name}...");
+            let mut cmd = Command::new("cargo");
+            cmd.args(args);
+            if name == "docs" {
+                // Broken intra-doc links are only warnings by default
+                cmd.env("RUSTDOCFLAGS", "-D warnings");
+            }
+            let context = || format!("failed to run cargo {name}");
+            let (passed, text) = if strict {
+                let output = cmd.output().with_context(context)?;
+                let text = format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                (output.status.success(), text)
+            } else {
+                (cmd.status().with_context(context)?.success(), String::new())
+            };
+            let summary = if passed {
+                "pass".to_string()
+            } else if name == "tests"
+                && let Some(n) = failed_test_count(&text)
+            {
+                format!("FAIL ({n} failed)")
+            } else {
+                "FAIL".to_string()
+            };
+
+            Ok(StepResult { name, passed, summary, output: text })
+        })
+        .collect()
+}
+
+fn summary_line(steps: &[StepResult]) -> String {
+    steps.iter().map(|s| format!("{}: {}", s.name, s.summary)).collect::<Vec<_>>().join(" | ")
+}
+
+fn exit_code(steps: &[StepResult]) -> i32 {
+    steps.iter().enumerate().filter(|(_, s)| !s.passed).map(|(i, _)| 1 << i).sum()
+}
+
+/// Sum the "N failed" counts across all `test result:` lines
+fn failed_test_count(output: &str) -> Option<usize> {
+    output
+        .lines()
+        .filter(|l| l.starts_with("test result:"))
+        .filter_map(|l| {
+            l.split(';').find_map(|part| part.trim().strip_suffix(" failed")?.parse::<usize>().ok())
+        })
+        .reduce(|a, b| a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(name: &'static str, passed: bool, summary: &str) -> StepResult {
+        StepResult { name, passed, summary: summary.to_string(), output: String::new() }
+    }
+
+    #[test]
+    fn test_failed_test_count() {
+        let output = "test result: FAILED. 5 passed; 2 failed; 0 ignored\n\
+                      test result: FAILED. 1 passed; 1 failed; 0 ignored\n";
+        assert_eq!(failed_test_count(output), Some(3));
+        assert_eq!(failed_test_count("error: could not compile"), None);
+    }
+
+    #[test]
+    fn test_exit_code_bits() {
+        let steps = [
+            step("clippy", true, "pass"),
+            step("fmt", false, "FAIL"),
+            step("tests", false, "FAIL (3 failed)"),
+            step("docs", true, "pass"),
+        ];
+        assert_eq!(exit_code(&steps), 2 | 4);
+        assert_eq!(
+            summary_line(&steps),
+            "clippy: pass | fmt: FAIL | tests: FAIL (3 failed) | docs: pass"
+        );
+    }
+}
@@ -1,8 +1,125 @@
-use anyhow::Result;
-use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use glob::Pattern;
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
 
 use crate::tokens;
 
+/// Project totals from the last passing `ci --set-baseline` run
+const BASELINE_FILE: &str = ".syntax_baseline.json";
+
+/// Budgets and output switches for `cargo syntax ci`
+#[derive(Args)]
+pub struct CiOptions {
+    /// Fail if total tokens exceed this limit
+    #[arg(long)]
+    pub max_tokens: Option<usize>,
+    /// Fail if T/L ratio exceeds this value
+    #[arg(long)]
+    pub max_tl: Option<f64>,
+    /// Fail if efficiency grade is below this (A+, A, B, C, D)
+    #[arg(long)]
+    pub min_grade: Option<String>,
+    /// JSON file mapping file globs to token limits, e.g. {"src/commands/*": 300}
+    #[arg(long)]
+    pub token_budget_file: Option<String>,
+    /// Output style: table or json
+    #[arg(long, value_enum, default_value = "table")]
+    pub format: Format,
+    /// Deprecated: use --format json
+    #[arg(long, hide = true, conflicts_with = "format")]
+    pub json: bool,
+    /// Emit GitHub Actions annotations on Cargo.toml to stderr (auto-enabled when GITHUB_ACTIONS=true)
+    #[arg(long)]
+    pub output_github: bool,
+    /// Also run the full `check --strict` suite (clippy, fmt, tests, docs)
+    #[arg(long)]
+    pub strict: bool,
+    /// Print the Shields.io badge URL (and set the `badge_url` output in GitHub Actions)
+    #[arg(long)]
+    pub emit_badge: bool,
+    /// After a passing run, store the totals in .syntax_baseline.json
+    #[arg(long)]
+    pub set_baseline: bool,
+    /// Fail if tokens grew by more than --regression-tolerance since the baseline
+    #[arg(long)]
+    pub check_regression: bool,
+    /// Allowed token growth over the baseline, in percent
+    #[arg(long, default_value = "5", requires = "check_regression")]
+    pub regression_tolerance: f64,
+    /// Token count the grade (and --min-grade, --emit-badge) is computed from
+    #[arg(long, value_enum, default_value = "total")]
+    pub grade_by: tokens::GradeBy,
+    /// Re-run the checks whenever a .rs file or Cargo.toml changes, until Ctrl-C
+    #[arg(
+        long,
+        conflicts_with_all = ["format", "json", "output_github", "emit_badge", "set_baseline"]
+    )]
+    pub watch: bool,
+}
+
+/// Output style for `ci --format`, named like `audit --format`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human-readable summary
+    Table,
+    /// One JSON object with the totals, grade and failures
+    Json,
+}
+
+/// Per-file token limits from `--token-budget-file`, keyed by glob
+type Budgets = Vec<(Pattern, usize)>;
+
+/// A failed or nearly failed check; `file` is set for per-file budgets
+struct Finding {
+    file: Option<String>,
+    message: String,
+}
+
+impl Finding {
+    fn project(message: String) -> Self {
+        Self { file: None, message }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Baseline {
+    total_tokens: usize,
+    ratio: f64,
+    grade: String,
+}
+
+/// Change since the baseline, as reported by `--check-regression`
+#[derive(Serialize)]
+struct BaselineDelta {
+    baseline_tokens: usize,
+    token_delta: isize,
+    token_pct: f64,
+}
+
+impl BaselineDelta {
+    fn new(baseline: &Baseline, total_tokens: usize) -> Self {
+        let token_delta = total_tokens as isize - baseline.total_tokens as isize;
+        Self {
+            baseline_tokens: baseline.total_tokens,
+            token_delta,
+            token_pct: tokens::pct_delta(token_delta, baseline.total_tokens),
+        }
+    }
+
+    fn regressed(&self, tolerance: f64) -> bool {
+        self.token_pct > tolerance
+    }
+}
+
 #[derive(Serialize)]
 struct CiOutput {
     files: usize,
@@ -12,52 +129,220 @@ struct CiOutput {
     grade: String,
     pass: bool,
     failures: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    badge_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<BaselineDelta>,
+}
+
+/// Everything one pass of the checks found, before any of it is printed
+struct Report {
+    stats: tokens::ProjectStats,
+    avg_ratio: f64,
+    grade: &'static str,
+    failures: Vec<Finding>,
+    near_misses: Vec<Finding>,
+    delta: Option<BaselineDelta>,
+    /// Output of the failed `--strict` steps
+    step_output: String,
+}
+
+pub fn run(opts: &CiOptions) -> Result<()> {
+    if opts.watch {
+        return watch(opts);
+    }
+    let CiOptions { format, output_github, emit_badge, set_baseline, .. } = *opts;
+    let Report { stats, avg_ratio, grade, failures, near_misses, delta, step_output } =
+        evaluate(opts)?;
+    eprint!("{step_output}");
+
+    let grade_ratio = stats.grade_ratio(opts.grade_by);
+    let badge_url = emit_badge.then(|| super::badge::badge_url(grade_ratio, None));
+
+    if format == Format::Json {
+        print_json(&stats, avg_ratio, grade, &failures, badge_url.clone(), delta);
+    } else {
+        if let Some(d) = &delta {
+            println!(
+                "Baseline: {} → {} tokens ({:+}, {:+.1}%)",
+                d.baseline_tokens, stats.total_tokens, d.token_delta, d.token_pct
+            );
+        }
+        print_human(&stats, avg_ratio, grade, &failures);
+        if let Some(url) = &badge_url {
+            println!("Badge: {url}");
+        }
+    }
+
+    if output_github || std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true") {
+        print_github(&stats, avg_ratio, grade, &failures, &near_misses);
+        if let Some(url) = &badge_url {
+            set_github_output("badge_url", url)?;
+        }
+    }
+
+    if !failures.is_empty() {
+        std::process::exit(1);
+    }
+
+    if set_baseline {
+        let baseline = Baseline {
+            total_tokens: stats.total_tokens,
+            ratio: (avg_ratio * 100.0).round() / 100.0,
+            grade: grade.to_string(),
+        };
+        std::fs::write(BASELINE_FILE, serde_json::to_string_pretty(&baseline)? + "\n")
+            .with_context(|| format!("failed to write {BASELINE_FILE}"))?;
+        eprintln!("Baseline updated: {BASELINE_FILE}");
+    }
+    Ok(())
+}
+
+/// Re-run the checks on every change to a `.rs` file or Cargo.toml, logging a timestamped
+/// pass/fail line each time under a status line that always shows the latest result
+fn watch(opts: &CiOptions) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+    // The status line is a single line, so a resize needs no redraw
+    let never_resized = AtomicBool::new(false);
+
+    println!("Watching .rs files and Cargo.toml (Ctrl-C to stop)\n");
+    loop {
+        let report = evaluate(opts)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        // Replace the old status line with the log line, then draw the new status below it
+        print!("\r\x1b[2K{}\n{}", log_line(now, &report.failures), status_line(&report));
+        io::stdout().flush()?;
+
+        super::top::wait_for_change(&rx, &never_resized, is_ci_input)?;
+        print!("\r\x1b[2K");
+    }
+}
+
+fn is_ci_input(path: &Path) -> bool {
+    super::top::is_rs(path) || path.file_name().is_some_and(|n| n == "Cargo.toml")
+}
+
+/// `[HH:MM:SS] PASS`, or `FAIL` with the first failure, for the UTC time `secs`
+fn log_line(secs: u64, failures: &[Finding]) -> String {
+    let (h, m, s) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let time = format!("[{h:02}:{m:02}:{s:02}]");
+    match failures {
+        [] => format!("{time} PASS"),
+        [only] => format!("{time} FAIL  {}", only.message),
+        [first, rest @ ..] => format!("{time} FAIL  {} (+{} more)", first.message, rest.len()),
+    }
 }
 
-pub fn run(
-    max_tokens: Option<usize>,
-    max_tl: Option<f64>,
-    min_grade: Option<&str>,
-    json: bool,
-) -> Result<()> {
+fn status_line(report: &Report) -> String {
+    let state = if report.failures.is_empty() { "✓ passing" } else { "✗ failing" };
+    format!(
+        "{state} · grade {} · {} tokens · T/L {:.1}",
+        report.grade, report.stats.total_tokens, report.avg_ratio
+    )
+}
+
+fn evaluate(opts: &CiOptions) -> Result<Report> {
+    let CiOptions { max_tokens, max_tl, strict, check_regression, regression_tolerance, .. } =
+        *opts;
+    let min_grade = opts.min_grade.as_deref();
+    let budgets = opts.token_budget_file.as_deref().map(load_budgets).transpose()?;
     let stats = tokens::scan_project()?;
     let avg_ratio = tokens::ratio(stats.total_tokens, stats.total_lines);
 
-    let (_, _, grade) = tokens::efficiency_grade(avg_ratio);
-    let mut failures: Vec<String> = Vec::new();
+    let (_, _, grade) = tokens::efficiency_grade(stats.grade_ratio(opts.grade_by));
+    let mut failures: Vec<Finding> = Vec::new();
 
     if let Some(max) = max_tokens
         && stats.total_tokens > max
     {
-        failures.push(format!("token budget exceeded: {} > {max} (max)", stats.total_tokens));
+        failures.push(Finding::project(format!(
+            "token budget exceeded: {} > {max} (max)",
+            stats.total_tokens
+        )));
     }
 
     if let Some(max) = max_tl
         && avg_ratio > max
     {
-        failures.push(format!("T/L ratio too high: {avg_ratio:.1} > {max:.1} (max)"));
+        failures
+            .push(Finding::project(format!("T/L ratio too high: {avg_ratio:.1} > {max:.1} (max)")));
     }
 
     if let Some(min) = min_grade
         && grade_rank(grade) < grade_rank(min)
     {
-        failures.push(format!("grade too low: {grade} < {min} (minimum)"));
+        failures.push(Finding::project(format!("grade too low: {grade} < {min} (minimum)")));
     }
 
-    if json {
-        print_json(&stats, avg_ratio, grade, &failures);
-    } else {
-        print_human(&stats, avg_ratio, grade, &failures);
+    let baseline = if check_regression { load_baseline()? } else { None };
+    let delta = baseline.as_ref().map(|b| BaselineDelta::new(b, stats.total_tokens));
+    if let Some(d) = &delta
+        && d.regressed(regression_tolerance)
+    {
+        failures.push(Finding::project(format!(
+            "token regression: {} → {} ({:+.1}% > {regression_tolerance}% tolerance)",
+            d.baseline_tokens, stats.total_tokens, d.token_pct
+        )));
     }
 
-    if failures.is_empty() {
-        Ok(())
-    } else {
-        std::process::exit(1);
+    let mut near_misses: Vec<Finding> = Vec::new();
+
+    for f in &stats.files {
+        let Some((glob, max)) = budgets.as_ref().and_then(|b| file_budget(b, &f.path)) else {
+            continue;
+        };
+        let file = Some(f.path.clone());
+        if f.tokens > max {
+            let message = format!("{}: {} > {max} tokens (budget \"{glob}\")", f.path, f.tokens);
+            failures.push(Finding { file, message });
+        } else if near_miss(f.tokens as f64, max as f64) {
+            let message = format!("{}: {} of {max} tokens (budget \"{glob}\")", f.path, f.tokens);
+            near_misses.push(Finding { file, message });
+        }
+    }
+
+    let mut step_output = String::new();
+    if strict {
+        for step in super::check::run_steps(true)?.iter().filter(|s| !s.passed) {
+            step_output += &step.output;
+            failures.push(Finding::project(format!("{}: {}", step.name, step.summary)));
+        }
+    }
+
+    if let Some(max) = max_tokens
+        && near_miss(stats.total_tokens as f64, max as f64)
+    {
+        near_misses.push(Finding::project(format!(
+            "token budget nearly reached: {} of {max}",
+            stats.total_tokens
+        )));
+    }
+
+    if let Some(max) = max_tl
+        && near_miss(avg_ratio, max)
+    {
+        near_misses.push(Finding::project(format!(
+            "T/L ratio nearly at limit: {avg_ratio:.1} of {max:.1}"
+        )));
     }
+
+    Ok(Report { stats, avg_ratio, grade, failures, near_misses, delta, step_output })
+}
+
+/// `None` (with a warning) when no baseline has been stored yet
+fn load_baseline() -> Result<Option<Baseline>> {
+    let Ok(content) = std::fs::read_to_string(BASELINE_FILE) else {
+        eprintln!("No {BASELINE_FILE} yet — run `cargo syntax ci --set-baseline` to create it");
+        return Ok(None);
+    };
+    let baseline = serde_json::from_str(&content)
+        .with_context(|| format!("invalid baseline file {BASELINE_FILE}"))?;
+    Ok(Some(baseline))
 }
 
-fn grade_rank(grade: &str) -> u8 {
+pub fn grade_rank(grade: &str) -> u8 {
     match grade {
         "A+" => 5,
         "A" => 4,
@@ -68,7 +353,44 @@ fn grade_rank(grade: &str) -> u8 {
     }
 }
 
-fn print_json(stats: &tokens::ProjectStats, avg_ratio: f64, grade: &str, failures: &[String]) {
+/// Read `{"<glob>": <max tokens>, ...}`
+fn load_budgets(path: &str) -> Result<Budgets> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+    let map: BTreeMap<String, usize> =
+        serde_json::from_str(&content).with_context(|| format!("invalid budget file {path}"))?;
+    map.into_iter()
+        .map(|(glob, max)| {
+            Ok((
+                Pattern::new(&glob).with_context(|| format!("invalid glob in {path}: {glob}"))?,
+                max,
+            ))
+        })
+        .collect()
+}
+
+/// The strictest budget whose glob matches `path`
+fn file_budget<'a>(budgets: &'a Budgets, path: &str) -> Option<(&'a str, usize)> {
+    budgets
+        .iter()
+        .filter(|(glob, _)| glob.matches(path))
+        .min_by_key(|(_, max)| *max)
+        .map(|(glob, max)| (glob.as_str(), *max))
+}
+
+/// Passing, but within 10% of the limit
+fn near_miss(value: f64, max: f64) -> bool {
+    value <= max && value >= max * 0.9
+}
+
+fn print_json(
+    stats: &tokens::ProjectStats,
+    avg_ratio: f64,
+    grade: &str,
+    failures: &[Finding],
+    badge_url: Option<String>,
+    baseline: Option<BaselineDelta>,
+) {
     let output = CiOutput {
         files: stats.files.len(),
         total_tokens: stats.total_tokens,
@@ -76,12 +398,14 @@ fn print_json(stats: &tokens::ProjectStats, avg_ratio: f64, grade: &str, failure
         ratio: (avg_ratio * 100.0).round() / 100.0,
         grade: grade.to_string(),
         pass: failures.is_empty(),
-        failures: failures.to_vec(),
+        failures: failures.iter().map(|f| f.message.clone()).collect(),
+        badge_url,
+        baseline,
     };
     println!("{}", serde_json::to_string_pretty(&output).unwrap_or_default());
 }
 
-fn print_human(stats: &tokens::ProjectStats, avg_ratio: f64, grade: &str, failures: &[String]) {
+fn print_human(stats: &tokens::ProjectStats, avg_ratio: f64, grade: &str, failures: &[Finding]) {
     println!(
         "cargo syntax ci: {} files, {} tokens, {:.1} T/L, grade {grade}",
         stats.files.len(),
@@ -94,13 +418,51 @@ fn print_human(stats: &tokens::ProjectStats, avg_ratio: f64, grade: &str, failur
     } else {
         println!();
         for f in failures {
-            println!("  FAIL: {f}");
+            println!("  FAIL: {}", f.message);
         }
         println!();
         println!("FAILED ({} check(s))", failures.len());
     }
 }
 
+/// Emit GitHub Actions workflow commands so results show up as PR check annotations.
+/// They go to stderr so `--format json` output stays parseable. Per-file budgets annotate that
+/// file; project-wide checks are attached to `Cargo.toml`.
+fn print_github(
+    stats: &tokens::ProjectStats,
+    avg_ratio: f64,
+    grade: &str,
+    failures: &[Finding],
+    near_misses: &[Finding],
+) {
+    let file = |f: &Finding| f.file.clone().unwrap_or_else(|| "Cargo.toml".to_string());
+    for f in failures {
+        eprintln!("::error file={},line=1,title=TokenBudget::{}", file(f), f.message);
+    }
+    for w in near_misses {
+        eprintln!("::warning file={},line=1,title=TokenBudget::{}", file(w), w.message);
+    }
+    if failures.is_empty() {
+        eprintln!(
+            "::notice title=TokenBudget::{} tokens, {avg_ratio:.1} T/L, grade {grade}",
+            stats.total_tokens
+        );
+    }
+}
+
+/// Append `name=value` to `$GITHUB_OUTPUT`, or use the legacy `::set-output` command
+/// on runners that don't provide the file
+fn set_github_output(name: &str, value: &str) -> Result<()> {
+    match std::env::var("GITHUB_OUTPUT") {
+        Ok(path) => {
+            let mut file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+            writeln!(file, "{name}={value}")?;
+        }
+        Err(_) => eprintln!("::set-output name={name}::{value}"),
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +483,43 @@ mod tests {
             assert!(grade_rank(w[0]) > grade_rank(w[1]), "{} should rank above {}", w[0], w[1]);
         }
     }
+
+    #[test]
+    fn test_file_budget_strictest_match() {
+        let budgets: Budgets = [("src/main.rs", 500), ("src/*", 800), ("src/commands/*", 300)]
+            .map(|(g, max)| (Pattern::new(g).unwrap(), max))
+            .into();
+        assert_eq!(file_budget(&budgets, "src/main.rs"), Some(("src/main.rs", 500)));
+        assert_eq!(file_budget(&budgets, "src/commands/ci.rs"), Some(("src/commands/*", 300)));
+        assert_eq!(file_budget(&budgets, "tests/a.rs"), None);
+    }
+
+    #[test]
+    fn test_baseline_regression() {
+        let baseline = Baseline { total_tokens: 1000, ratio: 8.0, grade: "B".to_string() };
+        let grown = BaselineDelta::new(&baseline, 1060);
+        assert_eq!(grown.token_delta, 60);
+        assert!(grown.regressed(5.0));
+        assert!(!grown.regressed(6.0), "growth equal to the tolerance passes");
+        assert!(!BaselineDelta::new(&baseline, 900).regressed(0.0));
+    }
+
+    #[test]
+    fn test_near_miss() {
+        for (value, max, expected) in
+            [(95.0, 100.0, true), (90.0, 100.0, true), (100.0, 100.0, true), (89.0, 100.0, false)]
+        {
+            assert_eq!(near_miss(value, max), expected, "near_miss({value}, {max})");
+        }
+        assert!(!near_miss(101.0, 100.0), "exceeding the budget is a failure, not a near miss");
+    }
+
+    #[test]
+    fn test_watch_log_line() {
+        let secs = 1_792_108_800 + 13 * 3600 + 5 * 60 + 9;
+        assert_eq!(log_line(secs, &[]), "[13:05:09] PASS");
+        let failures = [Finding::project("grade too low".into()), Finding::project("x".into())];
+        assert_eq!(log_line(secs, &failures), "[13:05:09] FAIL  grade too low (+1 more)");
+        assert!(is_ci_input(Path::new("./Cargo.toml")) && !is_ci_input(Path::new("Cargo.lock")));
+    }
 }
@@ -1,40 +1,36 @@
+use std::collections::BTreeMap;
 use std::process::Command;
 
 use anyhow::{Result, bail};
+use clap::ValueEnum;
 
 use crate::tokens;
 
-struct BranchStats {
-    name: String,
-    files: usize,
-    tokens: usize,
-    lines: usize,
+/// What `compare` judges the two branches by
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Metric {
+    /// Absolute token counts
+    Tokens,
+    /// Tokens per line
+    Ratio,
+    /// Efficiency grade (A+ to D)
+    Grade,
 }
 
-pub fn run(branch: &str) -> Result<()> {
+/// Per-file token counts on each side; `None` where the file doesn't exist
+type FileDelta = (String, Option<usize>, Option<usize>);
+
+/// `metric` picks the verdict under the summary table. With `diff_files`, a per-file table
+/// follows, hiding files that changed by less than `min_delta`
+pub fn run(branch: &str, metric: Metric, diff_files: bool, min_delta: usize) -> Result<()> {
     let current = current_branch()?;
 
     println!("Comparing token efficiency: {current} vs {branch}\n");
 
-    let current_stats = {
-        let stats = tokens::scan_project()?;
-        BranchStats {
-            name: current,
-            files: stats.files.len(),
-            tokens: stats.total_tokens,
-            lines: stats.total_lines,
-        }
-    };
-
-    let target_stats = {
-        let rev = tokens::count_rev_tokens(branch)?;
-        BranchStats {
-            name: branch.to_string(),
-            files: rev.files,
-            tokens: rev.tokens,
-            lines: rev.lines,
-        }
-    };
+    let scan = tokens::scan_project()?;
+    let current_stats = tokens::RevStats::of_project(&scan);
+    let target_stats = tokens::count_rev_tokens(branch)?;
+    let change = tokens::diff_rev_stats(target_stats, current_stats);
 
     let cur_ratio = tokens::ratio(current_stats.tokens, current_stats.lines);
     let tgt_ratio = tokens::ratio(target_stats.tokens, target_stats.lines);
@@ -42,11 +38,11 @@ pub fn run(branch: &str) -> Result<()> {
     let (_, _, cur_grade) = tokens::efficiency_grade(cur_ratio);
     let (_, _, tgt_grade) = tokens::efficiency_grade(tgt_ratio);
 
-    println!("{:<20} {:>10} {:>10} {:>10}", "", &current_stats.name, &target_stats.name, "Delta");
+    println!("{:<20} {:>10} {:>10} {:>10}", "", &current, branch, "Delta");
     tokens::separator(52);
-    print_row("Files", current_stats.files, target_stats.files);
-    print_row("Lines", current_stats.lines, target_stats.lines);
-    print_row("Tokens", current_stats.tokens, target_stats.tokens);
+    print_row("Files", current_stats.files, target_stats.files, change.file_delta);
+    print_row("Lines", current_stats.lines, target_stats.lines, change.line_delta);
+    print_row("Tokens", current_stats.tokens, target_stats.tokens, change.token_delta);
     println!(
         "{:<20} {:>10.1} {:>10.1} {:>+10.1}",
         "T/L ratio",
@@ -56,10 +52,118 @@ pub fn run(branch: &str) -> Result<()> {
     );
     println!("{:<20} {:>10} {:>10}", "Grade", cur_grade, tgt_grade);
 
-    let token_delta = current_stats.tokens as isize - target_stats.tokens as isize;
-    let ratio_delta = cur_ratio - tgt_ratio;
-
     println!();
+    match metric {
+        Metric::Tokens => {
+            print_token_verdict(&change, cur_ratio - tgt_ratio);
+            let (added, removed) = tokens::git_diff_token_count(branch)?;
+            println!("Changed lines: +{added}/-{removed} tokens");
+        }
+        Metric::Ratio => {
+            let change = verdict(tgt_ratio - cur_ratio);
+            let pct = percent_change(cur_ratio, tgt_ratio);
+            println!(
+                "T/L ratio: {tgt_ratio:.2} → {cur_ratio:.2} ({:+.2}, {pct:+.1}%) — efficiency {change}",
+                cur_ratio - tgt_ratio
+            );
+        }
+        Metric::Grade => {
+            let steps = i16::from(super::ci::grade_rank(cur_grade))
+                - i16::from(super::ci::grade_rank(tgt_grade));
+            let change = verdict(f64::from(steps));
+            println!("Grade: {tgt_grade} → {cur_grade} ({steps:+} grade(s)) — efficiency {change}");
+        }
+    }
+
+    if diff_files {
+        print_file_deltas(&scan, branch, &current, min_delta)?;
+    }
+
+    Ok(())
+}
+
+/// Leaderboard of the working tree and every remote branch, most efficient (lowest T/L) first.
+/// `top` limits it to the N most and N least efficient branches, plus the current one.
+pub fn run_all_branches(top: Option<usize>) -> Result<()> {
+    let current = current_branch()?;
+    let remotes = remote_branches()?;
+    if remotes.is_empty() {
+        bail!("No remote branches found — run `git fetch` first?");
+    }
+
+    println!("Comparing token efficiency: {current} vs {} remote branch(es)\n", remotes.len());
+
+    let mut rows = vec![(current, tokens::RevStats::of_project(&tokens::scan_project()?), true)];
+    for branch in remotes {
+        let stats = tokens::count_rev_tokens(&branch)?;
+        rows.push((branch, stats, false));
+    }
+    let ratio = |s: &tokens::RevStats| tokens::ratio(s.tokens, s.lines);
+    rows.sort_by(|a, b| ratio(&a.1).total_cmp(&ratio(&b.1)).then_with(|| a.0.cmp(&b.0)));
+
+    let current_idx = rows.iter().position(|(.., current)| *current).unwrap_or(0);
+    println!(
+        "  {:<4} {:<40} {:>6} {:>8} {:>9} {:>6} {:>6}",
+        "#", "Branch", "Files", "Lines", "Tokens", "T/L", "Grade"
+    );
+    tokens::separator(86);
+    let mut last = None;
+    for i in leaderboard_rows(rows.len(), top, current_idx) {
+        if last.is_some_and(|l| i > l + 1) {
+            println!("  ...");
+        }
+        last = Some(i);
+
+        let (name, stats, current) = &rows[i];
+        let (_, _, grade) = tokens::efficiency_grade(ratio(stats));
+        let name = if *current { format!("{name} (current)") } else { name.clone() };
+        let note = if i == 0 { "  ← most efficient" } else { "" };
+        println!(
+            "{} {:<4} {name:<40} {:>6} {:>8} {:>9} {:>6.1} {grade:>6}{note}",
+            if *current { ">" } else { " " },
+            i + 1,
+            stats.files,
+            stats.lines,
+            stats.tokens,
+            ratio(stats)
+        );
+    }
+    tokens::separator(86);
+    println!("> marks the current branch, measured on the working tree");
+
+    Ok(())
+}
+
+/// Indices of the rows to show: everything, or the `top` first and last ones plus `current`
+fn leaderboard_rows(len: usize, top: Option<usize>, current: usize) -> Vec<usize> {
+    match top {
+        Some(n) if 2 * n < len => {
+            (0..len).filter(|&i| i < n || i >= len - n || i == current).collect()
+        }
+        _ => (0..len).collect(),
+    }
+}
+
+/// Remote-tracking branches such as `origin/main`, without symbolic refs like `origin/HEAD`
+fn remote_branches() -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["branch", "-r", "--format=%(refname:short) %(symref)"])
+        .output()?;
+    if !output.status.success() {
+        bail!("git branch -r failed — are you in a git repository?");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| match l.trim().split_once(' ') {
+            Some((name, "")) => Some(name.to_string()),
+            Some(_) => None,
+            None => Some(l.trim().to_string()),
+        })
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
+fn print_token_verdict(change: &tokens::RevStatsDelta, ratio_delta: f64) {
     if ratio_delta < -0.1 {
         println!("Current branch is more token-efficient (lower T/L ratio)");
     } else if ratio_delta > 0.1 {
@@ -68,19 +172,88 @@ pub fn run(branch: &str) -> Result<()> {
         println!("Both branches have similar token efficiency (T/L ratio within 0.1)");
     }
 
+    let token_delta = change.token_delta;
     if token_delta != 0 {
         let sign = if token_delta > 0 { "+" } else { "" };
+        println!("Token delta: {sign}{token_delta} ({sign}{:.1}%)", change.token_pct_change());
+    }
+}
+
+/// `gain` is positive when the current branch is more efficient
+fn verdict(gain: f64) -> &'static str {
+    if gain > 0.0 {
+        "improved"
+    } else if gain < 0.0 {
+        "regressed"
+    } else {
+        "unchanged"
+    }
+}
+
+fn percent_change(cur: f64, tgt: f64) -> f64 {
+    if tgt > 0.0 { (cur - tgt) / tgt * 100.0 } else { 0.0 }
+}
+
+fn print_file_deltas(
+    scan: &tokens::ProjectStats,
+    branch: &str,
+    current: &str,
+    min_delta: usize,
+) -> Result<()> {
+    let mut files: BTreeMap<String, (Option<usize>, Option<usize>)> = BTreeMap::new();
+    for f in &scan.files {
+        files.entry(f.path.clone()).or_default().0 = Some(f.tokens);
+    }
+    for path in tokens::git_list_rs_files(branch)? {
+        let content = tokens::git_show_file(branch, &path)?;
+        files.entry(path).or_default().1 = Some(tokens::count_tokens(&content)?);
+    }
+
+    let mut rows: Vec<FileDelta> = files
+        .into_iter()
+        .map(|(path, (cur, tgt))| (path, cur, tgt))
+        .filter(|(_, cur, tgt)| delta(*cur, *tgt).unsigned_abs() >= min_delta)
+        .collect();
+    rows.sort_by_key(|(path, cur, tgt)| {
+        (std::cmp::Reverse(delta(*cur, *tgt).unsigned_abs()), path.clone())
+    });
+
+    println!();
+    println!("{:<50} {:>10} {:>10} {:>8}", "File", current, branch, "Delta");
+    tokens::separator(82);
+    for (path, cur, tgt) in &rows {
+        let show = |n: &Option<usize>| n.map_or_else(|| "-".to_string(), |n| n.to_string());
         println!(
-            "Token delta: {sign}{token_delta} ({sign}{:.1}%)",
-            tokens::pct_delta(token_delta, target_stats.tokens)
+            "{path:<50} {:>10} {:>10} {:>+8}  {}",
+            show(cur),
+            show(tgt),
+            delta(*cur, *tgt),
+            marker(*cur, *tgt)
         );
     }
+    if rows.is_empty() {
+        println!("No files changed by {min_delta} or more tokens.");
+    }
 
     Ok(())
 }
 
-fn print_row(label: &str, cur: usize, tgt: usize) {
-    let delta = cur as isize - tgt as isize;
+fn delta(cur: Option<usize>, tgt: Option<usize>) -> isize {
+    cur.unwrap_or(0) as isize - tgt.unwrap_or(0) as isize
+}
+
+/// `+` new on the current branch, `-` deleted, `↑`/`↓`/`=` changed in size
+fn marker(cur: Option<usize>, tgt: Option<usize>) -> &'static str {
+    match (cur, tgt) {
+        (Some(_), None) => "+",
+        (None, Some(_)) => "-",
+        (Some(c), Some(t)) if c > t => "↑",
+        (Some(c), Some(t)) if c < t => "↓",
+        _ => "=",
+    }
+}
+
+fn print_row(label: &str, cur: usize, tgt: usize, delta: isize) {
     println!("{label:<20} {cur:>10} {tgt:>10} {delta:>+10}");
 }
 
@@ -91,3 +264,39 @@ fn current_branch() -> Result<String> {
     }
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdict_and_percent_change() {
+        assert_eq!(verdict(0.5), "improved");
+        assert_eq!(verdict(-1.0), "regressed");
+        assert_eq!(verdict(0.0), "unchanged");
+        assert!((percent_change(8.8, 8.0) - 10.0).abs() < 1e-9);
+        assert_eq!(percent_change(8.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_leaderboard_rows() {
+        assert_eq!(leaderboard_rows(4, None, 2), [0, 1, 2, 3]);
+        assert_eq!(leaderboard_rows(4, Some(2), 3), [0, 1, 2, 3]);
+        assert_eq!(leaderboard_rows(7, Some(2), 3), [0, 1, 3, 5, 6]);
+        assert_eq!(leaderboard_rows(7, Some(1), 6), [0, 6]);
+    }
+
+    #[test]
+    fn test_marker_and_delta() {
+        for (cur, tgt, expected, d) in [
+            (Some(10), None, "+", 10),
+            (None, Some(10), "-", -10),
+            (Some(12), Some(10), "↑", 2),
+            (Some(8), Some(10), "↓", -2),
+            (Some(10), Some(10), "=", 0),
+        ] {
+            assert_eq!(marker(cur, tgt), expected);
+            assert_eq!(delta(cur, tgt), d);
+        }
+    }
+}
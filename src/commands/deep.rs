@@ -1,8 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use serde_json::{Value, json};
 
 use crate::tokens::{self, ProjectStats};
 
-const WINDOW_SIZE: usize = 3;
+/// Tuning for duplicate detection
+#[derive(Args, Clone, Copy)]
+pub struct DeepOptions {
+    /// Consecutive non-blank lines that make up a duplicate block (2-20)
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u16).range(2..=20))]
+    pub window: u16,
+    /// Hide duplicates that would save fewer than N tokens
+    #[arg(long, default_value = "0")]
+    pub min_savings: usize,
+}
 
 struct Fingerprint {
     file_idx: usize,
@@ -28,12 +43,99 @@ pub struct DeepResult {
     pub total_savings: usize,
 }
 
-pub fn run(stats: &ProjectStats) -> DeepResult {
+/// A function and the file it lives in (index into that project's `ProjectStats::files`)
+pub struct FnRef {
+    pub file_idx: usize,
+    pub name: String,
+    pub line: usize,
+}
+
+/// Near-duplicate pair where one function lives in this project and one in another
+pub struct CrossDuplicate {
+    pub local: FnRef,
+    pub external: FnRef,
+    pub similarity: f64,
+    pub savings: usize,
+}
+
+/// With `json`, print the `results_json` form of the analysis instead of the report.
+/// With `output_dot`, also write the `results_dot` graph to that file.
+pub fn run(
+    cross_project: Option<&str>,
+    opts: DeepOptions,
+    json: bool,
+    output_dot: Option<&str>,
+) -> Result<()> {
+    let stats = tokens::scan_project()?;
+
+    let Some(other) = cross_project else {
+        let result = analyze(&stats, opts);
+        if json {
+            println!("{}", serde_json::to_string_pretty(&results_json(&result, &stats))?);
+        } else if result.total_savings > 0 {
+            print_results(&result, &stats);
+        } else {
+            println!("Deep analysis: no cross-file duplicates found.");
+        }
+        if let Some(path) = output_dot {
+            std::fs::write(path, results_dot(&result, &stats))
+                .with_context(|| format!("failed to write {path}"))?;
+            let note =
+                format!("Wrote duplication graph to {path} (render with `dot -Tsvg {path}`)");
+            if json { eprintln!("{note}") } else { println!("\n{note}") }
+        }
+        return Ok(());
+    };
+
+    let root = Path::new(other);
+    if !root.is_dir() {
+        bail!("Not a directory: {other}");
+    }
+    let other_stats = tokens::scan_project_at(root)?;
+
+    println!(
+        "Comparing {} local files against {} files in {other}...\n",
+        stats.files.len(),
+        other_stats.files.len()
+    );
+
+    let dupes = find_cross_project_duplicates(&stats, &other_stats);
+    if dupes.is_empty() {
+        println!("No functions shared with {other}.");
+        return Ok(());
+    }
+
+    println!("Cross-project near-duplicates:\n");
+    for (i, d) in dupes.iter().enumerate() {
+        let here = &stats.files[d.local.file_idx].path;
+        let there = &other_stats.files[d.external.file_idx].path;
+        println!(
+            "  {}. {} ≈ {} ({:.0}% similar)",
+            i + 1,
+            d.local.name,
+            d.external.name,
+            d.similarity * 100.0
+        );
+        println!("     Here:  {here}:{}", d.local.line + 1);
+        println!("     There: {other}/{there}:{}", d.external.line + 1);
+        println!("     Saves: ~{} tokens\n", d.savings);
+    }
+
+    let total: usize = dupes.iter().map(|d| d.savings).sum();
+    tokens::separator(70);
+    println!("{} function(s) could move to a shared crate, ~{total} tokens saveable", dupes.len());
+
+    Ok(())
+}
+
+pub fn analyze(stats: &ProjectStats, opts: DeepOptions) -> DeepResult {
     let normalized: Vec<Vec<String>> =
         stats.files.iter().map(|f| f.content.lines().map(normalize_line).collect()).collect();
 
-    let clusters = find_duplicate_blocks(&normalized, stats);
-    let near_dupes = find_near_duplicates(stats);
+    let mut clusters = find_duplicate_blocks(&normalized, stats, usize::from(opts.window));
+    clusters.retain(|c| estimate_savings(c) >= opts.min_savings);
+    let mut near_dupes = find_near_duplicates(stats);
+    near_dupes.retain(|n| n.savings >= opts.min_savings);
 
     let total_savings: usize = clusters.iter().map(estimate_savings).sum::<usize>()
         + near_dupes.iter().map(|n| n.savings).sum::<usize>();
@@ -94,6 +196,90 @@ pub fn print_results(result: &DeepResult, stats: &ProjectStats) {
     );
 }
 
+/// `result` with file indices resolved to paths and 1-based line numbers
+pub fn results_json(result: &DeepResult, stats: &ProjectStats) -> Value {
+    let path = |fi: usize| stats.files[fi].path.as_str();
+    let clusters: Vec<Value> = result
+        .clusters
+        .iter()
+        .map(|c| {
+            let occurrences: Vec<Value> = c
+                .occurrences
+                .iter()
+                .map(|&(fi, start, end)| {
+                    json!({ "file": path(fi), "start_line": start + 1, "end_line": end + 1 })
+                })
+                .collect();
+            json!({
+                "occurrences": occurrences,
+                "preview": c.preview,
+                "tokens_per_instance": c.tokens_per_instance,
+                "estimated_savings": estimate_savings(c),
+            })
+        })
+        .collect();
+    let near_dupes: Vec<Value> = result
+        .near_dupes
+        .iter()
+        .map(|nd| {
+            json!({
+                "file": path(nd.file_idx),
+                "fn_a": { "name": nd.fn_a.0, "line": nd.fn_a.1 + 1 },
+                "fn_b": { "name": nd.fn_b.0, "line": nd.fn_b.1 + 1 },
+                "savings": nd.savings,
+            })
+        })
+        .collect();
+    json!({
+        "clusters": clusters,
+        "near_dupes": near_dupes,
+        "total_savings": result.total_savings,
+    })
+}
+
+/// Graphviz DOT graph of the analysis: one node per file, one edge per pair of files sharing
+/// duplicate blocks (solid) and a self-loop per file with near-duplicate functions (dashed).
+/// Edges are labeled with the summed estimated savings.
+pub fn results_dot(result: &DeepResult, stats: &ProjectStats) -> String {
+    let mut blocks: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for c in &result.clusters {
+        let mut files: Vec<usize> = c.occurrences.iter().map(|&(fi, ..)| fi).collect();
+        files.sort_unstable();
+        files.dedup();
+        let pairs: Vec<(usize, usize)> = match files[..] {
+            [only] => vec![(only, only)],
+            _ => files
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &a)| files[i + 1..].iter().map(move |&b| (a, b)))
+                .collect(),
+        };
+        for pair in pairs {
+            *blocks.entry(pair).or_default() += estimate_savings(c);
+        }
+    }
+    let mut near: BTreeMap<usize, usize> = BTreeMap::new();
+    for nd in &result.near_dupes {
+        *near.entry(nd.file_idx).or_default() += nd.savings;
+    }
+
+    let node = |fi: usize| format!("{:?}", stats.files[fi].path);
+    let mut dot =
+        String::from("graph duplication {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for fi in 0..stats.files.len() {
+        let _ = writeln!(dot, "    {};", node(fi));
+    }
+    for ((a, b), savings) in blocks {
+        let _ = writeln!(dot, "    {} -- {} [label=\"~{savings} tokens\"];", node(a), node(b));
+    }
+    for (fi, savings) in near {
+        let (n, label) = (node(fi), format!("~{savings} tokens"));
+        let _ = writeln!(dot, "    {n} -- {n} [label=\"{label}\", style=dashed];");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
 fn normalize_line(line: &str) -> String {
     line.split_whitespace().collect::<Vec<_>>().join(" ")
 }
@@ -101,8 +287,9 @@ fn normalize_line(line: &str) -> String {
 fn find_duplicate_blocks(
     normalized: &[Vec<String>],
     stats: &ProjectStats,
+    window_size: usize,
 ) -> Vec<DuplicateCluster> {
-    // Build fingerprints: hash of WINDOW_SIZE consecutive non-blank normalized lines
+    // Build fingerprints: hash of window_size consecutive non-blank normalized lines
     let mut map: HashMap<u64, Vec<Fingerprint>> = HashMap::new();
 
     for (file_idx, lines) in normalized.iter().enumerate() {
@@ -113,11 +300,11 @@ fn find_duplicate_blocks(
             .map(|(i, l)| (i, l.as_str()))
             .collect();
 
-        if non_blank.len() < WINDOW_SIZE {
+        if non_blank.len() < window_size {
             continue;
         }
 
-        for window in non_blank.windows(WINDOW_SIZE) {
+        for window in non_blank.windows(window_size) {
             let combined: String = window.iter().map(|(_, l)| *l).collect::<Vec<_>>().join("\n");
             // Skip trivial windows (single braces, use statements, etc.)
             if combined.len() < 20 {
@@ -144,24 +331,29 @@ fn find_duplicate_blocks(
         }
 
         // Verify actual text matches (not just hash collision)
-        let first_text = get_window_text(normalized, fps[0].file_idx, fps[0].start_line);
-        let all_match = fps
-            .iter()
-            .all(|fp| get_window_text(normalized, fp.file_idx, fp.start_line) == first_text);
+        let first_text =
+            get_window_text(normalized, fps[0].file_idx, fps[0].start_line, window_size);
+        let all_match = fps.iter().all(|fp| {
+            get_window_text(normalized, fp.file_idx, fp.start_line, window_size) == first_text
+        });
 
         if !all_match {
             continue;
         }
 
         // Get original (non-normalized) preview from first occurrence
-        let preview = get_original_window(&stats.files[fps[0].file_idx].content, fps[0].start_line);
+        let preview = get_original_window(
+            &stats.files[fps[0].file_idx].content,
+            fps[0].start_line,
+            window_size,
+        );
 
         let tokens_per_instance = tokens::count_tokens(&preview).unwrap_or(0);
 
         let occurrences: Vec<(usize, usize, usize)> = fps
             .iter()
             .map(|fp| {
-                let end = find_window_end(normalized, fp.file_idx, fp.start_line);
+                let end = find_window_end(normalized, fp.file_idx, fp.start_line, window_size);
                 (fp.file_idx, fp.start_line, end)
             })
             .collect();
@@ -201,19 +393,11 @@ fn find_near_duplicates(stats: &ProjectStats) -> Vec<NearDuplicate> {
 
         for i in 0..fns.len() {
             for j in (i + 1)..fns.len() {
-                let norm_a = normalize_line(&fns[i].body);
-                let norm_b = normalize_line(&fns[j].body);
-
-                // Skip very short functions
-                if norm_a.len() < 30 || norm_b.len() < 30 {
+                let Some(similarity) = fn_similarity(&fns[i].body, &fns[j].body) else {
                     continue;
-                }
-
-                let similarity = string_similarity(&norm_a, &norm_b);
+                };
                 if similarity > 0.75 && similarity < 1.0 {
-                    let tokens_a = tokens::count_tokens(&fns[i].body).unwrap_or(0);
-                    let tokens_b = tokens::count_tokens(&fns[j].body).unwrap_or(0);
-                    let savings = tokens_a.min(tokens_b).saturating_mul(60) / 100;
+                    let savings = fn_savings(&fns[i].body, &fns[j].body);
 
                     if savings >= 5 {
                         results.push(NearDuplicate {
@@ -232,15 +416,78 @@ fn find_near_duplicates(stats: &ProjectStats) -> Vec<NearDuplicate> {
     results
 }
 
-struct FnInfo {
-    name: String,
-    line: usize,
-    body: String,
+/// Compare every function in `local` against every function in `other`.
+/// Identical functions count here, since they are the first candidates for a shared crate.
+pub fn find_cross_project_duplicates(
+    local: &ProjectStats,
+    other: &ProjectStats,
+) -> Vec<CrossDuplicate> {
+    let collect = |stats: &ProjectStats| -> Vec<(usize, FnInfo)> {
+        stats
+            .files
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, f)| extract_functions(&f.content).into_iter().map(move |f| (idx, f)))
+            .collect()
+    };
+    let local_fns = collect(local);
+    let other_fns = collect(other);
+
+    let mut results = Vec::new();
+    for (li, lf) in &local_fns {
+        for (oi, of) in &other_fns {
+            let Some(similarity) = fn_similarity(&lf.body, &of.body) else { continue };
+            if similarity <= 0.75 {
+                continue;
+            }
+            let savings = fn_savings(&lf.body, &of.body);
+            if savings >= 5 {
+                results.push(CrossDuplicate {
+                    local: FnRef { file_idx: *li, name: lf.name.clone(), line: lf.line },
+                    external: FnRef { file_idx: *oi, name: of.name.clone(), line: of.line },
+                    similarity,
+                    savings,
+                });
+            }
+        }
+    }
+
+    results.sort_by_key(|d| std::cmp::Reverse(d.savings));
+    results
+}
+
+/// Word similarity of two function bodies, or None if either is too short to compare
+fn fn_similarity(a: &str, b: &str) -> Option<f64> {
+    let norm_a = normalize_line(a);
+    let norm_b = normalize_line(b);
+
+    // Skip very short functions
+    if norm_a.len() < 30 || norm_b.len() < 30 {
+        return None;
+    }
+    Some(string_similarity(&norm_a, &norm_b))
+}
+
+fn fn_savings(a: &str, b: &str) -> usize {
+    let tokens_a = tokens::count_tokens(a).unwrap_or(0);
+    let tokens_b = tokens::count_tokens(b).unwrap_or(0);
+    tokens_a.min(tokens_b).saturating_mul(60) / 100
+}
+
+pub struct FnInfo {
+    pub name: String,
+    pub line: usize,
+    pub body: String,
 }
 
-fn extract_functions(content: &str) -> Vec<FnInfo> {
+/// Find every `fn` item in `content` with its 1-based line and brace-matched body
+pub fn extract_functions(content: &str) -> Vec<FnInfo> {
     let mut fns = Vec::new();
     let lines: Vec<&str> = content.lines().collect();
+    let offsets: Vec<usize> = content
+        .split_inclusive('\n')
+        .scan(0, |offset, line| Some(std::mem::replace(offset, *offset + line.len())))
+        .collect();
 
     let mut i = 0;
     while i < lines.len() {
@@ -261,37 +508,18 @@ fn extract_functions(content: &str) -> Vec<FnInfo> {
                     .map_or(trimmed.len(), |p| p + name_start);
                 let name = trimmed[name_start..name_end].to_string();
 
-                if !name.is_empty() {
-                    // Find the opening brace
-                    let mut brace_line = i;
-                    while brace_line < lines.len() && !lines[brace_line].contains('{') {
-                        brace_line += 1;
-                    }
-
-                    if brace_line < lines.len() {
-                        // Count braces to find end
-                        let mut depth = 0;
-                        let mut end = brace_line;
-                        for (li, line) in lines.iter().enumerate().skip(brace_line) {
-                            for ch in line.chars() {
-                                if ch == '{' {
-                                    depth += 1;
-                                }
-                                if ch == '}' {
-                                    depth -= 1;
-                                }
-                            }
-                            if depth == 0 {
-                                end = li;
-                                break;
-                            }
-                        }
-
-                        let body: String = lines[i..=end].join("\n");
-                        fns.push(FnInfo { name, line: i, body });
-                        i = end + 1;
-                        continue;
-                    }
+                // Find the end with the item lexer, so braces in strings, chars and comments
+                // don't count; skip declarations without a body and bodies that never close
+                let rest = &content[offsets[i]..];
+                if !name.is_empty()
+                    && let Some(len) = tokens::item_end(rest)
+                    && rest[..len].trim_end().ends_with('}')
+                {
+                    let end = i + rest[..len].trim_end_matches('\n').lines().count() - 1;
+                    let body: String = lines[i..=end].join("\n");
+                    fns.push(FnInfo { name, line: i, body });
+                    i = end + 1;
+                    continue;
                 }
             }
         }
@@ -308,18 +536,23 @@ fn hash_str(s: &str) -> u64 {
     hasher.finish()
 }
 
-fn get_window_text(normalized: &[Vec<String>], file_idx: usize, start: usize) -> String {
+fn get_window_text(
+    normalized: &[Vec<String>],
+    file_idx: usize,
+    start: usize,
+    window_size: usize,
+) -> String {
     let lines = &normalized[file_idx];
     let non_blank: Vec<&str> = lines[start..]
         .iter()
         .filter(|l| !l.is_empty())
-        .take(WINDOW_SIZE)
+        .take(window_size)
         .map(String::as_str)
         .collect();
     non_blank.join("\n")
 }
 
-fn get_original_window(content: &str, start_line: usize) -> String {
+fn get_original_window(content: &str, start_line: usize, window_size: usize) -> String {
     let lines: Vec<&str> = content.lines().collect();
     let mut collected = 0;
     let mut end = start_line;
@@ -329,7 +562,7 @@ fn get_original_window(content: &str, start_line: usize) -> String {
             collected += 1;
         }
         end = i;
-        if collected >= WINDOW_SIZE {
+        if collected >= window_size {
             break;
         }
     }
@@ -337,7 +570,12 @@ fn get_original_window(content: &str, start_line: usize) -> String {
     lines[start_line..=end.min(lines.len() - 1)].join("\n")
 }
 
-fn find_window_end(normalized: &[Vec<String>], file_idx: usize, start: usize) -> usize {
+fn find_window_end(
+    normalized: &[Vec<String>],
+    file_idx: usize,
+    start: usize,
+    window_size: usize,
+) -> usize {
     let lines = &normalized[file_idx];
     let mut collected = 0;
     let mut end = start;
@@ -347,7 +585,7 @@ fn find_window_end(normalized: &[Vec<String>], file_idx: usize, start: usize) ->
             collected += 1;
         }
         end = i;
-        if collected >= WINDOW_SIZE {
+        if collected >= window_size {
             break;
         }
     }
@@ -355,7 +593,7 @@ fn find_window_end(normalized: &[Vec<String>], file_idx: usize, start: usize) ->
     end
 }
 
-fn estimate_savings(cluster: &DuplicateCluster) -> usize {
+pub fn estimate_savings(cluster: &DuplicateCluster) -> usize {
     let instances = cluster.occurrences.len();
     if instances <= 1 {
         return 0;
@@ -392,6 +630,44 @@ mod tests {
         assert_eq!(normalize_line("  "), "");
     }
 
+    #[test]
+    fn test_results_json_resolves_paths() {
+        let stats = tokens::scan_sources([
+            ("src/a.rs".to_string(), "fn a() {}\n".to_string()),
+            ("src/b.rs".to_string(), "fn b() {}\n".to_string()),
+        ])
+        .unwrap();
+        let result = DeepResult {
+            clusters: vec![DuplicateCluster {
+                occurrences: vec![(0, 0, 2), (1, 4, 6)],
+                preview: "let x = 1;".to_string(),
+                tokens_per_instance: 50,
+            }],
+            near_dupes: vec![NearDuplicate {
+                file_idx: 1,
+                fn_a: ("parse".to_string(), 9),
+                fn_b: ("parse_all".to_string(), 19),
+                savings: 30,
+            }],
+            total_savings: 70,
+        };
+        let json = results_json(&result, &stats);
+        let cluster = &json["clusters"][0];
+        assert_eq!(cluster["occurrences"][1]["file"], "src/b.rs");
+        assert_eq!(cluster["occurrences"][1]["start_line"], 5);
+        assert_eq!(cluster["estimated_savings"], 40);
+        assert_eq!(json["near_dupes"][0]["file"], "src/b.rs");
+        assert_eq!(json["near_dupes"][0]["fn_b"]["line"], 20);
+        assert_eq!(json["total_savings"], 70);
+
+        let dot = results_dot(&result, &stats);
+        assert!(dot.starts_with("graph duplication {"));
+        assert!(dot.contains("    \"src/a.rs\" -- \"src/b.rs\" [label=\"~40 tokens\"];"));
+        assert!(
+            dot.contains("    \"src/b.rs\" -- \"src/b.rs\" [label=\"~30 tokens\", style=dashed];")
+        );
+    }
+
     #[test]
     fn test_hash_str_consistency() {
         let h1 = hash_str("hello world");
@@ -430,12 +706,37 @@ mod tests {
         assert_eq!(fns[1].name, "bar");
     }
 
+    #[test]
+    fn test_extract_functions_skips_braces_in_literals() {
+        let code = "fn open() -> char {\n    '{'\n}\n\nfn close() -> &'static str {\n    \"}}\" // }\n}\n\nfn last() {}\n";
+        let fns = extract_functions(code);
+        let names: Vec<&str> = fns.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["open", "close", "last"]);
+        assert_eq!(fns[0].body, "fn open() -> char {\n    '{'\n}");
+        assert_eq!(fns[1].body.lines().count(), 3);
+        assert!(extract_functions("fn open() {\n    let x = 1;\n").is_empty());
+    }
+
     #[test]
     fn test_extract_functions_empty() {
         let fns = extract_functions("// no functions\nlet x = 1;\n");
         assert!(fns.is_empty());
     }
 
+    #[test]
+    fn test_fn_similarity_skips_short_bodies() {
+        assert_eq!(fn_similarity("fn a() {}", "fn b() {}"), None);
+        let body = "fn total(xs: &[u32]) -> u32 { xs.iter().sum() }";
+        assert_eq!(fn_similarity(body, body), Some(1.0));
+    }
+
+    #[test]
+    fn test_find_window_end_uses_window_size() {
+        let normalized = [["a", "", "b", "c", "d"].map(String::from).to_vec()];
+        assert_eq!(find_window_end(&normalized, 0, 0, 2), 2);
+        assert_eq!(find_window_end(&normalized, 0, 0, 4), 4);
+    }
+
     #[test]
     fn test_estimate_savings_single() {
         let c = DuplicateCluster {
@@ -2,7 +2,9 @@ use crate::{openrouter, tokens};
 use anyhow::{Context, Result, bail};
 use serde::Deserialize;
 use serde_json::json;
-use std::process::Command;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
 
 const DIFF_PROMPT: &str = "You are a Rust code auditor focused on token efficiency. You are reviewing ONLY the changed/new code in a file. Analyze the full file content but focus your suggestions on the recently changed parts. List 1-5 concrete improvements to make the changes more token-efficient. Only suggest changes that are clearly beneficial — if the code is already efficient, return an empty list.";
 
@@ -12,7 +14,7 @@ struct DiffResult {
     verdict: String,
 }
 
-fn diff_schema() -> serde_json::Value {
+pub(crate) fn diff_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -27,7 +29,18 @@ fn diff_schema() -> serde_json::Value {
     })
 }
 
-pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<()> {
+/// `summary` prints only the aggregate line; `fail_on_suggestions` exits 1 if any were made
+pub fn run(
+    range: Option<&str>,
+    staged: bool,
+    fix: bool,
+    model: &str,
+    files: Option<&str>,
+    summary: bool,
+    fail_on_suggestions: bool,
+) -> Result<()> {
+    let pattern = files_pattern(files)?;
+
     let diff_args = build_diff_args(range, staged);
     let diff_output = run_git_diff(&diff_args)?;
 
@@ -36,18 +49,29 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
         return Ok(());
     }
 
-    let changed_files = parse_changed_rs_files(&diff_output);
+    let mut changed_files = parse_changed_rs_files(&diff_output);
 
     if changed_files.is_empty() {
         println!("No .rs file changes found.");
         return Ok(());
     }
 
+    if let Some(pattern) = &pattern {
+        changed_files.retain(|f| pattern.matches(f));
+        if changed_files.is_empty() {
+            println!("No changed .rs files match {}.", pattern.as_str());
+            return Ok(());
+        }
+    }
+
     let label = if staged { "staged" } else { range.unwrap_or("unstaged") };
-    println!("Analyzing {label} changes via {model}...\n");
+    if !summary {
+        println!("Analyzing {label} changes via {model}...\n");
+    }
 
     let mut total_files = 0;
     let mut total_added_tokens = 0;
+    let mut total_removed_tokens = 0;
     let mut total_suggestions = 0;
     let mut total_saveable = 0;
     let mut efficient_files = 0;
@@ -60,21 +84,25 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
         let ratio = tokens::ratio(file_tokens, lines);
 
         let file_diff = extract_file_diff(&diff_output, file);
-        let added_lines = file_diff.lines().filter(|l| l.starts_with('+')).count();
-        let added_tokens_est = added_lines * 8;
+        let added_lines =
+            file_diff.lines().filter(|l| l.starts_with('+') && !l.starts_with("+++ ")).count();
+        let (added_tokens, removed_tokens) = tokens::diff_token_count(file_diff)?;
 
         total_files += 1;
-        total_added_tokens += added_tokens_est;
+        total_added_tokens += added_tokens;
+        total_removed_tokens += removed_tokens;
 
         let is_new = file_diff.contains("new file mode");
         let status = if is_new { "new file" } else { "modified" };
-        println!(
-            "{file}  ({status}, +{added_lines} lines, ~+{added_tokens_est} tokens, T/L: {ratio:.1})"
-        );
+        if !summary {
+            println!(
+                "{file}  ({status}, +{added_lines} lines, +{added_tokens}/-{removed_tokens} tokens, T/L: {ratio:.1})"
+            );
+            eprint!("  reviewing... ");
+        }
 
         let prompt =
             format!("GIT DIFF for this file:\n{file_diff}\n\nFULL FILE CONTENT:\n{content}");
-        eprint!("  reviewing... ");
 
         match openrouter::chat_json::<DiffResult>(
             model,
@@ -83,6 +111,11 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
             "diff_result",
             diff_schema(),
         ) {
+            Ok(result) if summary => {
+                if !(result.suggestions.is_empty() || result.verdict == "efficient") {
+                    total_suggestions += result.suggestions.len();
+                }
+            }
             Ok(result) => {
                 eprintln!("done");
                 if result.suggestions.is_empty() || result.verdict == "efficient" {
@@ -100,16 +133,29 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
                     files_to_fix.push(file.clone());
                 }
             }
+            Err(e) if summary => eprintln!("{file}: review failed: {e}"),
             Err(e) => {
                 eprintln!("failed");
                 println!("  (review failed: {e})");
             }
         }
-        println!();
+        if !summary {
+            println!();
+        }
+    }
+
+    if summary {
+        println!(
+            "{total_files} file(s) changed, ~{total_added_tokens} tokens added, {total_suggestions} suggestion(s)"
+        );
+        exit_on_suggestions(fail_on_suggestions, total_suggestions);
+        return Ok(());
     }
 
     tokens::separator(70);
-    println!("Summary: {total_files} file(s) changed, ~+{total_added_tokens} tokens added");
+    println!(
+        "Summary: {total_files} file(s) changed, +{total_added_tokens}/-{total_removed_tokens} tokens"
+    );
 
     if efficient_files == total_files {
         println!("All changes look token-efficient. ✓");
@@ -123,7 +169,15 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
     if fix && !files_to_fix.is_empty() {
         println!("\nRewriting {} file(s) with suggestions...\n", files_to_fix.len());
         for file in &files_to_fix {
-            super::rewrite::run(file, model)?;
+            super::rewrite::run(
+                file,
+                model,
+                None,
+                false,
+                super::rewrite::SizeLimits::default(),
+                &super::rewrite::Constraints::default(),
+                super::rewrite::RewriteMode::default(),
+            )?;
             println!();
         }
     } else if !fix && !files_to_fix.is_empty() {
@@ -132,9 +186,122 @@ pub fn run(range: Option<&str>, staged: bool, fix: bool, model: &str) -> Result<
         );
     }
 
+    exit_on_suggestions(fail_on_suggestions, total_suggestions);
+    Ok(())
+}
+
+/// Net tokens per changed `.rs` file (deleted files included) without calling the AI;
+/// exits 1 if the total grew
+pub fn token_delta(range: Option<&str>, staged: bool, files: Option<&str>) -> Result<()> {
+    let pattern = files_pattern(files)?;
+    let diff_output = run_git_diff(&build_diff_args(range, staged))?;
+
+    let changed = changed_paths(&diff_output, pattern.as_ref());
+    if changed.is_empty() {
+        println!("No .rs file changes found.");
+        return Ok(());
+    }
+
+    let mut net = 0;
+    for file in &changed {
+        let (added, removed) = tokens::diff_token_count(extract_file_diff(&diff_output, file))?;
+        let delta = added as isize - removed as isize;
+        net += delta;
+        println!("{delta:>+8} tokens  {file}");
+    }
+    tokens::separator(70);
+    println!("Net: {net:+} tokens across {} file(s)", changed.len());
+
+    if net > 0 {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
+/// The git diff with a `## TOKEN: +N -M` line before every hunk header, no AI calls.
+/// Paged through `less -R` when it is taller than the terminal.
+pub fn annotate(range: Option<&str>, staged: bool, files: Option<&str>) -> Result<()> {
+    let pattern = files_pattern(files)?;
+    let diff_output = run_git_diff(&build_diff_args(range, staged))?;
+
+    let changed = changed_paths(&diff_output, pattern.as_ref());
+    if changed.is_empty() {
+        println!("No .rs file changes found.");
+        return Ok(());
+    }
+
+    let mut annotated = String::new();
+    for file in &changed {
+        annotated += &annotate_hunks(extract_file_diff(&diff_output, file))?;
+    }
+    page(&annotated)
+}
+
+/// Inserts `## TOKEN: +added -removed` above each `@@` header, counting that hunk's lines
+fn annotate_hunks(diff: &str) -> Result<String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.starts_with("@@") {
+            let hunk: Vec<&str> = lines[i + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with("@@") && !l.starts_with("diff --git "))
+                .copied()
+                .collect();
+            let (added, removed) = tokens::diff_token_count(&hunk.join("\n"))?;
+            writeln!(out, "## TOKEN: +{added} -{removed}")?;
+        }
+        writeln!(out, "{line}")?;
+    }
+    Ok(out)
+}
+
+/// Prints `text`, through `less -R` if stdout is a terminal too short to hold it
+fn page(text: &str) -> Result<()> {
+    let height = terminal_size::terminal_size().map(|(_, terminal_size::Height(h))| usize::from(h));
+    let tall = height.is_some_and(|h| text.lines().count() > h);
+    if tall
+        && std::io::stdout().is_terminal()
+        && let Ok(mut less) = Command::new("less").arg("-R").stdin(Stdio::piped()).spawn()
+    {
+        if let Some(mut stdin) = less.stdin.take() {
+            // quitting less early closes the pipe; that is not an error
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        less.wait().context("failed to wait for less")?;
+        return Ok(());
+    }
+    print!("{text}");
+    Ok(())
+}
+
+/// Changed `.rs` paths in diff order, limited to `pattern` if given
+fn changed_paths<'a>(diff: &'a str, pattern: Option<&glob::Pattern>) -> Vec<&'a str> {
+    diff.lines()
+        .filter_map(diff_header_path)
+        .filter(|f| pattern.is_none_or(|p| p.matches(f)))
+        .collect()
+}
+
+fn files_pattern(files: Option<&str>) -> Result<Option<glob::Pattern>> {
+    files
+        .map(glob::Pattern::new)
+        .transpose()
+        .with_context(|| format!("invalid --files glob: {}", files.unwrap_or_default()))
+}
+
+/// Old-side path of a `diff --git a/<path> b/<path>` header line
+fn diff_header_path(line: &str) -> Option<&str> {
+    let (path, _) = line.strip_prefix("diff --git a/")?.split_once(" b/")?;
+    path.ends_with(".rs").then_some(path)
+}
+
+fn exit_on_suggestions(fail_on_suggestions: bool, suggestions: usize) {
+    if fail_on_suggestions && suggestions > 0 {
+        std::process::exit(1);
+    }
+}
+
 fn build_diff_args(range: Option<&str>, staged: bool) -> Vec<String> {
     let mut args = vec!["diff".to_string()];
     if staged {
@@ -171,3 +338,27 @@ fn extract_file_diff<'a>(full_diff: &'a str, file: &str) -> &'a str {
     let end = rest[1..].find("diff --git ").map_or(rest.len(), |p| p + 1);
     &rest[..end]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_header_path() {
+        assert_eq!(diff_header_path("diff --git a/src/lib.rs b/src/lib.rs"), Some("src/lib.rs"));
+        assert_eq!(diff_header_path("diff --git a/README.md b/README.md"), None);
+        assert_eq!(diff_header_path("+++ b/src/lib.rs"), None);
+    }
+
+    #[test]
+    fn test_annotate_hunks() {
+        let diff = "diff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n+++ b/src/lib.rs\n\
+                    @@ -1 +1 @@\n-fn a() {}\n+fn b() {}\n@@ -9,0 +9 @@ fn c\n+let x = 1;\n";
+        let annotated = annotate_hunks(diff).unwrap();
+        let lines: Vec<&str> = annotated.lines().collect();
+        assert_eq!(lines[3], "## TOKEN: +4 -4");
+        assert_eq!(lines[4], "@@ -1 +1 @@");
+        assert_eq!(lines[7], "## TOKEN: +6 -0");
+        assert_eq!(lines.len(), 10);
+    }
+}
@@ -1,10 +1,17 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::{openrouter, tokens};
+use crate::{openrouter, templates, tokens};
+
+/// Project mode only needs a preview of each file to describe the architecture
+const PREVIEW_TOKENS: usize = 400;
+/// Sections of CLAUDE.md rewritten from the project explanation; all others are kept
+const GENERATED_SECTIONS: [&str; 2] = ["Overview", "Key Modules"];
 
 const FILE_PROMPT: &str = "\
 You are a Rust code explainer for developer onboarding. \
@@ -18,6 +25,38 @@ Given a list of all source files with their sizes and contents, \
 explain the project architecture: what it does, how modules connect, \
 and where a new developer should start reading. Be concise.";
 
+const FUNCTIONS_PROMPT: &str = "\
+You are a Rust documentation writer. \
+Given a Rust source file and the list of functions it defines, \
+describe what each function does in one short line, suitable for an API lookup table. \
+Return exactly one entry per listed function, using the given names.";
+
+const COMPARE_PROMPT: &str = "\
+You are a Rust architect reviewing a large change. \
+Given architecture explanations of the same project on two branches, \
+summarize what changed structurally: modules added, removed, merged or split, \
+and responsibilities that moved. Ignore wording differences between the explanations. Be concise.";
+
+/// Output style for `--all-functions`
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Aligned plain-text table
+    Table,
+    /// Markdown table
+    Md,
+}
+
+#[derive(Deserialize)]
+struct FunctionList {
+    functions: Vec<FunctionDescription>,
+}
+
+#[derive(Deserialize)]
+struct FunctionDescription {
+    name: String,
+    one_line_description: String,
+}
+
 #[derive(Deserialize)]
 struct FileExplanation {
     purpose: String,
@@ -45,7 +84,7 @@ struct ModuleInfo {
     purpose: String,
 }
 
-fn file_schema() -> serde_json::Value {
+pub(crate) fn file_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -77,7 +116,7 @@ fn file_schema() -> serde_json::Value {
     })
 }
 
-fn project_schema() -> serde_json::Value {
+pub(crate) fn project_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -107,10 +146,96 @@ fn project_schema() -> serde_json::Value {
     })
 }
 
-pub fn run(path: &str, model: &str) -> Result<()> {
+#[derive(Deserialize)]
+struct ArchitectureDiff {
+    summary: String,
+    changes: Vec<ArchitectureChange>,
+}
+
+#[derive(Deserialize)]
+struct ArchitectureChange {
+    area: String,
+    change: String,
+}
+
+pub(crate) fn compare_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "summary": {
+                "type": "string",
+                "description": "1-3 sentence summary of the structural difference"
+            },
+            "changes": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "area": { "type": "string", "description": "Module, file or layer affected" },
+                        "change": { "type": "string", "description": "What changed, one sentence" }
+                    },
+                    "required": ["area", "change"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["summary", "changes"],
+        "additionalProperties": false
+    })
+}
+
+pub(crate) fn functions_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "functions": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string", "description": "Function name as listed" },
+                        "one_line_description": { "type": "string", "description": "What it does, one line" }
+                    },
+                    "required": ["name", "one_line_description"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["functions"],
+        "additionalProperties": false
+    })
+}
+
+/// `all_functions` switches file mode to a per-function lookup table printed as `format`.
+/// `compare` explains the architecture of both the working tree and that branch, then their differences.
+/// `save_to` writes one Markdown explanation per file into that directory, plus `index.md` with `index`.
+pub fn run(
+    path: &str,
+    model: &str,
+    all_functions: bool,
+    format: Format,
+    compare: Option<&str>,
+    save_to: Option<&str>,
+    index: bool,
+) -> Result<()> {
     let p = Path::new(path);
 
-    if p.is_file() {
+    if let Some(dir) = save_to {
+        save_explanations(p, model, Path::new(dir), index)
+    } else if let Some(branch) = compare {
+        if !p.exists() {
+            bail!("Directory not found: {path} (pass the project's source directory)");
+        }
+        if !p.is_dir() {
+            bail!("--compare explains the whole project; pass a directory, not {path}");
+        }
+        explain_compare(branch, model)
+    } else if all_functions {
+        if !p.is_file() {
+            bail!("--all-functions needs a single .rs file, not {path}");
+        }
+        explain_functions(path, model, format)
+    } else if p.is_file() {
         explain_file(path, model)
     } else if p.is_dir() {
         explain_project(model)
@@ -125,13 +250,7 @@ fn explain_file(file: &str, model: &str) -> Result<()> {
     println!("Explaining {file} ({lines} lines, {token_count} tokens) via {model}...");
     eprint!("  analyzing... ");
 
-    let result = openrouter::chat_json::<FileExplanation>(
-        model,
-        FILE_PROMPT,
-        &content,
-        "file_explanation",
-        file_schema(),
-    )?;
+    let result = describe_file(&content, model)?;
     eprintln!("done");
 
     println!();
@@ -153,6 +272,176 @@ fn explain_file(file: &str, model: &str) -> Result<()> {
     Ok(())
 }
 
+fn describe_file(content: &str, model: &str) -> Result<FileExplanation> {
+    openrouter::chat_json::<FileExplanation>(
+        model,
+        FILE_PROMPT,
+        content,
+        "file_explanation",
+        file_schema(),
+    )
+}
+
+/// Explain every file under `path` (or just that file) into `<dir>/<path>.md`, failures are reported and skipped
+fn save_explanations(path: &Path, model: &str, dir: &Path, index: bool) -> Result<()> {
+    let files = if path.is_file() {
+        let file = path.to_string_lossy().into_owned();
+        let (content, tokens, lines) = tokens::read_rs_file(&file)?;
+        vec![(file, content, lines, tokens)]
+    } else if path.is_dir() {
+        tokens::scan_project()?
+            .files
+            .into_iter()
+            .map(|f| (f.path, f.content, f.lines, f.tokens))
+            .collect()
+    } else {
+        bail!("Path not found: {}", path.display())
+    };
+    if files.is_empty() {
+        bail!("No .rs files found in project");
+    }
+
+    println!("Explaining {} file(s) via {model} into {}/...", files.len(), dir.display());
+
+    let mut written = Vec::new();
+    for (file, content, lines, token_count) in &files {
+        eprint!("  {file} ({lines} lines, {token_count} tokens)... ");
+        let result = match describe_file(content, model) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("failed: {e}");
+                continue;
+            }
+        };
+        let out = markdown_path(dir, file);
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&out, file_markdown(file, &result))?;
+        eprintln!("{}", out.display());
+        written.push((file.as_str(), result.purpose));
+    }
+
+    if index {
+        let out = dir.join("index.md");
+        fs::create_dir_all(dir)?;
+        fs::write(&out, index_markdown(&written))?;
+        println!("  Index: {}", out.display());
+    }
+
+    println!();
+    println!("Wrote {} of {} explanation(s)", written.len(), files.len());
+    if written.len() < files.len() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `src/commands/audit.rs` → `<dir>/src/commands/audit.md`
+fn markdown_path(dir: &Path, file: &str) -> PathBuf {
+    dir.join(Path::new(file.trim_start_matches("./")).with_extension("md"))
+}
+
+fn md_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn file_markdown(file: &str, e: &FileExplanation) -> String {
+    let mut out = format!("# `{file}`\n\n{}\n", e.purpose);
+    if !e.key_items.is_empty() {
+        out.push_str(
+            "\n## Key items\n\n| Name | Kind | Description |\n|------|------|-------------|\n",
+        );
+        for item in &e.key_items {
+            out.push_str(&format!(
+                "| `{}` | {} | {} |\n",
+                item.name,
+                item.kind,
+                md_cell(&item.description)
+            ));
+        }
+    }
+    if !e.depends_on.is_empty() {
+        out.push_str("\n## Dependencies\n\n");
+        for dep in &e.depends_on {
+            out.push_str(&format!("- `{dep}`\n"));
+        }
+    }
+    out
+}
+
+/// Links are relative to the index, which sits at the root of the output directory
+fn index_markdown(files: &[(&str, String)]) -> String {
+    let mut out = String::from("# Codebase overview\n\n| File | Purpose |\n|------|---------|\n");
+    for (file, purpose) in files {
+        let link = Path::new(file.trim_start_matches("./")).with_extension("md");
+        out.push_str(&format!(
+            "| [{file}]({}) | {} |\n",
+            link.to_string_lossy().replace('\\', "/"),
+            md_cell(purpose)
+        ));
+    }
+    out
+}
+
+fn explain_functions(file: &str, model: &str, format: Format) -> Result<()> {
+    let (content, token_count, lines) = tokens::read_rs_file(file)?;
+    let fns = super::deep::extract_functions(&content);
+    if fns.is_empty() {
+        bail!("No functions found in {file}");
+    }
+
+    println!(
+        "Explaining {} functions in {file} ({lines} lines, {token_count} tokens) via {model}...",
+        fns.len()
+    );
+    eprint!("  analyzing... ");
+
+    let names: Vec<&str> = fns.iter().map(|f| f.name.as_str()).collect();
+    let prompt = format!("FUNCTIONS: {}\n\nSOURCE:\n{content}", names.join(", "));
+    let result = openrouter::chat_json::<FunctionList>(
+        model,
+        FUNCTIONS_PROMPT,
+        &prompt,
+        "function_list",
+        functions_schema(),
+    )?;
+    eprintln!("done");
+    println!();
+
+    let describe = |name: &str| {
+        result
+            .functions
+            .iter()
+            .find(|d| d.name == name)
+            .map_or("(no description)", |d| d.one_line_description.as_str())
+    };
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0).max(8);
+
+    match format {
+        Format::Table => {
+            println!("  {:>5}  {:<width$}  Description", "Line", "Function");
+            for f in &fns {
+                println!("  {:>5}  {:<width$}  {}", f.line, f.name, describe(&f.name));
+            }
+        }
+        Format::Md => {
+            println!("| Line | Function | Description |");
+            println!("|-----:|----------|-------------|");
+            for f in &fns {
+                println!(
+                    "| {} | `{}` | {} |",
+                    f.line,
+                    f.name,
+                    describe(&f.name).replace('|', "\\|")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn explain_project(model: &str) -> Result<()> {
     let stats = tokens::scan_project()?;
 
@@ -166,37 +455,255 @@ fn explain_project(model: &str) -> Result<()> {
         stats.total_tokens
     );
 
-    let mut manifest = String::new();
-    for f in &stats.files {
-        manifest
-            .push_str(&format!("--- {} ({} lines, {} tokens) ---\n", f.path, f.lines, f.tokens));
-        let preview: String = f.content.lines().take(30).collect::<Vec<_>>().join("\n");
-        manifest.push_str(&preview);
-        manifest.push_str("\n\n");
+    eprint!("  analyzing... ");
+    let result = describe_project(&stats, model)?;
+    eprintln!("done");
+
+    println!();
+    println!("  {}", result.summary);
+    println!();
+
+    println!("  Modules:");
+    for m in &result.modules {
+        println!("    {:<40} {}", m.path, m.purpose);
+    }
+
+    println!();
+    println!("  Start here: {}", result.start_here);
+
+    Ok(())
+}
+
+/// Regenerate the AI-written sections of CLAUDE.md from a project explanation, keep the rest,
+/// and write it after showing the diff and asking
+pub fn update_claude_md(model: &str) -> Result<()> {
+    let stats = tokens::scan_project()?;
+    if stats.files.is_empty() {
+        bail!("No .rs files found in project");
     }
 
+    println!(
+        "Explaining project ({} files, {} tokens) via {model}...",
+        stats.files.len(),
+        stats.total_tokens
+    );
     eprint!("  analyzing... ");
+    let result = describe_project(&stats, model)?;
+    eprintln!("done");
+    println!();
+
+    let existing = fs::read_to_string("CLAUDE.md").ok();
+    let updated = claude_md(existing.as_deref().unwrap_or(templates::CLAUDE_MD), &result);
+    if existing.as_deref() == Some(updated.as_str()) {
+        println!("CLAUDE.md is already up to date.");
+        return Ok(());
+    }
+
+    println!("CLAUDE.md:");
+    super::rewrite::print_diff(existing.as_deref().unwrap_or_default(), &updated);
+    if !matches!(tokens::ask_accept("Write CLAUDE.md? [y/n]")?.as_str(), "y" | "Y") {
+        println!("Kept CLAUDE.md");
+        return Ok(());
+    }
+    fs::write("CLAUDE.md", &updated).context("failed to write CLAUDE.md")?;
+    println!("Wrote CLAUDE.md");
+    Ok(())
+}
+
+/// `base` with Overview and Key Modules regenerated, in the canonical order Overview,
+/// Code Style, Key Modules, Build & Check, then every other section of `base` as it was.
+/// Code Style and Build & Check come from the template when `base` lacks them.
+fn claude_md(base: &str, project: &ProjectExplanation) -> String {
+    let (preamble, mut kept) = md_sections(base);
+    kept.retain(|(title, _)| !GENERATED_SECTIONS.contains(&title.as_str()));
+    let (_, template) = md_sections(templates::CLAUDE_MD);
+    let mut take = |title: &str| {
+        match kept.iter().position(|(t, _)| t == title) {
+            Some(i) => Some(kept.remove(i).1),
+            None => template.iter().find(|(t, _)| t == title).map(|(_, text)| text.clone()),
+        }
+        .unwrap_or_default()
+    };
 
-    let result = openrouter::chat_json::<ProjectExplanation>(
+    let overview =
+        format!("## Overview\n{}\n\nStart here: {}\n", project.summary, project.start_here);
+    let modules: String =
+        project.modules.iter().map(|m| format!("- `{}` — {}\n", m.path, m.purpose)).collect();
+    let mut sections = vec![
+        overview,
+        take("Code Style"),
+        format!("## Key Modules\n{modules}"),
+        take("Build & Check"),
+    ];
+    sections.extend(kept.into_iter().map(|(_, text)| text));
+
+    let preamble = if preamble.trim().is_empty() { "# Project Instructions" } else { &preamble };
+    let mut md = format!("{}\n\n", preamble.trim_end());
+    for text in sections.iter().filter(|t| !t.is_empty()) {
+        md += text.trim_end();
+        md += "\n\n";
+    }
+    md.truncate(md.trim_end().len());
+    md + "\n"
+}
+
+/// Text before the first `## ` heading, then `(title, text)` for each `## ` section
+fn md_sections(md: &str) -> (String, Vec<(String, String)>) {
+    let mut preamble = String::new();
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in md.lines() {
+        if let Some(title) = line.strip_prefix("## ") {
+            sections.push((title.trim().to_string(), String::new()));
+        }
+        let text = sections.last_mut().map_or(&mut preamble, |(_, text)| text);
+        text.push_str(line);
+        text.push('\n');
+    }
+    (preamble, sections)
+}
+
+fn describe_project(stats: &tokens::ProjectStats, model: &str) -> Result<ProjectExplanation> {
+    let manifest =
+        tokens::build_manifest_truncated(stats, PREVIEW_TOKENS, tokens::model_token_limit(model))?;
+    openrouter::chat_json::<ProjectExplanation>(
         model,
         PROJECT_PROMPT,
         &manifest,
         "project_explanation",
         project_schema(),
+    )
+}
+
+fn explain_compare(branch: &str, model: &str) -> Result<()> {
+    let current = tokens::scan_project()?;
+    let target = tokens::scan_sources(
+        tokens::git_list_rs_files(branch)?
+            .into_iter()
+            .filter_map(|path| Some((path.clone(), tokens::git_show_file(branch, &path).ok()?))),
+    )?;
+    if current.files.is_empty() || target.files.is_empty() {
+        bail!("No .rs files found on one side of the comparison");
+    }
+
+    println!(
+        "Comparing architecture: working tree ({} files, {} tokens) vs {branch} ({} files, {} tokens) via {model}...",
+        current.files.len(),
+        current.total_tokens,
+        target.files.len(),
+        target.total_tokens
+    );
+
+    eprint!("  explaining working tree... ");
+    let ours = describe_project(&current, model)?;
+    eprintln!("done");
+    eprint!("  explaining {branch}... ");
+    let theirs = describe_project(&target, model)?;
+    eprintln!("done");
+
+    let prompt = format!(
+        "BEFORE ({branch}):\n{}\n\nAFTER (working tree):\n{}",
+        outline(&theirs),
+        outline(&ours)
+    );
+    eprint!("  comparing... ");
+    let diff = openrouter::chat_json::<ArchitectureDiff>(
+        model,
+        COMPARE_PROMPT,
+        &prompt,
+        "architecture_diff",
+        compare_schema(),
     )?;
     eprintln!("done");
 
     println!();
-    println!("  {}", result.summary);
+    println!("  {branch}: {}", theirs.summary);
+    println!("  working tree: {}", ours.summary);
     println!();
+    println!("  {}", diff.summary);
 
-    println!("  Modules:");
-    for m in &result.modules {
-        println!("    {:<40} {}", m.path, m.purpose);
+    if !diff.changes.is_empty() {
+        println!();
+        println!("  Changes:");
+        for c in &diff.changes {
+            println!("    {:<40} {}", c.area, c.change);
+        }
     }
 
-    println!();
-    println!("  Start here: {}", result.start_here);
-
     Ok(())
 }
+
+/// Plain-text form of an explanation, used as input for the comparison prompt
+fn outline(e: &ProjectExplanation) -> String {
+    let mut out = format!("Summary: {}\nModules:\n", e.summary);
+    for m in &e.modules {
+        out.push_str(&format!("- {}: {}\n", m.path, m.purpose));
+    }
+    out.push_str(&format!("Start here: {}\n", e.start_here));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_markdown_layout() {
+        assert_eq!(
+            markdown_path(Path::new("wiki"), "src/commands/audit.rs"),
+            Path::new("wiki/src/commands/audit.md")
+        );
+
+        let e = FileExplanation {
+            purpose: "Audits things.".into(),
+            key_items: vec![KeyItem {
+                name: "run".into(),
+                kind: "function".into(),
+                description: "Entry point | prints a report".into(),
+            }],
+            depends_on: vec!["tokens".into()],
+        };
+        let md = file_markdown("src/commands/audit.rs", &e);
+        assert!(md.starts_with("# `src/commands/audit.rs`\n\nAudits things.\n"));
+        assert!(md.contains("| `run` | function | Entry point \\| prints a report |\n"));
+        assert!(md.ends_with("## Dependencies\n\n- `tokens`\n"));
+
+        let index = index_markdown(&[("src/commands/audit.rs", "Audits things.".into())]);
+        assert!(
+            index.contains("| [src/commands/audit.rs](src/commands/audit.md) | Audits things. |")
+        );
+    }
+
+    #[test]
+    fn test_claude_md_merge() {
+        let project = ProjectExplanation {
+            summary: "A CLI.".into(),
+            modules: vec![ModuleInfo { path: "src/main.rs".into(), purpose: "Entry point".into() }],
+            start_here: "src/main.rs".into(),
+        };
+        let fresh = claude_md(templates::CLAUDE_MD, &project);
+        let titles: Vec<String> = md_sections(&fresh).1.into_iter().map(|(t, _)| t).collect();
+        assert_eq!(
+            titles,
+            [
+                "Overview",
+                "Code Style",
+                "Key Modules",
+                "Build & Check",
+                "Crates to prefer (when applicable)",
+                "Toolchain"
+            ]
+        );
+        assert!(fresh.starts_with("# Project Instructions\n\n## Overview\nA CLI.\n"));
+        assert!(
+            fresh.contains("## Key Modules\n- `src/main.rs` — Entry point\n\n## Build & Check")
+        );
+
+        let custom = "# Mine\n\n## Key Modules\n- old\n\n## Notes\nKeep me.\n";
+        let merged = claude_md(custom, &project);
+        assert!(merged.starts_with("# Mine\n\n## Overview\n"));
+        assert!(!merged.contains("- old"));
+        assert!(merged.contains("## Code Style\n- Write minimal"));
+        assert!(merged.ends_with("## Notes\nKeep me.\n"));
+        assert_eq!(claude_md(&merged, &project), merged);
+    }
+}
@@ -1,10 +1,51 @@
+use std::collections::HashSet;
 use std::process::Command;
 
 use anyhow::{Context, Result};
+use serde::Deserialize;
 
 use crate::tokens;
 
-pub fn run() -> Result<()> {
+#[derive(Deserialize)]
+struct ClippyMsg {
+    reason: String,
+    message: Option<Diagnostic>,
+}
+
+#[derive(Deserialize)]
+struct Diagnostic {
+    code: Option<Code>,
+    spans: Vec<Span>,
+    children: Vec<Self>,
+}
+
+#[derive(Deserialize)]
+struct Code {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: u32,
+    suggestion_applicability: Option<String>,
+}
+
+impl Diagnostic {
+    /// `clippy --fix` only applies machine-applicable suggestions
+    fn is_fixable(&self) -> bool {
+        self.spans
+            .iter()
+            .any(|s| s.suggestion_applicability.as_deref() == Some("MachineApplicable"))
+            || self.children.iter().any(Self::is_fixable)
+    }
+}
+
+pub fn run(check: bool) -> Result<()> {
+    if check {
+        return check_only();
+    }
+
     let before = {
         let stats = tokens::scan_project()?;
         stats.total_tokens
@@ -30,7 +71,7 @@ pub fn run() -> Result<()> {
     println!("Tokens after:  {after}");
 
     if diff > 0 {
-        let pct = (diff as f64 / before as f64) * 100.0;
+        let pct = tokens::pct_delta(diff, before);
         println!("Saved:         {diff} tokens ({pct:.1}%)");
     } else if diff < 0 {
         println!("Added:         {} tokens (formatting may add whitespace)", diff.unsigned_abs());
@@ -40,3 +81,70 @@ pub fn run() -> Result<()> {
 
     Ok(())
 }
+
+/// Report what `fix` would change without touching any file; exits 1 if anything is fixable
+fn check_only() -> Result<()> {
+    println!("Running clippy...");
+    let clippy = Command::new("cargo")
+        .args(["clippy", "--all-targets", "--message-format=json"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .context("failed to run cargo clippy")?;
+    let fixable = count_fixable(&String::from_utf8_lossy(&clippy.stdout));
+
+    println!("Running fmt --check...");
+    let fmt = Command::new("cargo")
+        .args(["fmt", "--check", "--", "-l"])
+        .output()
+        .context("failed to run cargo fmt --check")?;
+    let unformatted = String::from_utf8_lossy(&fmt.stdout).lines().count();
+
+    println!();
+    if fixable == 0 && unformatted == 0 {
+        println!("Nothing to fix — code is clean and formatted.");
+        return Ok(());
+    }
+    println!(
+        "{fixable} auto-fixable clippy warning(s), {unformatted} formatting issue(s) — run `cargo syntax fix` to apply"
+    );
+    std::process::exit(1);
+}
+
+/// Distinct clippy diagnostics in `--message-format=json` output that `--fix` can apply
+fn count_fixable(json: &str) -> usize {
+    let mut seen = HashSet::new();
+    json.lines()
+        .filter_map(|l| serde_json::from_str::<ClippyMsg>(l).ok())
+        .filter(|m| m.reason == "compiler-message")
+        .filter_map(|m| m.message)
+        .filter(|d| d.code.as_ref().is_some_and(|c| c.code.starts_with("clippy::")))
+        .filter(Diagnostic::is_fixable)
+        .filter(|d| {
+            let span = d.spans.first().map(|s| (s.file_name.clone(), s.line_start));
+            seen.insert((span, d.code.as_ref().map(|c| c.code.clone())))
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_fixable() {
+        let msg = |code: &str, applicability: &str| {
+            format!(
+                r#"{{"reason":"compiler-message","message":{{"code":{{"code":"{code}"}},"spans":[{{"file_name":"src/main.rs","line_start":2,"suggestion_applicability":null}}],"children":[{{"code":null,"spans":[{{"file_name":"src/main.rs","line_start":2,"suggestion_applicability":"{applicability}"}}],"children":[]}}]}}}}"#
+            )
+        };
+        let output = [
+            msg("clippy::needless_return", "MachineApplicable"),
+            msg("clippy::needless_return", "MachineApplicable"),
+            msg("clippy::manual_let_else", "HasPlaceholders"),
+            msg("dead_code", "MachineApplicable"),
+            r#"{"reason":"build-finished","success":true}"#.to_string(),
+        ]
+        .join("\n");
+        assert_eq!(count_fixable(&output), 1);
+    }
+}
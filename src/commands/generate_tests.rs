@@ -1,6 +1,7 @@
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use glob::Pattern;
 use serde::Deserialize;
 use serde_json::json;
 
@@ -21,6 +22,22 @@ Rules: \
 9. In Rust edition 2024, std::env::set_var/remove_var are unsafe — wrap in unsafe {} \
 10. Return ONLY the test functions, no markdown fences or explanations";
 
+const PROPERTY_PROMPT: &str = "\
+You are a Rust test engineer. Given a Rust source file from a crate, generate property-based \
+integration tests with the `proptest` crate. The tests will be placed in a separate file (tests/ directory). \
+proptest syntax: `use proptest::prelude::*;` then a `proptest! { ... }` block containing \
+`#[test] fn name(x in any::<u32>(), s in \"[a-z]{1,8}\", v in prop::collection::vec(any::<i64>(), 0..50)) { ... }`. \
+`any::<T>()` works for every type implementing `Arbitrary` (primitives, String, Vec, Option, tuples); \
+use range (`0..100usize`) or regex string strategies to constrain inputs, and `prop_assert!`/`prop_assert_eq!` inside the block. \
+Rules: \
+1. Import the crate with `use <crate_name>::<module>::*;` — do NOT use `mod tests` or `use super::*;` \
+2. Test invariants that hold for all inputs (round-trips, idempotence, ordering, bounds, length relations), \
+never specific expected values \
+3. Cover every public function that takes generatable inputs; skip ones needing filesystem or network \
+4. Use descriptive test names: prop_<function>_<invariant> \
+5. Keep tests minimal and token-efficient (no unnecessary comments) \
+6. Return ONLY the test code, no markdown fences or explanations";
+
 const EXPLAIN_PROMPT: &str = "\
 Given a Rust source file and generated tests, produce a brief summary of test coverage.";
 
@@ -32,7 +49,20 @@ struct TestCoverage {
     coverage_notes: String,
 }
 
-fn coverage_schema() -> serde_json::Value {
+/// Public functions of one source file and which of them the generated tests cover
+struct Coverage {
+    file: String,
+    public: Vec<String>,
+    tested: Vec<String>,
+}
+
+impl Coverage {
+    fn missing(&self) -> impl Iterator<Item = &str> {
+        self.public.iter().filter(|f| !self.tested.contains(f)).map(String::as_str)
+    }
+}
+
+pub(crate) fn coverage_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -60,7 +90,150 @@ fn coverage_schema() -> serde_json::Value {
     })
 }
 
-pub fn run(file: &str, output: Option<&str>, model: &str) -> Result<()> {
+/// With `dry_run`, print the tests and exit 1 if they contain no test functions, never writing.
+/// With `threshold`, exit 1 after writing when fewer than that % of public functions are tested.
+/// `property` asks for `proptest` invariants instead of example-based tests.
+pub fn run(
+    file: &str,
+    output: Option<&str>,
+    model: &str,
+    dry_run: bool,
+    threshold: Option<u8>,
+    property: bool,
+) -> Result<()> {
+    if property {
+        check_proptest_dependency();
+    }
+    let (test_code, coverage) = generate(file, model, property)?;
+    let below = threshold.is_some_and(|t| below_threshold(std::slice::from_ref(&coverage), t));
+
+    println!();
+    tokens::separator(70);
+    println!("{test_code}");
+    tokens::separator(70);
+
+    if dry_run {
+        finish_dry_run(count_test_fns(&test_code), below);
+        return Ok(());
+    }
+
+    // Determine output path
+    let target = if let Some(out) = output { out.to_string() } else { default_test_path(file) };
+
+    println!();
+    let input = tokens::ask_accept(&format!("Write to {target}? [y/n/append]"))?;
+
+    match input.as_str() {
+        "y" | "Y" => {
+            write_tests(&target, &test_code, false)?;
+            println!("Written to {target}");
+            try_compile(&target);
+        }
+        "a" | "append" => {
+            write_tests(&target, &test_code, true)?;
+            println!("Appended to {target}");
+            try_compile(&target);
+        }
+        _ => println!("Discarded."),
+    }
+
+    exit_if_below(below);
+    Ok(())
+}
+
+/// Generate tests for every .rs file under `dir`, one test file each or one `--combine`d file
+pub fn run_module(
+    dir: &str,
+    model: &str,
+    combine: bool,
+    exclude: Option<&Pattern>,
+    dry_run: bool,
+    threshold: Option<u8>,
+    property: bool,
+) -> Result<()> {
+    let root = Path::new(dir);
+    if !root.is_dir() {
+        bail!("Not a directory: {dir}");
+    }
+    let module = module_name(dir);
+
+    let mut files: Vec<String> = tokens::rust_file_walker_at(root)
+        .map(|e| e.path().display().to_string())
+        .filter(|f| !exclude.is_some_and(|p| is_excluded(p, f)))
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        bail!("No .rs files found in {dir}");
+    }
+
+    if property {
+        check_proptest_dependency();
+    }
+    println!("Generating tests for {} file(s) in {dir}\n", files.len());
+    let mut generated = Vec::new();
+    let mut coverage = Vec::new();
+    for file in &files {
+        match generate(file, model, property) {
+            Ok((code, cov)) => {
+                generated.push((file.as_str(), code));
+                coverage.push(cov);
+            }
+            Err(e) => eprintln!("  skipping {file}: {e}"),
+        }
+        println!();
+    }
+    let below = threshold.is_some_and(|t| below_threshold(&coverage, t));
+
+    let outputs: Vec<(String, String)> = if combine {
+        let sections = generated
+            .iter()
+            .map(|(file, code)| combined_section(root, file, code))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        vec![(format!("tests/test_{module}.rs"), sections)]
+    } else {
+        generated
+            .into_iter()
+            .map(|(file, code)| (module_test_path(root, &module, file), code))
+            .collect()
+    };
+    if outputs.is_empty() {
+        bail!("No tests generated");
+    }
+
+    if dry_run {
+        for (target, code) in &outputs {
+            println!("{target}:");
+            tokens::separator(70);
+            println!("{code}");
+            tokens::separator(70);
+        }
+        finish_dry_run(outputs.iter().map(|(_, code)| count_test_fns(code)).sum(), below);
+        return Ok(());
+    }
+
+    tokens::separator(70);
+    for (target, code) in &outputs {
+        println!("  {target}  ({} lines)", code.lines().count());
+    }
+    println!();
+    let input = tokens::ask_accept(&format!("Write {} test file(s)? [y/n]", outputs.len()))?;
+    if matches!(input.as_str(), "y" | "Y") {
+        for (target, code) in &outputs {
+            write_tests(target, code, false)?;
+            println!("Written to {target}");
+        }
+        try_compile("tests/");
+    } else {
+        println!("Discarded.");
+    }
+
+    exit_if_below(below);
+    Ok(())
+}
+
+/// Ask the model for tests of one file and print its coverage analysis
+fn generate(file: &str, model: &str, property: bool) -> Result<(String, Coverage)> {
     let (content, token_count, lines) = tokens::read_rs_file(file)?;
 
     let crate_name = detect_crate_name();
@@ -74,8 +247,14 @@ pub fn run(file: &str, output: Option<&str>, model: &str) -> Result<()> {
          Import as: use {crate_name}::{module_path}::*;\n\n\
          Source file ({file}):\n{content}"
     );
-    let test_code = openrouter::chat(model, TEST_PROMPT, &prompt)?;
-    let test_code = tokens::strip_markdown_fences(&test_code);
+    let system = if property { PROPERTY_PROMPT } else { TEST_PROMPT };
+    let test_code = openrouter::chat(model, system, &prompt)?;
+    // The model sometimes splits tests across several code blocks
+    let test_code = tokens::strip_all_markdown_fences(&test_code)
+        .into_iter()
+        .filter(|b| !b.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
     eprintln!("done");
 
     let test_tokens = tokens::count_tokens(&test_code)?;
@@ -107,32 +286,116 @@ pub fn run(file: &str, output: Option<&str>, model: &str) -> Result<()> {
         println!("  {}", cov.coverage_notes);
     }
 
+    let tested = coverage.map(|c| c.functions_tested).unwrap_or_default();
+    let coverage = Coverage {
+        file: file.to_string(),
+        public: public_fns(&content),
+        tested: tested.iter().map(|name| fn_name(name)).collect(),
+    };
+    Ok((test_code, coverage))
+}
+
+/// `--dry-run` exits 1 when the model returned no test functions or coverage is too low
+fn finish_dry_run(count: usize, below_threshold: bool) {
     println!();
-    tokens::separator(70);
-    println!("{test_code}");
-    tokens::separator(70);
+    if count == 0 {
+        eprintln!("Dry run: no test functions generated.");
+        std::process::exit(1);
+    }
+    println!("Dry run: {count} test function(s) generated, nothing written.");
+    exit_if_below(below_threshold);
+}
 
-    // Determine output path
-    let target = if let Some(out) = output { out.to_string() } else { default_test_path(file) };
+fn exit_if_below(below_threshold: bool) {
+    if below_threshold {
+        std::process::exit(1);
+    }
+}
+
+/// Print the coverage against `threshold` percent and any untested functions; true when below
+fn below_threshold(coverage: &[Coverage], threshold: u8) -> bool {
+    let public: usize = coverage.iter().map(|c| c.public.len()).sum();
+    let missing: Vec<(&str, &str)> =
+        coverage.iter().flat_map(|c| c.missing().map(|f| (c.file.as_str(), f))).collect();
+    let tested = public - missing.len();
+    let pct = if public > 0 { tokens::pct(tested, public) } else { 100.0 };
 
     println!();
-    let input = tokens::ask_accept(&format!("Write to {target}? [y/n/append]"))?;
+    if pct >= f64::from(threshold) {
+        println!("Coverage: {tested}/{public} public functions ({pct:.0}%) — meets {threshold}%");
+        return false;
+    }
 
-    match input.as_str() {
-        "y" | "Y" => {
-            write_tests(&target, &test_code, false)?;
-            println!("Written to {target}");
-            try_compile(&target);
-        }
-        "a" | "append" => {
-            write_tests(&target, &test_code, true)?;
-            println!("Appended to {target}");
-            try_compile(&target);
-        }
-        _ => println!("Discarded."),
+    println!("Coverage: {tested}/{public} public functions ({pct:.0}%) — below {threshold}%");
+    println!("Not covered:");
+    for (file, name) in &missing {
+        println!("  {file}: {name}");
     }
+    println!();
+    println!("For I/O-bound functions, add an ignored stub so they are tracked:");
+    if let Some((_, name)) = missing.first() {
+        println!(
+            "  #[test]\n  #[ignore = \"needs filesystem or network\"]\n  fn test_{name}() {{}}"
+        );
+    }
+    true
+}
 
-    Ok(())
+/// Names of the `pub fn`s outside test code; `pub(crate)` and narrower are unreachable from tests/
+fn public_fns(content: &str) -> Vec<String> {
+    let (production, _) = tokens::split_tests(content);
+    super::deep::extract_functions(&production)
+        .into_iter()
+        .filter(|f| f.body.trim_start().starts_with("pub "))
+        .map(|f| f.name)
+        .collect()
+}
+
+/// The model may report `tokens::count_tokens` or `count_tokens()`; keep the bare name
+fn fn_name(reported: &str) -> String {
+    let name = reported.trim().trim_end_matches("()");
+    name.rsplit("::").next().unwrap_or(name).to_string()
+}
+
+fn count_test_fns(code: &str) -> usize {
+    code.lines()
+        .map(str::trim_start)
+        .filter(|l| l.starts_with("#[test]") || l.starts_with("#[tokio::test"))
+        .count()
+}
+
+/// `--exclude` matches either the file name (`mod.rs`) or the whole path (`src/*/mod.rs`)
+fn is_excluded(pattern: &Pattern, file: &str) -> bool {
+    let name = Path::new(file).file_name().unwrap_or_default().to_string_lossy();
+    pattern.matches(&name) || pattern.matches(file)
+}
+
+fn module_name(dir: &str) -> String {
+    Path::new(dir.trim_end_matches('/'))
+        .file_name()
+        .map_or_else(|| "module".to_string(), |n| n.to_string_lossy().replace('-', "_"))
+}
+
+/// `src/commands/ci.rs` in module `commands` → `tests/test_commands_ci.rs`
+fn module_test_path(root: &Path, module: &str, file: &str) -> String {
+    format!("tests/test_{module}_{}.rs", file_slug(root, file))
+}
+
+/// File path below `root` without extension, with `/` turned into `_`
+fn file_slug(root: &Path, file: &str) -> String {
+    let rel = Path::new(file).strip_prefix(root).unwrap_or(Path::new(file));
+    rel.with_extension("").display().to_string().replace(['/', '\\', '-'], "_")
+}
+
+/// Wrap one file's tests in its own `mod section_<file>` so imports and test names can't clash;
+/// the prefix keeps names like `mod.rs`, `type.rs` or `1x.rs` valid identifiers
+fn combined_section(root: &Path, file: &str, code: &str) -> String {
+    let body = code.lines().map(|l| if l.is_empty() { String::new() } else { format!("    {l}") });
+    format!(
+        "// ===== {file} =====\nmod section_{} {{\n{}\n}}",
+        super::refactor::module_ident(&file_slug(root, file)),
+        body.collect::<Vec<_>>().join("\n")
+    )
 }
 
 fn default_test_path(source: &str) -> String {
@@ -195,6 +458,35 @@ fn detect_crate_name() -> String {
         .unwrap_or_else(|| "crate_name".to_string())
 }
 
+/// Property tests need `proptest`; say how to add it rather than letting the tests fail to compile
+fn check_proptest_dependency() {
+    let manifest = std::fs::read_to_string("Cargo.toml").unwrap_or_default();
+    if !declares_dependency(&manifest, "proptest") {
+        println!("Note: proptest is not in Cargo.toml. Add it before compiling the tests:");
+        println!("  cargo add --dev proptest\n");
+    }
+}
+
+/// Whether any `[*dependencies]` table of `manifest` has `name` as a key (`name = …` or `name.workspace = true`)
+fn declares_dependency(manifest: &str, name: &str) -> bool {
+    let mut in_deps = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            let table = line.trim_matches(['[', ']']);
+            if table.ends_with(&format!("dependencies.{name}")) {
+                return true;
+            }
+            in_deps = table.ends_with("dependencies");
+            continue;
+        }
+        let key = line.split(['=', '.']).next().unwrap_or_default().trim();
+        if in_deps && key == name {
+            return true;
+        }
+    }
+    false
+}
+
 fn file_to_module_path(file: &str) -> String {
     file.strip_prefix("src/")
         .unwrap_or(file)
@@ -225,6 +517,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_count_test_fns() {
+        let code = "use x::*;\n\n#[test]\nfn a() {}\n\n    #[tokio::test]\n    async fn b() {}\n";
+        assert_eq!(count_test_fns(code), 2);
+        assert_eq!(count_test_fns("fn helper() {}"), 0);
+    }
+
+    #[test]
+    fn test_public_fns_and_coverage() {
+        let src = "pub fn a() {}\nfn b() {}\npub(crate) fn c() {}\n    pub fn d(&self) {}\n\n\
+                   #[cfg(test)]\nmod tests {\n    pub fn helper() {}\n}\n";
+        let public = public_fns(src);
+        assert_eq!(public, ["a", "d"]);
+
+        let tested = ["tokens::a()", "b"].iter().map(|n| fn_name(n)).collect();
+        let cov = Coverage { file: "src/x.rs".to_string(), public, tested };
+        assert_eq!(cov.missing().collect::<Vec<_>>(), ["d"]);
+        assert!(below_threshold(std::slice::from_ref(&cov), 60));
+        assert!(!below_threshold(&[cov], 50));
+    }
+
+    #[test]
+    fn test_declares_dependency() {
+        let manifest = "[package]\nname = \"proptest-demo\"\n\n[dependencies]\nserde = \"1\"\n\n\
+                        [dev-dependencies]\nproptest = \"1\"\n";
+        assert!(declares_dependency(manifest, "proptest"));
+        assert!(declares_dependency(manifest, "serde"));
+        assert!(!declares_dependency("[package]\nname = \"proptest\"\n", "proptest"));
+        assert!(declares_dependency("[dev-dependencies]\nproptest.workspace = true\n", "proptest"));
+        assert!(declares_dependency("[dev-dependencies.proptest]\nversion = \"1\"\n", "proptest"));
+        assert!(!declares_dependency(
+            "[dev-dependencies]\nproptest-derive = \"0.5\"\n",
+            "proptest"
+        ));
+    }
+
     #[test]
     fn test_detect_crate_name() {
         assert_eq!(detect_crate_name(), "cargo_syntax");
@@ -247,4 +575,39 @@ mod tests {
             assert_eq!(default_test_path(input), expected, "input: {input}");
         }
     }
+
+    #[test]
+    fn test_module_test_path() {
+        let root = Path::new("src/commands");
+        assert_eq!(module_name("src/commands/"), "commands");
+        assert_eq!(
+            module_test_path(root, "commands", "src/commands/ci.rs"),
+            "tests/test_commands_ci.rs"
+        );
+        assert_eq!(
+            module_test_path(root, "commands", "src/commands/sub/mod.rs"),
+            "tests/test_commands_sub_mod.rs"
+        );
+    }
+
+    #[test]
+    fn test_combined_section_idents() {
+        let root = Path::new("src/commands");
+        for (file, module) in [
+            ("src/commands/mod.rs", "mod section_mod {"),
+            ("src/commands/type.rs", "mod section_type {"),
+            ("src/commands/1x.rs", "mod section_m_1x {"),
+            ("src/commands/sub/mod.rs", "mod section_sub_mod {"),
+        ] {
+            let section = combined_section(root, file, "#[test]\nfn t() {}");
+            assert_eq!(section.lines().nth(1), Some(module), "file: {file}");
+        }
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let pattern = Pattern::new("mod.rs").unwrap();
+        assert!(is_excluded(&pattern, "src/commands/mod.rs"));
+        assert!(!is_excluded(&pattern, "src/commands/ci.rs"));
+    }
 }
@@ -1,46 +1,224 @@
+use std::collections::HashSet;
+use std::path::Path;
 use std::process::Command;
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use serde::{Deserialize, Serialize};
 
 use crate::tokens;
 
+/// Plot rows in `--graph`, not counting the axis
+const GRAPH_HEIGHT: usize = 12;
+
+/// Room for the y-axis labels left of the `--graph` plot
+const Y_LABEL_WIDTH: usize = 9;
+
+/// Token growth over the previous commit that gets a `*` in `--graph`
+const SPIKE_PCT: f64 = 5.0;
+
+/// Saving and reloading scanned history, so repeated runs skip the git scan
+#[derive(Args)]
+pub struct HistoryStore {
+    /// Write the scanned commits to this JSON file
+    #[arg(long, value_name = "FILE")]
+    pub export: Option<String>,
+    /// Show commits from a previous --export instead of scanning git
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["export", "file", "since_tag", "until_tag", "author", "since"]
+    )]
+    pub import: Option<String>,
+    /// Keep the commits already in the --export file and only scan new ones
+    #[arg(long, requires = "export")]
+    pub merge: bool,
+}
+
+/// Narrows the commits `git log` lists
+#[derive(Args)]
+pub struct CommitFilter {
+    /// Only commits by this author (matched like `git log --author`), plus their own token delta
+    #[arg(long, value_name = "EMAIL")]
+    pub author: Option<String>,
+    /// Only commits after this date (e.g. 2026-10-01 or "2 weeks ago")
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<String>,
+}
+
+impl CommitFilter {
+    fn git_args(&self) -> Vec<String> {
+        let author = self.author.iter().map(|a| format!("--author={a}"));
+        author.chain(self.since.iter().map(|s| format!("--since={s}"))).collect()
+    }
+}
+
+/// `--graph` chart options
+#[derive(Args)]
+pub struct GraphOptions {
+    /// Draw an ASCII chart of total tokens per commit, with each commit's grade
+    #[arg(long, conflicts_with = "file")]
+    pub graph: bool,
+    /// Chart width in columns (default: terminal width)
+    #[arg(long, value_name = "N", requires = "graph")]
+    pub width: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
 struct CommitStats {
     hash: String,
     message: String,
-    files: usize,
-    tokens: usize,
-    lines: usize,
+    /// Commit time, seconds since the Unix epoch
+    timestamp: i64,
+    #[serde(flatten)]
+    stats: tokens::RevStats,
 }
 
-pub fn run(n: usize) -> Result<()> {
-    let output = Command::new("git").args(["log", "--oneline", "-n", &n.to_string()]).output()?;
+/// With `since_tag`, all commits in `since..until` (default HEAD) are scanned and `n` is ignored
+pub fn run(
+    n: usize,
+    file: Option<&str>,
+    since_tag: Option<&str>,
+    until_tag: Option<&str>,
+    filter: &CommitFilter,
+    store: &HistoryStore,
+    graph: &GraphOptions,
+) -> Result<()> {
+    if let Some(path) = &store.import {
+        let snapshots = load_snapshots(path)?;
+        if snapshots.is_empty() {
+            bail!("No commits in {path}");
+        }
+        println!("Loaded {} commits from {path}\n", snapshots.len());
+        print_snapshots(&snapshots);
+        print_graph(&snapshots, graph);
+        return Ok(());
+    }
+
+    let mut args = vec!["log".to_string(), "--format=%H %ct %s".to_string()];
+    args.extend(filter.git_args());
+    match since_tag {
+        Some(since) => args.push(format!("{since}..{}", until_tag.unwrap_or("HEAD"))),
+        None => {
+            args.extend(["-n".to_string(), n.to_string()]);
+            args.extend(until_tag.map(String::from));
+        }
+    }
+    let output = Command::new("git").args(&args).output()?;
 
     if !output.status.success() {
         bail!("git log failed — are you in a git repository?");
     }
 
     let log = String::from_utf8_lossy(&output.stdout);
-    let commits: Vec<(&str, &str)> = log.lines().filter_map(|line| line.split_once(' ')).collect();
+    let commits: Vec<(&str, i64, &str)> = log.lines().filter_map(parse_log_line).collect();
 
     if commits.is_empty() {
         bail!("No commits found");
     }
 
-    println!("Scanning {} commits for token trends...\n", commits.len());
+    if let Some(file) = file {
+        file_history(&commits, file.trim_start_matches("./"))?;
+        if since_tag.is_some() {
+            println!();
+            println!("Note: per-version summary skipped — it covers the whole project, not --file");
+        }
+        return Ok(());
+    }
+
+    let existing = match &store.export {
+        Some(path) if store.merge && Path::new(path).exists() => load_snapshots(path)?,
+        _ => Vec::new(),
+    };
+    let known: HashSet<&str> = existing.iter().map(|s| s.hash.as_str()).collect();
+    let new: Vec<_> = commits.iter().filter(|(hash, ..)| !known.contains(hash)).collect();
 
-    let mut snapshots: Vec<CommitStats> = Vec::new();
+    match commits.len() - new.len() {
+        0 => println!("Scanning {} commits for token trends...\n", new.len()),
+        kept => println!(
+            "Scanning {} commits for token trends ({kept} already exported)...\n",
+            new.len()
+        ),
+    }
 
-    for (hash, msg) in &commits {
-        let rev = tokens::count_rev_tokens(hash)?;
-        snapshots.push(CommitStats {
+    let mut scanned: Vec<CommitStats> = Vec::new();
+    for (hash, timestamp, msg) in new {
+        scanned.push(CommitStats {
             hash: hash.to_string(),
             message: msg.to_string(),
-            files: rev.files,
-            tokens: rev.tokens,
-            lines: rev.lines,
+            timestamp: *timestamp,
+            stats: tokens::count_rev_tokens(hash)?,
         });
     }
+    let snapshots = merge_snapshots(existing, scanned);
+
+    print_snapshots(&snapshots);
+    print_graph(&snapshots, graph);
+
+    if let Some(path) = &store.export {
+        let json = serde_json::to_string_pretty(&snapshots)?;
+        std::fs::write(path, json + "\n").with_context(|| format!("failed to write {path}"))?;
+        println!("\nExported {} commits to {path}", snapshots.len());
+    }
+
+    if let Some(author) = &filter.author {
+        print_author_impact(author, &commits)?;
+    }
+
+    if let Some(since) = since_tag {
+        print_version_deltas(since, until_tag)?;
+    }
 
+    Ok(())
+}
+
+/// Tokens added and removed by the listed commits themselves, each against its first parent,
+/// so changes by other authors in between don't count
+fn print_author_impact(author: &str, commits: &[(&str, i64, &str)]) -> Result<()> {
+    let (mut added, mut removed) = (0, 0);
+    for (hash, ..) in commits {
+        let output = Command::new("git")
+            .args(["diff-tree", "-p", "--root", "-m", "--first-parent", "--no-commit-id", hash])
+            .args(["--", "*.rs"])
+            .output()?;
+        if !output.status.success() {
+            bail!("git diff-tree failed for {hash}");
+        }
+        let (a, r) = tokens::diff_token_count(&String::from_utf8_lossy(&output.stdout))?;
+        added += a;
+        removed += r;
+    }
+    let net = added as isize - removed as isize;
+    println!();
+    println!(
+        "Commits by {author}: +{added}/-{removed} tokens (net {net:+}) across {} commit(s)",
+        commits.len()
+    );
+    Ok(())
+}
+
+/// `<full hash> <unix time> <subject>`, as printed by `git log --format='%H %ct %s'`
+fn parse_log_line(line: &str) -> Option<(&str, i64, &str)> {
+    let (hash, rest) = line.split_once(' ')?;
+    let (timestamp, message) = rest.split_once(' ').unwrap_or((rest, ""));
+    Some((hash, timestamp.parse().ok()?, message))
+}
+
+fn load_snapshots(path: &str) -> Result<Vec<CommitStats>> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("cannot read {path}"))?;
+    serde_json::from_str(&content).with_context(|| format!("invalid history export {path}"))
+}
+
+/// Union of both sets, newest first; `scanned` wins when a hash appears in both
+fn merge_snapshots(existing: Vec<CommitStats>, mut scanned: Vec<CommitStats>) -> Vec<CommitStats> {
+    let hashes: HashSet<String> = scanned.iter().map(|s| s.hash.clone()).collect();
+    scanned.extend(existing.into_iter().filter(|s| !hashes.contains(&s.hash)));
+    scanned.sort_by_key(|s| std::cmp::Reverse(s.timestamp));
+    scanned
+}
+
+/// Table of `snapshots` (newest first), printed oldest first, then the overall trend
+fn print_snapshots(snapshots: &[CommitStats]) {
     println!(
         "{:<10} {:>5} {:>8} {:>6} {:>6}  Message",
         "Commit", "Files", "Tokens", "Lines", "T/L"
@@ -48,13 +226,13 @@ pub fn run(n: usize) -> Result<()> {
     tokens::separator(75);
 
     for s in snapshots.iter().rev() {
-        let ratio = tokens::ratio(s.tokens, s.lines);
+        let ratio = tokens::ratio(s.stats.tokens, s.stats.lines);
         println!(
             "{:<10} {:>5} {:>8} {:>6} {:>5.1}  {}",
-            s.hash,
-            s.files,
-            s.tokens,
-            s.lines,
+            short_hash(&s.hash),
+            s.stats.files,
+            s.stats.tokens,
+            s.stats.lines,
             ratio,
             truncate(&s.message, 30)
         );
@@ -63,20 +241,300 @@ pub fn run(n: usize) -> Result<()> {
     if snapshots.len() >= 2 {
         let newest = &snapshots[0];
         let oldest = snapshots.last().unwrap();
-        let delta = newest.tokens as isize - oldest.tokens as isize;
+        let change = tokens::diff_rev_stats(oldest.stats, newest.stats);
+        let delta = change.token_delta;
         let sign = if delta >= 0 { "+" } else { "" };
 
         println!();
         println!(
             "Trend: {sign}{delta} tokens ({sign}{:.1}%) over {} commits",
-            tokens::pct_delta(delta, oldest.tokens),
+            change.token_pct_change(),
             snapshots.len()
         );
     }
+}
+
+fn print_graph(snapshots: &[CommitStats], opts: &GraphOptions) {
+    if !opts.graph {
+        return;
+    }
+    let width = opts.width.unwrap_or_else(|| {
+        terminal_size::terminal_size().map_or(80, |(terminal_size::Width(w), _)| usize::from(w))
+    });
+    println!();
+    print!("{}", render_graph(snapshots, width.saturating_sub(2)));
+}
+
+/// Chart of `snapshots` (newest first) drawn oldest to newest across `width` columns.
+/// Each commit is plotted as its grade letter, with `*` above commits that grew by `SPIKE_PCT` or more.
+fn render_graph(snapshots: &[CommitStats], width: usize) -> String {
+    let points: Vec<&CommitStats> = snapshots.iter().rev().collect();
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return String::new();
+    };
+    let min = points.iter().map(|s| s.stats.tokens).min().unwrap_or(0);
+    let max = points.iter().map(|s| s.stats.tokens).max().unwrap_or(0);
+    let cols = width.saturating_sub(Y_LABEL_WIDTH + 2).max(10);
+
+    let x = |i: usize| if points.len() > 1 { i * (cols - 1) / (points.len() - 1) } else { 0 };
+    let y = |tokens: f64| {
+        if max == min {
+            GRAPH_HEIGHT / 2
+        } else {
+            ((tokens - min as f64) * (GRAPH_HEIGHT - 1) as f64 / (max - min) as f64).round()
+                as usize
+        }
+    };
+
+    // One spare row on top so spike markers above the highest point still fit
+    let mut grid = vec![vec![' '; cols]; GRAPH_HEIGHT + 1];
+    for (i, pair) in points.windows(2).enumerate() {
+        let (x0, x1) = (x(i), x(i + 1));
+        let (t0, t1) = (pair[0].stats.tokens as f64, pair[1].stats.tokens as f64);
+        let line =
+            (x0 + 1..x1).map(|col| (col, t0 + (t1 - t0) * (col - x0) as f64 / (x1 - x0) as f64));
+        for (col, t) in line {
+            grid[y(t)][col] = '.';
+        }
+    }
+    for (i, s) in points.iter().enumerate() {
+        let row = y(s.stats.tokens as f64);
+        let (_, _, grade) = tokens::efficiency_grade(tokens::ratio(s.stats.tokens, s.stats.lines));
+        grid[row][x(i)] = grade.chars().next().unwrap_or('?');
+        let spike = i > 0 && {
+            let change = tokens::diff_rev_stats(points[i - 1].stats, s.stats);
+            change.token_delta > 0 && change.token_pct_change() >= SPIKE_PCT
+        };
+        if spike {
+            grid[row + 1][x(i)] = '*';
+        }
+    }
+
+    let mut out = format!(
+        "Total tokens over {} commits ({} to {}):\n",
+        points.len(),
+        short_hash(&first.hash),
+        short_hash(&last.hash)
+    );
+    for (row, line) in grid.iter().enumerate().rev() {
+        let label = match row {
+            r if r == GRAPH_HEIGHT - 1 => max.to_string(),
+            0 => min.to_string(),
+            _ => String::new(),
+        };
+        let line: String = line.iter().collect();
+        out.push_str(&format!("{label:>w$} |{}\n", line.trim_end(), w = Y_LABEL_WIDTH - 1));
+    }
+    out.push_str(&format!("{:>w$} +{}\n", "", "-".repeat(cols), w = Y_LABEL_WIDTH - 1));
+
+    let start = super::batch::iso_date(first.timestamp.max(0) as u64);
+    let end = super::batch::iso_date(last.timestamp.max(0) as u64);
+    let gap = cols.saturating_sub(start.len() + end.len()).max(1);
+    out.push_str(&format!("{:w$}{start}{:gap$}{end}\n", "", "", w = Y_LABEL_WIDTH + 1));
+    out.push_str(&format!(
+        "Points show the grade (A-D); * = +{SPIKE_PCT:.0}% or more tokens than the previous commit\n"
+    ));
+    out
+}
+
+/// Token delta between consecutive semver tags from `since` to `until` (or HEAD)
+fn print_version_deltas(since: &str, until: Option<&str>) -> Result<()> {
+    let output = Command::new("git").args(["tag", "--sort=v:refname"]).output()?;
+    if !output.status.success() {
+        bail!("git tag failed — are you in a git repository?");
+    }
+    let tag_list = String::from_utf8_lossy(&output.stdout);
+    let tags: Vec<&str> = tag_list.lines().filter(|t| is_semver(t)).collect();
+
+    let Some(start) = tags.iter().position(|t| *t == since) else {
+        println!();
+        println!("Note: per-version summary skipped — {since} is not a semver tag");
+        return Ok(());
+    };
+    let end = match until {
+        Some(u) => match tags.iter().position(|t| *t == u) {
+            Some(end) if end > start => end,
+            Some(_) => {
+                println!();
+                println!("Note: per-version summary skipped — {u} is not after {since}");
+                return Ok(());
+            }
+            None => {
+                println!();
+                println!("Note: per-version summary skipped — {u} is not a semver tag");
+                return Ok(());
+            }
+        },
+        None => tags.len() - 1,
+    };
+
+    let mut points = tags[start..=end].to_vec();
+    if until.is_none() {
+        points.push("HEAD");
+    }
+    let counts =
+        points.iter().map(|rev| tokens::count_rev_tokens(rev)).collect::<Result<Vec<_>>>()?;
+
+    println!();
+    println!("Per-version token delta:");
+    for (pair, count) in points.windows(2).zip(counts.windows(2)) {
+        let change = tokens::diff_rev_stats(count[0], count[1]);
+        let delta = change.token_delta;
+        let sign = if delta >= 0 { "+" } else { "" };
+        println!(
+            "  {}→{}: {sign}{delta} tokens ({sign}{:.1}%)",
+            pair[0],
+            pair[1],
+            change.token_pct_change()
+        );
+    }
 
     Ok(())
 }
 
+/// `v1.2.3`, `1.2.3` and `v1.2.3-rc.1` all count; anything else is ignored
+fn is_semver(tag: &str) -> bool {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3 && parts.iter().all(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Token count of one file at each commit, oldest first, with a trend marker per step
+fn file_history(commits: &[(&str, i64, &str)], file: &str) -> Result<()> {
+    println!("Token history for {file} over {} commits\n", commits.len());
+    println!(
+        "{:<10} {:>8} {:>6} {:>6}  {:<10} Message",
+        "Commit", "Tokens", "Lines", "T/L", "Trend"
+    );
+    tokens::separator(75);
+
+    let mut prev: Option<Option<usize>> = None;
+    for (hash, _, msg) in commits.iter().rev() {
+        let content = tokens::git_show_file(hash, file).ok();
+        let count = content.as_deref().map(tokens::count_tokens).transpose()?;
+        let mark = prev.map_or_else(String::new, |p| trend(p, count));
+
+        match (count, &content) {
+            (Some(t), Some(c)) => {
+                let lines = c.lines().count();
+                let ratio = tokens::ratio(t, lines);
+                println!(
+                    "{:<10} {t:>8} {lines:>6} {ratio:>5.1}  {mark:<10} {}",
+                    short_hash(hash),
+                    truncate(msg, 30)
+                );
+            }
+            _ => println!(
+                "{:<10} {:>8} {:>6} {:>6}  {mark:<10} {}",
+                short_hash(hash),
+                "-",
+                "-",
+                "-",
+                truncate(msg, 30)
+            ),
+        }
+        prev = Some(count);
+    }
+
+    Ok(())
+}
+
+/// `↑ +12`, `↓ -5`, `=`, or added/deleted when the file appears or disappears
+fn trend(prev: Option<usize>, cur: Option<usize>) -> String {
+    match (prev, cur) {
+        (None, Some(_)) => "added".to_string(),
+        (Some(_), None) => "deleted".to_string(),
+        (None, None) => String::new(),
+        (Some(a), Some(b)) if b > a => format!("↑ +{}", b - a),
+        (Some(a), Some(b)) if b < a => format!("↓ -{}", a - b),
+        _ => "=".to_string(),
+    }
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(7)]
+}
+
 fn truncate(s: &str, max: usize) -> String {
     if s.len() <= max { s.to_string() } else { format!("{}...", &s[..max - 3]) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trend_markers() {
+        assert_eq!(trend(Some(100), Some(120)), "↑ +20");
+        assert_eq!(trend(Some(120), Some(100)), "↓ -20");
+        assert_eq!(trend(Some(100), Some(100)), "=");
+        assert_eq!(trend(None, Some(50)), "added");
+        assert_eq!(trend(Some(50), None), "deleted");
+        assert_eq!(trend(None, None), "");
+    }
+
+    #[test]
+    fn test_merge_snapshots() {
+        let commit = |hash: &str, timestamp: i64, tokens: usize| CommitStats {
+            hash: hash.to_string(),
+            message: String::new(),
+            timestamp,
+            stats: tokens::RevStats { files: 1, tokens, lines: 10 },
+        };
+        let existing = vec![commit("b", 20, 100), commit("a", 10, 90)];
+        let scanned = vec![commit("c", 30, 120), commit("b", 20, 105)];
+        let merged = merge_snapshots(existing, scanned);
+        let order: Vec<&str> = merged.iter().map(|s| s.hash.as_str()).collect();
+        assert_eq!(order, ["c", "b", "a"]);
+        assert_eq!(merged[1].stats.tokens, 105);
+        assert_eq!(
+            parse_log_line("abc123 1700000000 Fix it"),
+            Some(("abc123", 1700000000, "Fix it"))
+        );
+    }
+
+    #[test]
+    fn test_render_graph() {
+        let commit = |hash: &str, timestamp: i64, tokens: usize| CommitStats {
+            hash: hash.to_string(),
+            message: String::new(),
+            timestamp,
+            stats: tokens::RevStats { files: 1, tokens, lines: 100 },
+        };
+        // Newest first, as scanned: 800 → 810 → 1100 tokens
+        let snapshots = vec![
+            commit("ccccccc3", 1_792_108_800, 1100),
+            commit("bbbbbbb2", 1_792_022_400, 810),
+            commit("aaaaaaa1", 1_791_936_000, 800),
+        ];
+        let chart = render_graph(&snapshots, 31);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert_eq!(lines[0], "Total tokens over 3 commits (aaaaaaa to ccccccc):");
+        // Spike marker above the newest point, then the point itself with its grade
+        assert_eq!(lines[1], "         |                   *");
+        assert_eq!(lines[2], "    1100 |                   C");
+        assert_eq!(lines[3], "         |                  .");
+        assert_eq!(lines[13], "     800 |B........B");
+        assert_eq!(lines[14], "         +--------------------");
+        assert_eq!(lines[15], "          2026-10-14 2026-10-16");
+    }
+
+    #[test]
+    fn test_commit_filter_git_args() {
+        let filter = CommitFilter { author: Some("me@example.com".into()), since: None };
+        assert_eq!(filter.git_args(), ["--author=me@example.com"]);
+        let filter = CommitFilter { author: Some("me".into()), since: Some("2 weeks ago".into()) };
+        assert_eq!(filter.git_args(), ["--author=me", "--since=2 weeks ago"]);
+    }
+
+    #[test]
+    fn test_is_semver() {
+        assert!(is_semver("v0.1.0"));
+        assert!(is_semver("1.20.3"));
+        assert!(is_semver("v2.0.0-rc.1"));
+        assert!(!is_semver("v1.2"));
+        assert!(!is_semver("release-1.0.0"));
+        assert!(!is_semver("v1.x.0"));
+    }
+}
@@ -3,10 +3,55 @@ use std::path::Path;
 use std::process::Command;
 
 use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
 
 use crate::templates;
 
-pub fn run(name: &str) -> Result<()> {
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Template {
+    /// Binary crate with a plain main.rs
+    Minimal,
+    /// Library crate (cargo init --lib)
+    Library,
+    /// Binary crate with clap + anyhow
+    Cli,
+    /// Binary crate with tokio + anyhow
+    Async,
+}
+
+impl Template {
+    /// Source file to write, relative to src/
+    fn source(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Minimal => ("main.rs", templates::MAIN_RS),
+            Self::Library => ("lib.rs", templates::LIB_RS),
+            Self::Cli => ("main.rs", templates::CLI_MAIN_RS),
+            Self::Async => ("main.rs", templates::ASYNC_MAIN_RS),
+        }
+    }
+
+    fn dependencies(self) -> &'static str {
+        match self {
+            Self::Minimal | Self::Library => "",
+            Self::Cli => templates::CLI_DEPS,
+            Self::Async => templates::ASYNC_DEPS,
+        }
+    }
+
+    /// `base` is `CLAUDE_MD` or `CURSOR_RULES`; the per-template section is appended to either
+    fn instructions(self, base: &str) -> String {
+        let extra = match self {
+            Self::Minimal => "",
+            Self::Library => templates::CLAUDE_MD_LIBRARY,
+            Self::Cli => templates::CLAUDE_MD_CLI,
+            Self::Async => templates::CLAUDE_MD_ASYNC,
+        };
+        format!("{base}{extra}")
+    }
+}
+
+/// `cursor` writes `.cursorrules` instead of `CLAUDE.md`; `no_claude` skips `CLAUDE.md` alone.
+pub fn run(name: &str, template: Template, no_claude: bool, cursor: bool) -> Result<()> {
     let path = Path::new(name);
 
     if path.exists() {
@@ -15,25 +60,39 @@ pub fn run(name: &str) -> Result<()> {
 
     println!("Creating project '{name}'...");
 
-    let status =
-        Command::new("cargo").args(["init", name]).status().context("failed to run cargo init")?;
+    let mut args = vec!["init", name];
+    if matches!(template, Template::Library) {
+        args.push("--lib");
+    }
+    let status = Command::new("cargo").args(&args).status().context("failed to run cargo init")?;
 
     if !status.success() {
         bail!("cargo init failed");
     }
 
-    // Append lints to Cargo.toml
+    // Add template dependencies and lints to Cargo.toml
     let cargo_toml = path.join("Cargo.toml");
     let mut content = fs::read_to_string(&cargo_toml)?;
+    let deps = template.dependencies();
+    if !deps.is_empty() {
+        content = content.replacen("[dependencies]\n", &format!("[dependencies]\n{deps}"), 1);
+    }
     content.push_str(templates::CARGO_LINTS);
     fs::write(&cargo_toml, content)?;
 
+    let (source_name, source) = template.source();
+    fs::write(path.join("src").join(source_name), source)?;
+
     // Write config files
     fs::write(path.join("rustfmt.toml"), templates::RUSTFMT_TOML)?;
     fs::write(path.join("clippy.toml"), templates::CLIPPY_TOML)?;
     fs::write(path.join("rust-toolchain.toml"), templates::RUST_TOOLCHAIN_TOML)?;
     fs::write(path.join(".gitignore"), templates::GITIGNORE)?;
-    fs::write(path.join("CLAUDE.md"), templates::CLAUDE_MD)?;
+    if cursor {
+        fs::write(path.join(".cursorrules"), template.instructions(templates::CURSOR_RULES))?;
+    } else if !no_claude {
+        fs::write(path.join("CLAUDE.md"), template.instructions(templates::CLAUDE_MD))?;
+    }
 
     println!("Project '{name}' created with token-efficient config.");
     println!();
@@ -42,3 +101,112 @@ pub fn run(name: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Create a workspace with one crate per `members` entry under `crates/`, named
+/// `<name>-<member>`. Members named `cli` (or ending in `-cli`) are binaries that depend on
+/// every library member; the rest are libraries. Lints are declared once at the root.
+pub fn run_workspace(name: &str, members: &[String], no_claude: bool, cursor: bool) -> Result<()> {
+    let path = Path::new(name);
+    if path.exists() {
+        bail!("directory '{name}' already exists");
+    }
+    if members.is_empty() {
+        bail!("--members needs at least one crate name");
+    }
+
+    println!("Creating workspace '{name}'...");
+
+    fs::create_dir_all(path.join("crates"))?;
+    let lints = templates::CARGO_LINTS.replacen("[lints.clippy]", "[workspace.lints.clippy]", 1);
+    fs::write(path.join("Cargo.toml"), format!("{}{lints}", templates::WORKSPACE_TOML))?;
+
+    let (bins, libs): (Vec<&String>, Vec<&String>) = members.iter().partition(|m| is_binary(m));
+    for member in &libs {
+        new_member(path, name, member, Template::Library, "")?;
+    }
+    let lib_deps: String =
+        libs.iter().map(|lib| format!("{name}-{lib} = {{ path = \"../{lib}\" }}\n")).collect();
+    for member in &bins {
+        new_member(path, name, member, Template::Cli, &lib_deps)?;
+    }
+
+    fs::write(path.join("rustfmt.toml"), templates::RUSTFMT_TOML)?;
+    fs::write(path.join("clippy.toml"), templates::CLIPPY_TOML)?;
+    fs::write(path.join("rust-toolchain.toml"), templates::RUST_TOOLCHAIN_TOML)?;
+    fs::write(path.join(".gitignore"), templates::GITIGNORE)?;
+
+    let layout: String = libs
+        .iter()
+        .map(|m| format!("- `crates/{m}` — library `{name}-{m}`\n"))
+        .chain(bins.iter().map(|m| format!("- `crates/{m}` — binary `{name}-{m}`\n")))
+        .collect();
+    let instructions = |base: &str| format!("{base}{}{layout}", templates::CLAUDE_MD_WORKSPACE);
+    if cursor {
+        fs::write(path.join(".cursorrules"), instructions(templates::CURSOR_RULES))?;
+    } else if !no_claude {
+        fs::write(path.join("CLAUDE.md"), instructions(templates::CLAUDE_MD))?;
+    }
+
+    if !Command::new("git").args(["init", "-q", name]).status().is_ok_and(|s| s.success()) {
+        println!("Note: git init failed — the workspace is not under version control.");
+    }
+
+    println!(
+        "Workspace '{name}' created with {} crate(s) and token-efficient config.",
+        members.len()
+    );
+    println!();
+    println!("  cd {name}");
+    println!("  cargo syntax check");
+
+    Ok(())
+}
+
+/// `cargo new` one member in `crates/<member>`, then add `deps` and the workspace lints
+fn new_member(root: &Path, name: &str, member: &str, template: Template, deps: &str) -> Result<()> {
+    let dir = root.join("crates").join(member);
+    let kind = if matches!(template, Template::Library) { "--lib" } else { "--bin" };
+    let status = Command::new("cargo")
+        .args(["new", "-q", "--vcs", "none", kind, "--name", &format!("{name}-{member}")])
+        .arg(&dir)
+        .status()
+        .context("failed to run cargo new")?;
+    if !status.success() {
+        bail!("cargo new failed for crates/{member}");
+    }
+
+    let cargo_toml = dir.join("Cargo.toml");
+    let mut content = fs::read_to_string(&cargo_toml)?;
+    let deps = format!("{}{deps}", template.dependencies());
+    content = content.replacen("[dependencies]\n", &format!("[dependencies]\n{deps}"), 1);
+    // Recent cargo versions add the `[lints]` table themselves when the workspace has lints
+    if !content.contains("[lints]") {
+        content.push_str(templates::MEMBER_LINTS);
+    }
+    fs::write(&cargo_toml, content)?;
+
+    let (source_name, source) = template.source();
+    fs::write(dir.join("src").join(source_name), source)?;
+    println!(
+        "  crates/{member} ({})",
+        if matches!(template, Template::Library) { "lib" } else { "bin" }
+    );
+    Ok(())
+}
+
+fn is_binary(member: &str) -> bool {
+    member == "cli" || member.ends_with("-cli")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_binary() {
+        assert!(is_binary("cli"));
+        assert!(is_binary("admin-cli"));
+        assert!(!is_binary("core"));
+        assert!(!is_binary("client"));
+    }
+}
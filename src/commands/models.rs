@@ -1,9 +1,76 @@
-use anyhow::Result;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::openrouter;
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use serde::{Deserialize, Serialize};
 
-pub fn run(search: Option<&str>) -> Result<()> {
-    println!("Fetching models from OpenRouter...");
+use crate::{openrouter, tokens};
+
+const CACHE_DIR: &str = ".syntax_cache";
+
+/// Release manifest of the stable channel; `[pkg.rust] version` is the current stable release
+const STABLE_MANIFEST_URL: &str = "https://static.rust-lang.org/dist/channel-rust-stable.toml";
+
+const TOOLCHAIN_FILE: &str = "rust-toolchain.toml";
+
+/// Minor releases a pinned toolchain may fall behind stable before `--update-toolchain` warns
+const MAX_RELEASES_BEHIND: u32 = 3;
+
+/// (label, description, candidate IDs in order of preference)
+const RECOMMENDED: &[(&str, &str, &[&str])] = &[
+    (
+        "Free",
+        "free, good for trying out",
+        &["qwen/qwen3-coder:free", "deepseek/deepseek-chat:free"],
+    ),
+    (
+        "Cheap",
+        "best value for code tasks",
+        &["deepseek/deepseek-chat", "deepseek/deepseek-chat-v3-0324"],
+    ),
+    (
+        "Best",
+        "highest quality rewrites",
+        &["anthropic/claude-sonnet-4", "anthropic/claude-sonnet-4.5"],
+    ),
+    ("Large", "1M+ context for huge files", &["google/gemini-2.5-flash", "google/gemini-2.5-pro"]),
+];
+
+/// Price limits applied to the model list
+#[derive(Args, Clone, Copy)]
+pub struct PriceFilter {
+    /// Only show models with free input
+    #[arg(long, conflicts_with = "max_cost")]
+    pub free: bool,
+    /// Only show models whose input costs at most this many cents per million tokens
+    #[arg(long, value_name = "CENTS_PER_M")]
+    pub max_cost: Option<f64>,
+}
+
+impl PriceFilter {
+    fn matches(self, model: &openrouter::Model) -> bool {
+        if self.free && !model.is_free() {
+            return false;
+        }
+        self.max_cost.is_none_or(|max| model.prompt_price().is_some_and(|p| p * 1e8 <= max))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BenchmarkEntry {
+    model: String,
+    elapsed_secs: f64,
+    tokens_before: usize,
+    tokens_after: usize,
+    saved: isize,
+    saved_per_sec: f64,
+    cost_usd: Option<f64>,
+    error: Option<String>,
+}
+
+/// With `cost_estimate`, show what processing that many input tokens costs per model instead
+pub fn run(search: Option<&str>, cost_estimate: Option<usize>, price: PriceFilter) -> Result<()> {
+    println!("Loading models from OpenRouter...");
     println!();
 
     let all_models = openrouter::list_models()?;
@@ -19,23 +86,30 @@ pub fn run(search: Option<&str>) -> Result<()> {
             code_keywords.iter().any(|k| id.contains(k))
         });
     }
+    models.retain(|m| price.matches(m));
 
     models.sort_by(|a, b| {
-        let cost_a = prompt_cost(a).unwrap_or(f64::MAX);
-        let cost_b = prompt_cost(b).unwrap_or(f64::MAX);
+        let cost_a = a.prompt_price().unwrap_or(f64::MAX);
+        let cost_b = b.prompt_price().unwrap_or(f64::MAX);
         cost_a.partial_cmp(&cost_b).unwrap_or(std::cmp::Ordering::Equal)
     });
 
-    println!("{:<50} {:>10} {:>12} {:>12}", "Model ID", "Context", "Input/M", "Output/M");
-    crate::tokens::separator(86);
+    if let Some(input) = cost_estimate {
+        print_cost_estimate(&models, input);
+        return Ok(());
+    }
+
+    println!("  {:<50} {:>10} {:>12} {:>12}", "Model ID", "Context", "Input/M", "Output/M");
+    crate::tokens::separator(88);
 
     for model in &models {
         println!(
-            "{:<50} {:>10} {:>12} {:>12}",
+            "{} {:<50} {:>10} {:>12} {:>12}",
+            if model.is_free() { "✓" } else { " " },
             model.id,
-            format_context(model),
-            format_input_cost(model),
-            format_output_cost(model),
+            model.context_label(),
+            model.input_cost_label(),
+            model.output_cost_label(),
         );
     }
 
@@ -49,65 +123,410 @@ pub fn run(search: Option<&str>) -> Result<()> {
     println!();
     println!("Usage: cargo syntax rewrite src/main.rs --model <MODEL_ID>");
     println!("   or: cargo syntax review 5 --model <MODEL_ID>");
+    println!("Details: cargo syntax models --details <MODEL_ID>");
 
     Ok(())
 }
 
-fn print_recommendations(models: &[openrouter::Model]) {
-    let picks: &[(&str, &str, &[&str])] = &[
-        (
-            "Free",
-            "free, good for trying out",
-            &["qwen/qwen3-coder:free", "deepseek/deepseek-chat:free"],
-        ),
-        (
-            "Cheap",
-            "best value for code tasks",
-            &["deepseek/deepseek-chat", "deepseek/deepseek-chat-v3-0324"],
-        ),
-        (
-            "Best",
-            "highest quality rewrites",
-            &["anthropic/claude-sonnet-4", "anthropic/claude-sonnet-4.5"],
-        ),
-        (
-            "Large",
-            "1M+ context for huge files",
-            &["google/gemini-2.5-flash", "google/gemini-2.5-pro"],
-        ),
-    ];
+/// Everything OpenRouter reports about one model
+pub fn details(id: &str) -> Result<()> {
+    let models = openrouter::list_models()?;
+    let Some(model) = models.iter().find(|m| m.id.eq_ignore_ascii_case(id)) else {
+        let q = id.to_lowercase();
+        let close: Vec<&str> =
+            models.iter().filter(|m| m.id.contains(&q)).map(|m| m.id.as_str()).take(5).collect();
+        if close.is_empty() {
+            bail!("Model not found: {id}");
+        }
+        bail!("Model not found: {id} — did you mean {}?", close.join(", "));
+    };
+
+    let per_token = |p: Option<f64>| p.map_or_else(|| "—".to_string(), |v| format!("${v}"));
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+    println!("{}", model.name);
+    tokens::separator(70);
+    println!("ID:          {}", model.id);
+    match model.top_provider.as_ref().and_then(|p| p.context_length) {
+        Some(top) if Some(top) != model.context_length => {
+            println!("Context:     {} tokens ({top} at the top provider)", model.context_label());
+        }
+        _ => println!("Context:     {} tokens", model.context_label()),
+    }
+    if let Some(max) = model.top_provider.as_ref().and_then(|p| p.max_completion_tokens) {
+        println!("Max output:  {max} tokens");
+    }
+    println!(
+        "Input:       {} per token, {} per million",
+        per_token(model.prompt_price()),
+        model.input_cost_label()
+    );
+    println!(
+        "Output:      {} per token, {} per million",
+        per_token(model.completion_price()),
+        model.output_cost_label()
+    );
+    println!("JSON schema: {}", yes_no(model.supports_json_schema()));
+    // OpenRouter streams every model; the parameter list doesn't mention it
+    println!("Streaming:   yes");
+    println!("Tools:       {}", yes_no(model.supports("tools")));
+    if let Some(moderated) = model.top_provider.as_ref().and_then(|p| p.is_moderated) {
+        println!("Moderated:   {}", yes_no(moderated));
+    }
+    if !model.supported_parameters.is_empty() {
+        println!("Parameters:  {}", model.supported_parameters.join(", "));
+    }
+    println!("Page:        {}", model.url());
+
+    if let Some(desc) = model.description.as_deref().filter(|d| !d.is_empty()) {
+        println!();
+        println!("{desc}");
+    }
+
+    Ok(())
+}
+
+/// Cost of `input` tokens in plus `input / 4` out, cheapest first, with the number of rewrites
+/// whose savings (valued at the model's input price) would pay for it
+fn print_cost_estimate(models: &[openrouter::Model], input: usize) {
+    let output = input / 4;
+    let savings = benchmark_savings();
+    let mut rows: Vec<(&openrouter::Model, Option<f64>)> =
+        models.iter().map(|m| (m, rewrite_cost(m, input, output))).collect();
+    rows.sort_by(|a, b| a.1.unwrap_or(f64::MAX).total_cmp(&b.1.unwrap_or(f64::MAX)));
+
+    println!("Estimate: {input} input + {output} output tokens");
+    println!();
+    println!("{:<50} {:>12} {:>12} {:>10}", "Model ID", "Cost", "Avg saved", "Payback");
+    tokens::separator(87);
+    for (model, cost) in rows {
+        let saved = savings.model(&model.id);
+        let payback = match (cost, saved, model.prompt_price()) {
+            (Some(c), Some(s), Some(p)) if s > 0.0 && p > 0.0 => {
+                format!("{:.0}", (c / (s * p)).ceil())
+            }
+            (Some(0.0), _, _) => "free".to_string(),
+            _ => "—".to_string(),
+        };
+        println!(
+            "{:<50} {:>12} {:>12} {payback:>10}",
+            model.id,
+            cost.map_or_else(|| "—".to_string(), |c| format!("${c:.4}")),
+            saved.map_or_else(|| "—".to_string(), |s| format!("{s:.0} tok")),
+        );
+    }
 
+    println!();
+    if savings.all.is_none() {
+        println!(
+            "Payback needs savings data — run `cargo syntax models --benchmark <file>` first."
+        );
+    } else {
+        println!(
+            "Avg saved: tokens saved per rewrite in {CACHE_DIR}/benchmark_*.json (all models when one has none)"
+        );
+        println!(
+            "Payback: rewrites whose savings, valued at the model's input price, cover the cost"
+        );
+    }
+}
+
+/// Average tokens saved per successful rewrite, from earlier `--benchmark` runs
+struct Savings {
+    by_model: Vec<(String, f64)>,
+    all: Option<f64>,
+}
+
+impl Savings {
+    fn model(&self, id: &str) -> Option<f64> {
+        self.by_model.iter().find(|(m, _)| m == id).map(|(_, s)| *s).or(self.all)
+    }
+}
+
+fn benchmark_savings() -> Savings {
+    let entries: Vec<BenchmarkEntry> = std::fs::read_dir(CACHE_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("benchmark_"))
+        .filter_map(|e| std::fs::read_to_string(e.path()).ok())
+        .filter_map(|c| serde_json::from_str::<Vec<BenchmarkEntry>>(&c).ok())
+        .flatten()
+        .filter(|e| e.error.is_none())
+        .collect();
+    average_savings(&entries)
+}
+
+fn average_savings(entries: &[BenchmarkEntry]) -> Savings {
+    let mean = |saved: &[isize]| {
+        (!saved.is_empty()).then(|| saved.iter().sum::<isize>() as f64 / saved.len() as f64)
+    };
+    let mut by_model: Vec<(String, Vec<isize>)> = Vec::new();
+    for e in entries {
+        match by_model.iter_mut().find(|(m, _)| *m == e.model) {
+            Some((_, saved)) => saved.push(e.saved),
+            None => by_model.push((e.model.clone(), vec![e.saved])),
+        }
+    }
+    let all: Vec<isize> = entries.iter().map(|e| e.saved).collect();
+    Savings {
+        by_model: by_model.into_iter().filter_map(|(m, s)| Some((m, mean(&s)?))).collect(),
+        all: mean(&all),
+    }
+}
+
+fn print_recommendations(models: &[openrouter::Model]) {
     println!();
     println!("Recommended for cargo-syntax:");
 
-    for (label, desc, candidates) in picks {
-        let found = candidates.iter().find_map(|id| models.iter().find(|m| m.id == *id));
-        let Some(model) = found else { continue };
+    for (label, desc, candidates) in RECOMMENDED {
+        let Some(model) = find_candidate(models, candidates) else { continue };
 
         let ctx = model.context_length.map(|c| format!("{c}")).unwrap_or_default();
-        let cost = format_input_cost(model);
+        let cost = model.input_cost_label();
         println!("  {label:<6} {:<40} — {cost}, {ctx} ctx, {desc}", model.id);
     }
 }
 
-fn prompt_cost(model: &openrouter::Model) -> Option<f64> {
-    model.pricing.as_ref()?.prompt.as_ref()?.parse().ok()
+fn find_candidate<'a>(
+    models: &'a [openrouter::Model],
+    candidates: &[&str],
+) -> Option<&'a openrouter::Model> {
+    candidates.iter().find_map(|id| models.iter().find(|m| m.id == *id))
 }
 
-fn format_context(model: &openrouter::Model) -> String {
-    model.context_length.map_or_else(|| "—".to_string(), |c| c.to_string())
+/// Rewrite `file` with each of the first `count` recommended models and compare the results
+pub fn benchmark(file: &str, count: usize) -> Result<()> {
+    tokens::read_rs_file(file)?;
+
+    println!("Loading models from OpenRouter...");
+    let all_models = openrouter::list_models()?;
+    let models: Vec<&openrouter::Model> = RECOMMENDED
+        .iter()
+        .filter_map(|(_, _, candidates)| find_candidate(&all_models, candidates))
+        .take(count)
+        .collect();
+
+    if models.is_empty() {
+        bail!("None of the recommended models are currently available");
+    }
+
+    println!("Benchmarking {} model(s) on {file}...", models.len());
+    println!();
+
+    let mut entries = Vec::new();
+    for model in &models {
+        eprint!("  {}... ", model.id);
+        let start = Instant::now();
+        let result = super::rewrite::rewrite_file(file, &model.id, None);
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let entry = match result {
+            Ok(r) => {
+                eprintln!("done ({elapsed:.1}s)");
+                let saved = r.saved();
+                BenchmarkEntry {
+                    model: model.id.clone(),
+                    elapsed_secs: elapsed,
+                    tokens_before: r.tokens_before,
+                    tokens_after: r.tokens_after,
+                    saved,
+                    saved_per_sec: if elapsed > 0.0 { saved as f64 / elapsed } else { 0.0 },
+                    cost_usd: rewrite_cost(model, r.tokens_before, r.tokens_after),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                eprintln!("failed");
+                BenchmarkEntry {
+                    model: model.id.clone(),
+                    elapsed_secs: elapsed,
+                    tokens_before: 0,
+                    tokens_after: 0,
+                    saved: 0,
+                    saved_per_sec: 0.0,
+                    cost_usd: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        entries.push(entry);
+    }
+
+    println!();
+    println!(
+        "{:<40} {:>8} {:>14} {:>7} {:>8} {:>10}",
+        "Model", "Time", "Tokens", "Saved", "Saved/s", "Cost"
+    );
+    tokens::separator(92);
+    for e in &entries {
+        if let Some(err) = &e.error {
+            println!("{:<40} failed: {err}", e.model);
+            continue;
+        }
+        let cost = e.cost_usd.map_or_else(|| "—".to_string(), |c| format!("${c:.5}"));
+        println!(
+            "{:<40} {:>7.1}s {:>14} {:>+7} {:>8.1} {:>10}",
+            e.model,
+            e.elapsed_secs,
+            format!("{} → {}", e.tokens_before, e.tokens_after),
+            e.saved,
+            e.saved_per_sec,
+            cost
+        );
+    }
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let out = format!("{CACHE_DIR}/benchmark_{secs}.json");
+    std::fs::create_dir_all(CACHE_DIR)?;
+    std::fs::write(&out, serde_json::to_string_pretty(&entries)?)?;
+    println!();
+    println!("Results saved to {out}");
+
+    Ok(())
 }
 
-fn format_cost(price: Option<&String>) -> String {
-    price
-        .and_then(|s| s.parse::<f64>().ok())
-        .map_or_else(|| "—".to_string(), |v| format!("${:.4}", v * 1e6))
+/// Pin `rust-toolchain.toml` to the latest stable release, keeping the pin's precision (`1.93` or `1.93.1`)
+pub fn update_toolchain() -> Result<()> {
+    let content = std::fs::read_to_string(TOOLCHAIN_FILE).with_context(|| {
+        format!("cannot read {TOOLCHAIN_FILE} — run `cargo syntax apply` first")
+    })?;
+    let Some(channel) = toolchain_channel(&content) else {
+        bail!("No `channel = \"...\"` line in {TOOLCHAIN_FILE}");
+    };
+    let Some(current) = parse_version(channel) else {
+        println!(
+            "{TOOLCHAIN_FILE} follows the `{channel}` channel, not a pinned version — nothing to update"
+        );
+        return Ok(());
+    };
+
+    println!("Checking the latest stable Rust release...");
+    let latest = fetch_stable_version()?;
+    let latest_str = format!("{}.{}.{}", latest.0, latest.1, latest.2);
+
+    if latest.1 > current.1 + MAX_RELEASES_BEHIND {
+        println!(
+            "Warning: {channel} is {} releases behind stable {latest_str}",
+            latest.1 - current.1
+        );
+    }
+    let pinned = if channel.matches('.').count() == 1 {
+        format!("{}.{}", latest.0, latest.1)
+    } else {
+        latest_str.clone()
+    };
+    if current >= latest || pinned == channel {
+        println!("{TOOLCHAIN_FILE} is up to date ({channel}, latest stable {latest_str})");
+        return Ok(());
+    }
+    std::fs::write(
+        TOOLCHAIN_FILE,
+        content.replacen(&format!("\"{channel}\""), &format!("\"{pinned}\""), 1),
+    )?;
+    println!("Updated {TOOLCHAIN_FILE}: {channel} → {pinned}");
+    Ok(())
+}
+
+fn fetch_stable_version() -> Result<(u32, u32, u32)> {
+    let agent = ureq::Agent::new_with_config(
+        ureq::config::Config::builder().http_status_as_error(false).build(),
+    );
+    let mut response = agent.get(STABLE_MANIFEST_URL).call()?;
+    let status = response.status();
+    if status != 200 {
+        bail!("static.rust-lang.org returned HTTP {status}");
+    }
+    let manifest = response.body_mut().with_config().limit(16 * 1024 * 1024).read_to_string()?;
+    stable_version(&manifest).context("no [pkg.rust] version in the stable channel manifest")
+}
+
+/// `version = "1.95.0 (29483883e 2025-08-04)"` under `[pkg.rust]`
+fn stable_version(manifest: &str) -> Option<(u32, u32, u32)> {
+    let (_, rest) = manifest.split_once("[pkg.rust]\n")?;
+    let line = rest.lines().find(|l| l.starts_with("version = "))?;
+    parse_version(line.split('"').nth(1)?.split(' ').next()?)
+}
+
+/// The `channel` value of a `[toolchain]` table
+fn toolchain_channel(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find(|l| l.trim_start().starts_with("channel"))
+        .and_then(|l| l.split('"').nth(1))
+}
+
+/// `1.93` or `1.93.1`; named channels such as `stable` or `nightly-2025-01-01` give `None`
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+    parts.next().is_none().then_some((major, minor, patch))
 }
 
-fn format_input_cost(model: &openrouter::Model) -> String {
-    format_cost(model.pricing.as_ref().and_then(|p| p.prompt.as_ref()))
+/// Cost of one rewrite: the file goes in as the prompt, the rewrite comes back as completion
+fn rewrite_cost(model: &openrouter::Model, input: usize, output: usize) -> Option<f64> {
+    Some(model.prompt_price()? * input as f64 + model.completion_price()? * output as f64)
 }
 
-fn format_output_cost(model: &openrouter::Model) -> String {
-    format_cost(model.pricing.as_ref().and_then(|p| p.completion.as_ref()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(model: &str, saved: isize) -> BenchmarkEntry {
+        BenchmarkEntry {
+            model: model.to_string(),
+            elapsed_secs: 1.0,
+            tokens_before: 100,
+            tokens_after: (100 - saved) as usize,
+            saved,
+            saved_per_sec: saved as f64,
+            cost_usd: None,
+            error: None,
+        }
+    }
+
+    fn model(id: &str, prompt: &str) -> openrouter::Model {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": id,
+            "pricing": { "prompt": prompt, "completion": "0" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_price_filter() {
+        let free = model("qwen/qwen3-coder:free", "0");
+        let cheap = model("deepseek/deepseek-chat", "0.00000032");
+        let pricey = model("anthropic/claude-sonnet-4", "0.000003");
+
+        let only_free = PriceFilter { free: true, max_cost: None };
+        assert!(only_free.matches(&free) && !only_free.matches(&cheap));
+
+        let budget = PriceFilter { free: false, max_cost: Some(50.0) };
+        assert!(budget.matches(&free) && budget.matches(&cheap) && !budget.matches(&pricey));
+    }
+
+    #[test]
+    fn test_toolchain_versions() {
+        let manifest = "manifest-version = \"2\"\n[pkg.cargo]\nversion = \"0.96.0 (abc 2026-01-01)\"\n\
+                        [pkg.rust]\nversion = \"1.95.0 (29483883e 2026-01-15)\"\n";
+        assert_eq!(stable_version(manifest), Some((1, 95, 0)));
+        assert_eq!(toolchain_channel(crate::templates::RUST_TOOLCHAIN_TOML), Some("1.93"));
+        assert_eq!(parse_version("1.93"), Some((1, 93, 0)));
+        assert_eq!(parse_version("1.93.1"), Some((1, 93, 1)));
+        assert_eq!(parse_version("stable"), None);
+        assert_eq!(parse_version("nightly-2026-01-01"), None);
+    }
+
+    #[test]
+    fn test_average_savings_per_model_and_overall() {
+        let savings = average_savings(&[entry("a", 10), entry("a", 30), entry("b", 50)]);
+        assert_eq!(savings.model("a"), Some(20.0));
+        assert_eq!(savings.model("b"), Some(50.0));
+        assert_eq!(savings.model("c"), Some(30.0));
+        assert_eq!(average_savings(&[]).model("a"), None);
+    }
 }
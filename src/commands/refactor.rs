@@ -1,9 +1,20 @@
-use anyhow::{Result, bail};
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
 use serde::Deserialize;
 use serde_json::json;
 
+use super::deep;
 use crate::{openrouter, tokens};
 
+/// Per-file cap so one huge file cannot crowd out the rest of the project
+const MAX_FILE_TOKENS: usize = 8_000;
+
+/// `--dry-run` runs `deep` with its defaults
+const DRY_RUN_DEEP: deep::DeepOptions = deep::DeepOptions { window: 3, min_savings: 0 };
+
 const REFACTOR_PROMPT: &str = "\
 You are a Rust architect analyzing an entire project for cross-file refactoring opportunities. \
 Focus on: \
@@ -14,12 +25,125 @@ Only suggest changes with clear, significant token savings. \
 Each suggestion must reference the specific files and functions involved. \
 Order by impact (highest savings first).";
 
+const GENERATE_PROMPT: &str = "\
+You are a Rust architect implementing one cross-file refactoring. \
+You get a duplicated pattern, the suggested fix, and the full content of every file involved. \
+Write the shared code as a new module that the files import from `crate::shared::<module_name>`, \
+then return the complete new content of each involved file using it. \
+Keep behavior identical and keep every public item that other code may use. \
+Return complete files, never diffs or placeholders.";
+
+/// Narrow `refactor` to one class of duplication
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PatternKind {
+    /// Functions duplicated or nearly duplicated across files
+    DuplicateFns,
+    /// Structs that repeat the same fields
+    SharedStructs,
+    /// `use` blocks repeated across modules
+    RepeatedImports,
+    /// Parallel impls that could share a trait
+    TraitExtract,
+}
+
+impl PatternKind {
+    fn prompt(self) -> &'static str {
+        match self {
+            Self::DuplicateFns => {
+                "\
+You are a Rust architect looking ONLY for function-level duplication across files. \
+Find functions with identical or near-identical bodies, or the same logic with different names, \
+and propose a single shared function (with its module and signature) to replace them. \
+Ignore structs, imports and trait design. Order by impact (highest savings first)."
+            }
+            Self::SharedStructs => {
+                "\
+You are a Rust architect looking ONLY for duplicated struct definitions across files. \
+Find structs that repeat the same fields or field groups and propose a shared struct, \
+embedding, or generic type. Name the structs and fields involved. \
+Ignore functions, imports and traits. Order by impact (highest savings first)."
+            }
+            Self::RepeatedImports => {
+                "\
+You are a Rust architect looking ONLY for repeated `use` statements across files. \
+Find groups of imports duplicated in many modules and propose a prelude module, \
+re-exports, or consolidated paths. List the exact `use` lines involved. \
+Ignore function bodies and types. Order by impact (highest savings first)."
+            }
+            Self::TraitExtract => {
+                "\
+You are a Rust architect looking ONLY for opportunities to introduce a trait. \
+Find types with parallel impls, matching method sets, or match arms dispatching over similar types \
+that one trait (with default methods where useful) would unify. Give the trait signature. \
+Ignore plain duplicate functions and imports. Order by impact (highest savings first)."
+            }
+        }
+    }
+
+    /// What the `suggestion` field should contain for this pattern
+    fn suggestion_hint(self) -> &'static str {
+        match self {
+            Self::DuplicateFns => "The shared function to extract: module path and signature",
+            Self::SharedStructs => {
+                "The shared struct or generic type and which fields move into it"
+            }
+            Self::RepeatedImports => "The prelude/re-export to add and which use lines it replaces",
+            Self::TraitExtract => {
+                "The trait to introduce: name, methods, and which types implement it"
+            }
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct RefactorResult {
     patterns: Vec<Pattern>,
     summary: String,
 }
 
+#[derive(Deserialize)]
+struct Generated {
+    module_name: String,
+    shared_code: String,
+    files: Vec<GeneratedFile>,
+}
+
+#[derive(Deserialize)]
+struct GeneratedFile {
+    path: String,
+    content: String,
+}
+
+pub(crate) fn generate_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "module_name": {
+                "type": "string",
+                "description": "snake_case name of the new module under src/shared/"
+            },
+            "shared_code": {
+                "type": "string",
+                "description": "Full Rust source of src/shared/<module_name>.rs"
+            },
+            "files": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Path of an involved file, as given" },
+                        "content": { "type": "string", "description": "Complete new file content" }
+                    },
+                    "required": ["path", "content"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["module_name", "shared_code", "files"],
+        "additionalProperties": false
+    })
+}
+
 #[derive(Deserialize)]
 struct Pattern {
     description: String,
@@ -28,7 +152,9 @@ struct Pattern {
     tokens_saved: u32,
 }
 
-fn refactor_schema() -> serde_json::Value {
+pub(crate) fn refactor_schema(pattern: Option<PatternKind>) -> serde_json::Value {
+    let suggestion = pattern
+        .map_or("How to refactor: extract to shared fn/trait/module", PatternKind::suggestion_hint);
     json!({
         "type": "object",
         "properties": {
@@ -48,7 +174,7 @@ fn refactor_schema() -> serde_json::Value {
                         },
                         "suggestion": {
                             "type": "string",
-                            "description": "How to refactor: extract to shared fn/trait/module"
+                            "description": suggestion
                         },
                         "tokens_saved": {
                             "type": "integer",
@@ -69,7 +195,9 @@ fn refactor_schema() -> serde_json::Value {
     })
 }
 
-pub fn run(model: &str) -> Result<()> {
+/// `pattern` swaps the broad prompt for one focused on a single duplication class.
+/// With `generate`, each pattern is turned into code and applied after confirmation.
+pub fn run(model: &str, pattern: Option<PatternKind>, generate: bool) -> Result<()> {
     let stats = tokens::scan_project()?;
 
     if stats.files.is_empty() {
@@ -82,16 +210,20 @@ pub fn run(model: &str) -> Result<()> {
         stats.total_tokens
     );
 
-    let manifest = tokens::build_manifest(&stats);
+    let manifest = tokens::build_manifest_truncated(
+        &stats,
+        MAX_FILE_TOKENS,
+        tokens::model_token_limit(model),
+    )?;
 
     eprint!("  analyzing... ");
 
     let result = openrouter::chat_json::<RefactorResult>(
         model,
-        REFACTOR_PROMPT,
+        pattern.map_or(REFACTOR_PROMPT, PatternKind::prompt),
         &manifest,
         "refactor_result",
-        refactor_schema(),
+        refactor_schema(pattern),
     )?;
     eprintln!("done");
 
@@ -102,27 +234,304 @@ pub fn run(model: &str) -> Result<()> {
         return Ok(());
     }
 
-    let mut total_saveable: u32 = 0;
+    print_patterns(&result.patterns);
+    tokens::separator(70);
+    println!("{}", result.summary);
+    print_total(&result.patterns, stats.total_tokens);
+
+    if generate {
+        for (i, p) in result.patterns.iter().enumerate() {
+            println!();
+            tokens::separator(70);
+            println!("Pattern {}: {}", i + 1, p.description);
+            if let Err(e) = generate_pattern(model, p, &stats) {
+                println!("  Skipped: {e:#}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `--dry-run`: duplicate code found locally by `deep`, printed like the AI's patterns, with no API calls.
+/// Pattern kinds only the model can find are listed as `[AI required]`.
+pub fn dry_run(pattern: Option<PatternKind>) -> Result<()> {
+    let stats = tokens::scan_project()?;
+
+    if stats.files.is_empty() {
+        bail!("No .rs files found in project");
+    }
+
+    println!(
+        "Scanning {} files ({} tokens) for cross-file duplication locally (dry run, no API calls)...",
+        stats.files.len(),
+        stats.total_tokens
+    );
+    println!();
+
+    let local = match pattern {
+        None | Some(PatternKind::DuplicateFns) => {
+            local_patterns(&deep::analyze(&stats, DRY_RUN_DEEP), &stats)
+        }
+        Some(_) => Vec::new(),
+    };
+    if local.is_empty() {
+        println!("No duplicated code found locally. ✓");
+        println!();
+    } else {
+        print_patterns(&local);
+    }
+
+    let ai_only: Vec<_> = PatternKind::value_variants()
+        .iter()
+        .filter(|&&k| k != PatternKind::DuplicateFns && pattern.is_none_or(|p| p == k))
+        .filter_map(ValueEnum::to_possible_value)
+        .collect();
+    if !ai_only.is_empty() {
+        println!("Not checked locally:");
+        for kind in &ai_only {
+            println!(
+                "  [AI required] {}: {}",
+                kind.get_name(),
+                kind.get_help().unwrap_or_default()
+            );
+        }
+        println!();
+    }
+
+    tokens::separator(70);
+    print_total(&local, stats.total_tokens);
+    println!("Run without --dry-run for the full AI analysis.");
+    Ok(())
+}
+
+/// `deep`'s duplicate blocks and near-duplicate functions as refactor patterns
+fn local_patterns(result: &deep::DeepResult, stats: &tokens::ProjectStats) -> Vec<Pattern> {
+    let path = |i: usize| stats.files[i].path.clone();
+    let blocks = result.clusters.iter().map(|c| {
+        let (_, start, end) = c.occurrences[0];
+        let preview = c.preview.lines().next().unwrap_or_default().trim();
+        let mut files: Vec<String> = c.occurrences.iter().map(|(fi, ..)| path(*fi)).collect();
+        files.dedup();
+        Pattern {
+            description: format!(
+                "{}-line block repeated {} times: `{preview}`",
+                end - start + 1,
+                c.occurrences.len()
+            ),
+            files,
+            suggestion: "Extract the block into a shared function".to_string(),
+            tokens_saved: deep::estimate_savings(c) as u32,
+        }
+    });
+    let near = result.near_dupes.iter().map(|n| Pattern {
+        description: format!("{} ≈ {} (near-duplicate functions)", n.fn_a.0, n.fn_b.0),
+        files: vec![path(n.file_idx)],
+        suggestion: "Merge into one function and pass in what differs".to_string(),
+        tokens_saved: n.savings as u32,
+    });
+    blocks.chain(near).collect()
+}
 
-    for (i, p) in result.patterns.iter().enumerate() {
+fn print_patterns(patterns: &[Pattern]) {
+    for (i, p) in patterns.iter().enumerate() {
         println!("  {}. {}", i + 1, p.description);
         println!("     Files: {}", p.files.join(", "));
         println!("     Fix: {}", p.suggestion);
         println!("     Saves: ~{} tokens", p.tokens_saved);
         println!();
-        total_saveable += p.tokens_saved;
     }
+}
 
-    tokens::separator(70);
-    println!("{}", result.summary);
-
+fn print_total(patterns: &[Pattern], project_tokens: usize) {
+    let total_saveable: u32 = patterns.iter().map(|p| p.tokens_saved).sum();
     if total_saveable > 0 {
-        let save_pct = tokens::pct(total_saveable as usize, stats.total_tokens);
+        let save_pct = tokens::pct(total_saveable as usize, project_tokens);
         println!(
             "{} pattern(s) found, ~{total_saveable} tokens saveable ({save_pct:.1}% of project)",
-            result.patterns.len()
+            patterns.len()
         );
     }
+}
+
+/// Ask for the shared module and rewritten files, then write them if confirmed and
+/// `cargo check` passes; otherwise every touched file is restored
+fn generate_pattern(model: &str, pattern: &Pattern, stats: &tokens::ProjectStats) -> Result<()> {
+    let files: Vec<&tokens::FileStats> = pattern
+        .files
+        .iter()
+        .filter_map(|path| stats.files.iter().find(|f| f.path == *path))
+        .collect();
+    if files.is_empty() {
+        bail!("none of the listed files were found");
+    }
+
+    let mut prompt =
+        format!("PATTERN: {}\nSUGGESTED FIX: {}\n", pattern.description, pattern.suggestion);
+    for f in &files {
+        prompt.push_str(&format!("\n=== {} ===\n{}\n", f.path, f.content));
+    }
+
+    eprint!("  generating... ");
+    let generated = openrouter::chat_json::<Generated>(
+        model,
+        GENERATE_PROMPT,
+        &prompt,
+        "refactor_code",
+        generate_schema(),
+    )?;
+    eprintln!("done");
+
+    let name = module_ident(&generated.module_name);
+    let shared_path = format!("src/shared/{name}.rs");
+    let shared_code = tokens::strip_markdown_fences(&generated.shared_code);
+    let mut writes: Vec<(String, String)> =
+        vec![(shared_path.clone(), format!("{}\n", shared_code.trim_end()))];
+    let (before, mut after) = (files.iter().map(|f| f.tokens).sum::<usize>(), 0);
+    for g in generated.files {
+        // Only files that were part of the pattern may be replaced
+        let Some(f) = files.iter().find(|f| f.path == g.path) else { continue };
+        let content = format!("{}\n", tokens::strip_markdown_fences(&g.content).trim_end());
+        after += tokens::count_tokens(&content)?;
+        writes.push((f.path.clone(), content));
+    }
+    after += tokens::count_tokens(&shared_code)?;
+
+    println!("  New module: {shared_path} ({} lines)", shared_code.lines().count());
+    for (path, _) in &writes[1..] {
+        println!("  Rewrites:   {path}");
+    }
+    println!("  Tokens: {before} → {after} ({:+})", after as isize - before as isize);
+    writes.extend(module_declarations(&name)?);
+
+    if !matches!(tokens::ask_accept("  Apply? [y/n]")?.as_str(), "y" | "Y") {
+        println!("  Discarded.");
+        return Ok(());
+    }
 
+    let originals: Vec<(String, Option<String>)> =
+        writes.iter().map(|(path, _)| (path.clone(), std::fs::read_to_string(path).ok())).collect();
+    let new_dir = !Path::new("src/shared").exists();
+    if let Err(e) = write_all(&writes) {
+        println!("  Rolling back...");
+        roll_back(&originals, new_dir)?;
+        return Err(e);
+    }
+
+    eprint!("  cargo check... ");
+    if let Err(e) = cargo_check() {
+        eprintln!("failed ✗");
+        println!("  {e}");
+        println!("  Rolling back...");
+        roll_back(&originals, new_dir)?;
+        return Ok(());
+    }
+    eprintln!("passed ✓");
+    println!("  Applied.");
+    Ok(())
+}
+
+fn write_all(writes: &[(String, String)]) -> Result<()> {
+    std::fs::create_dir_all("src/shared")?;
+    for (path, content) in writes {
+        std::fs::write(path, content).with_context(|| format!("failed to write {path}"))?;
+    }
+    Ok(())
+}
+
+/// Put back every file `write_all` may have touched: rewrite the originals, delete the new
+/// files (ignoring ones that were never written) and, if `new_dir`, the empty src/shared
+fn roll_back(originals: &[(String, Option<String>)], new_dir: bool) -> Result<()> {
+    for (path, original) in originals {
+        match original {
+            Some(content) => std::fs::write(path, content)?,
+            None if Path::new(path).exists() => std::fs::remove_file(path)?,
+            None => {}
+        }
+    }
+    if new_dir {
+        let _ = std::fs::remove_dir("src/shared");
+    }
+    Ok(())
+}
+
+/// `pub mod <name>;` in src/shared/mod.rs, and `mod shared;` in the crate root, where missing
+fn module_declarations(name: &str) -> Result<Vec<(String, String)>> {
+    let mut writes = Vec::new();
+    let mod_rs = "src/shared/mod.rs";
+    let existing = std::fs::read_to_string(mod_rs).unwrap_or_default();
+    let decl = format!("pub mod {name};");
+    if !existing.lines().any(|l| l.trim() == decl) {
+        writes.push((mod_rs.to_string(), format!("{existing}{decl}\n")));
+    }
+
+    let root = if Path::new("src/lib.rs").exists() { "src/lib.rs" } else { "src/main.rs" };
+    let content =
+        std::fs::read_to_string(root).with_context(|| format!("failed to read {root}"))?;
+    if !content.lines().any(|l| matches!(l.trim(), "mod shared;" | "pub mod shared;")) {
+        writes.push((root.to_string(), format!("mod shared;\n{content}")));
+    }
+    Ok(writes)
+}
+
+/// Lowercase ASCII identifier, since the name becomes a file and a `mod` item
+pub(super) fn module_ident(name: &str) -> String {
+    let ident: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    let ident = ident.trim_matches('_');
+    match ident.chars().next() {
+        None => "common".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("m_{ident}"),
+        Some(_) => ident.to_string(),
+    }
+}
+
+pub(super) fn cargo_check() -> Result<()> {
+    let check = Command::new("cargo")
+        .args(["check", "--quiet", "--all-targets"])
+        .output()
+        .context("failed to run cargo check")?;
+    if !check.status.success() {
+        let stderr = String::from_utf8_lossy(&check.stderr);
+        bail!("cargo check: {}", stderr.lines().next().unwrap_or("failed"));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_ident() {
+        for (input, expected) in [
+            ("cargo_runner", "cargo_runner"),
+            ("Cargo Runner", "cargo_runner"),
+            ("  ratio-calc! ", "ratio_calc"),
+            ("2fast", "m_2fast"),
+            ("", "common"),
+        ] {
+            assert_eq!(module_ident(input), expected, "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn test_local_patterns() {
+        let block = "fn load(path: &str) -> String {\n    let raw = std::fs::read_to_string(path).unwrap();\n    \
+                     let trimmed = raw.trim().to_string();\n    trimmed.replace(\"\\r\", \"\")\n}\n";
+        let stats = tokens::scan_sources([
+            ("src/a.rs".to_string(), block.to_string()),
+            ("src/b.rs".to_string(), block.replace("load", "read")),
+        ])
+        .unwrap();
+        let patterns = local_patterns(&deep::analyze(&stats, DRY_RUN_DEEP), &stats);
+
+        assert!(!patterns.is_empty());
+        assert_eq!(patterns[0].files, ["src/a.rs", "src/b.rs"]);
+        assert!(patterns[0].description.contains("repeated 2 times"));
+        assert!(patterns[0].tokens_saved > 0);
+    }
+}
@@ -1,10 +1,10 @@
-use anyhow::Result;
+use std::fs;
+
+use anyhow::{Context, Result};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::{openrouter, tokens};
-
-const DEFAULT_MAX_TOKENS: usize = 20_000;
+use crate::{github, openrouter, tokens};
 
 const REVIEW_PROMPT: &str = "\
 You are a Rust code auditor focused on token efficiency. \
@@ -17,7 +17,14 @@ struct ReviewResult {
     suggestions: Vec<tokens::Suggestion>,
 }
 
-fn review_schema() -> serde_json::Value {
+/// What happened to one file, kept for the Markdown report
+enum Outcome {
+    Reviewed { suggestions: Vec<tokens::Suggestion>, savings: usize },
+    Skipped(String),
+    Failed(String),
+}
+
+pub(crate) fn review_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -31,21 +38,58 @@ fn review_schema() -> serde_json::Value {
     })
 }
 
-pub fn run(n: usize, model: &str) -> Result<()> {
-    let stats = tokens::scan_project_sorted()?;
+/// Per-file model after routing, or the limit the file exceeds
+fn plan(f: &tokens::FileStats, model: &str, fallback: Option<&str>) -> Result<String, usize> {
+    let file_model = tokens::route_model(model, fallback, f.tokens);
+    let max_tokens = tokens::model_token_limit(file_model);
+    if f.tokens > max_tokens { Err(max_tokens) } else { Ok(file_model.to_string()) }
+}
+
+fn review_file(model: &str, content: &str) -> Result<ReviewResult> {
+    openrouter::chat_json(model, REVIEW_PROMPT, content, "review_result", review_schema())
+}
+
+/// Reviews `parallel` files at a time (needs the `tokio` feature), printing in file order
+/// With `file`, only that file is reviewed and the project is never scanned
+pub fn run(
+    n: usize,
+    model: &str,
+    fallback: Option<&str>,
+    output: Option<&str>,
+    pr_comment: bool,
+    parallel: usize,
+    file: Option<&str>,
+) -> Result<()> {
+    let stats = match file {
+        Some(file) => {
+            let (content, _, _) = tokens::read_rs_file(file)?;
+            tokens::scan_sources([(file.trim_start_matches("./").to_string(), content)])?
+        }
+        None => {
+            let mut stats = tokens::scan_project()?;
+            stats.files.sort_by(tokens::FileStats::cmp_by_rank);
+            stats
+        }
+    };
 
     let show = n.min(stats.files.len());
-    let max_tokens = model_context_limit(model).unwrap_or(DEFAULT_MAX_TOKENS);
+    let plans: Vec<_> = stats.files.iter().take(show).map(|f| plan(f, model, fallback)).collect();
+    let mut pool = start_pool(&stats, &plans, parallel);
 
-    println!(
-        "Scanning project... {} files, {} tokens total",
-        stats.files.len(),
-        stats.total_tokens
-    );
-    println!("Reviewing top {show} files via {model}...");
+    if let Some(file) = file {
+        println!("Reviewing {file} via {model}...");
+    } else {
+        println!(
+            "Scanning project... {} files, {} tokens total",
+            stats.files.len(),
+            stats.total_tokens
+        );
+        println!("Reviewing top {show} files via {model}...");
+    }
     println!();
 
     let mut total_estimated_savings = 0;
+    let mut outcomes = Vec::with_capacity(show);
 
     for (i, f) in stats.files.iter().take(show).enumerate() {
         let pct_of_total = tokens::pct(f.tokens, stats.total_tokens);
@@ -59,25 +103,31 @@ pub fn run(n: usize, model: &str) -> Result<()> {
             f.ratio
         );
 
-        if f.tokens > max_tokens {
-            println!(
-                "      (skipped — {} tokens exceeds {max_tokens} limit for {model})",
-                f.tokens
-            );
-            println!("      Tip: split this file into smaller modules.");
-            println!();
-            continue;
+        let file_model = match &plans[i] {
+            Ok(file_model) => file_model.as_str(),
+            Err(max_tokens) => {
+                let file_model = tokens::route_model(model, fallback, f.tokens);
+                println!(
+                    "      (skipped — {} tokens exceeds {max_tokens} limit for {file_model})",
+                    f.tokens
+                );
+                println!("      Tip: split this file into smaller modules.");
+                println!();
+                outcomes.push(Outcome::Skipped(format!("exceeds {max_tokens} token limit")));
+                continue;
+            }
+        };
+        if file_model != model {
+            println!("      (exceeds {model} limit — using {file_model})");
         }
 
         eprint!("      [{}/{}] reviewing... ", i + 1, show);
 
-        match openrouter::chat_json::<ReviewResult>(
-            model,
-            REVIEW_PROMPT,
-            &f.content,
-            "review_result",
-            review_schema(),
-        ) {
+        let reviewed = match &mut pool {
+            Some(pool) => pool.take(i),
+            None => review_file(file_model, &f.content),
+        };
+        match reviewed {
             Ok(result) => {
                 eprintln!("done");
                 let estimated: u32 = result.suggestions.iter().map(|s| s.tokens_saved).sum();
@@ -89,16 +139,19 @@ pub fn run(n: usize, model: &str) -> Result<()> {
                     );
                 }
 
-                if estimated > 0 {
-                    let capped = (estimated as usize).min(f.tokens / 2);
+                let capped = (estimated as usize).min(f.tokens / 2);
+                if capped > 0 {
                     let est_pct = tokens::pct(capped, f.tokens);
                     println!("      => est. savings: ~{capped} tokens ({est_pct:.1}%)");
                     total_estimated_savings += capped;
                 }
+                outcomes
+                    .push(Outcome::Reviewed { suggestions: result.suggestions, savings: capped });
             }
             Err(e) => {
                 eprintln!("failed");
                 println!("      (review failed: {e})");
+                outcomes.push(Outcome::Failed(e.to_string()));
             }
         }
         println!();
@@ -121,18 +174,172 @@ pub fn run(n: usize, model: &str) -> Result<()> {
     println!();
     println!("Run `cargo syntax rewrite <file>` on any file to apply changes.");
 
+    if output.is_some() || pr_comment {
+        let report = markdown_report(&stats, &outcomes, model, total_estimated_savings);
+        if let Some(path) = output {
+            fs::write(path, &report).with_context(|| format!("failed to write {path}"))?;
+            println!("Wrote Markdown report to {path}");
+        }
+        if pr_comment {
+            eprint!("  posting PR comment... ");
+            github::post_pr_comment(&report)?;
+            eprintln!("done");
+        }
+    }
+
     Ok(())
 }
 
-fn model_context_limit(model: &str) -> Option<usize> {
-    let id = model.to_lowercase();
-    if id.contains("gemini") || id.contains("claude-sonnet-4") || id.contains("claude-opus") {
-        Some(100_000)
-    } else if id.contains("gpt-4o") || id.contains("gpt-4.1") {
-        Some(80_000)
-    } else if id.contains("deepseek") || id.contains("qwen") {
-        Some(30_000)
-    } else {
-        None
+#[cfg(not(feature = "tokio"))]
+enum Pool {}
+
+#[cfg(not(feature = "tokio"))]
+impl Pool {
+    fn take(&mut self, _: usize) -> Result<ReviewResult> {
+        match *self {}
     }
 }
+
+#[cfg(not(feature = "tokio"))]
+fn start_pool(
+    _: &tokens::ProjectStats,
+    _: &[Result<String, usize>],
+    parallel: usize,
+) -> Option<Pool> {
+    if parallel > 1 {
+        eprintln!("Note: built without the `tokio` feature — reviewing one file at a time");
+    }
+    None
+}
+
+/// Reviews running in the background, handed out in file order as they finish
+#[cfg(feature = "tokio")]
+struct Pool {
+    runtime: tokio::runtime::Runtime,
+    rx: tokio::sync::mpsc::UnboundedReceiver<(usize, Result<ReviewResult>)>,
+    done: std::collections::HashMap<usize, Result<ReviewResult>>,
+}
+
+#[cfg(feature = "tokio")]
+impl Pool {
+    /// Block until the review of file `i` has arrived
+    fn take(&mut self, i: usize) -> Result<ReviewResult> {
+        loop {
+            if let Some(result) = self.done.remove(&i) {
+                return result;
+            }
+            match self.runtime.block_on(self.rx.recv()) {
+                Some((j, result)) => {
+                    self.done.insert(j, result);
+                }
+                None => anyhow::bail!("review task for file #{} ended early", i + 1),
+            }
+        }
+    }
+}
+
+/// Dispatch every reviewable file up front, at most `parallel` requests in flight
+#[cfg(feature = "tokio")]
+fn start_pool(
+    stats: &tokens::ProjectStats,
+    plans: &[Result<String, usize>],
+    parallel: usize,
+) -> Option<Pool> {
+    use std::sync::Arc;
+    use tokio::sync::{Semaphore, mpsc};
+
+    if parallel <= 1 {
+        return None;
+    }
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Note: could not start tokio ({e}) — reviewing one file at a time");
+            return None;
+        }
+    };
+    let semaphore = Arc::new(Semaphore::new(parallel));
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    for (i, (f, plan)) in stats.files.iter().zip(plans).enumerate() {
+        let Ok(file_model) = plan else { continue };
+        let (semaphore, tx) = (Arc::clone(&semaphore), tx.clone());
+        let (file_model, content) = (file_model.clone(), f.content.clone());
+        runtime.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = tokio::task::spawn_blocking(move || review_file(&file_model, &content))
+                .await
+                .unwrap_or_else(|e| Err(e.into()));
+            let _ = tx.send((i, result));
+        });
+    }
+
+    Some(Pool { runtime, rx, done: std::collections::HashMap::new() })
+}
+
+fn markdown_report(
+    stats: &tokens::ProjectStats,
+    outcomes: &[Outcome],
+    model: &str,
+    total_savings: usize,
+) -> String {
+    let top_tokens: usize = stats.files.iter().take(outcomes.len()).map(|f| f.tokens).sum();
+    let mut md = String::from("# Token Efficiency Review\n\n");
+    md.push_str(&format!(
+        "Reviewed {}/{} files ({top_tokens} of {} tokens) via `{model}`.\n\n",
+        outcomes.len(),
+        stats.files.len(),
+        stats.total_tokens
+    ));
+
+    md.push_str("| # | File | Lines | Tokens | T/L | Est. savings |\n");
+    md.push_str("|--:|------|------:|-------:|----:|-------------:|\n");
+    for (i, (f, outcome)) in stats.files.iter().zip(outcomes).enumerate() {
+        let savings = match outcome {
+            Outcome::Reviewed { savings, .. } => format!("~{savings}"),
+            Outcome::Skipped(_) => "skipped".to_string(),
+            Outcome::Failed(_) => "failed".to_string(),
+        };
+        md.push_str(&format!(
+            "| {} | `{}` | {} | {} | {:.1} | {savings} |\n",
+            i + 1,
+            f.path,
+            f.lines,
+            f.tokens,
+            f.ratio
+        ));
+    }
+
+    if total_savings > 0 {
+        let total_pct = tokens::pct(total_savings, stats.total_tokens);
+        md.push_str(&format!(
+            "\n**Estimated total savings: ~{total_savings} tokens ({total_pct:.1}%)**\n"
+        ));
+    }
+
+    for (f, outcome) in stats.files.iter().zip(outcomes) {
+        md.push_str(&format!("\n## `{}`\n\n", f.path));
+        match outcome {
+            Outcome::Reviewed { suggestions, .. } if suggestions.is_empty() => {
+                md.push_str("No suggestions.\n");
+            }
+            Outcome::Reviewed { suggestions, .. } => {
+                for s in suggestions {
+                    md.push_str(&format!(
+                        "> **{}** — `{}` (~{} tokens)\n\n",
+                        s.description, s.location, s.tokens_saved
+                    ));
+                }
+            }
+            Outcome::Skipped(reason) => {
+                md.push_str(&format!("_Skipped: {reason}. Consider splitting this file._\n"));
+            }
+            Outcome::Failed(err) => {
+                md.push_str(&format!("_Review failed: {err}_\n"));
+            }
+        }
+    }
+
+    md
+}
@@ -1,12 +1,12 @@
-use crate::{openrouter, tokens};
-use anyhow::{Result, bail};
+use crate::{openrouter, templates, tokens};
+use anyhow::{Context, Result, bail};
+use clap::{Args, ValueEnum};
 use serde::Deserialize;
 use serde_json::json;
 use std::path::Path;
-use tiktoken_rs::o200k_base;
 
-const REWRITE_PROMPT: &str = "You are a Rust code optimizer focused on token efficiency. Rewrite the given Rust code to minimize token count while preserving identical behavior. Apply these rules: - Prefer iterator chains over manual loops - Use ? operator instead of match/unwrap on Result/Option - Inline format args (write `\"{x}\"` not `\"{}\", x`) - Remove redundant closures, borrows, lifetimes, clone calls - Use manual_let_else, matches!, and other idiomatic patterns - Collapse collapsible if/else blocks - Remove unnecessary type annotations - Remove comments that restate the code Return ONLY the rewritten Rust code. No markdown fences, no explanations.";
-const EXPLAIN_PROMPT: &str = "You are a Rust code auditor. Given an ORIGINAL and REWRITTEN version of the same file, list each change: what was changed and how many tokens it saves. Be specific (mention function names, patterns).";
+/// Follows "You are a <language> code auditor."
+const EXPLAIN_PROMPT: &str = "Given an ORIGINAL and REWRITTEN version of the same file, list each change: what was changed and how many tokens it saves. Be specific (mention function names, patterns).";
 
 #[derive(Deserialize)]
 struct ExplainResult {
@@ -19,6 +19,7 @@ struct Change {
 }
 
 pub struct RewriteResult {
+    pub model: String,
     pub original: String,
     pub rewritten: String,
     pub tokens_before: usize,
@@ -33,7 +34,180 @@ impl RewriteResult {
     }
 }
 
-fn explain_schema() -> serde_json::Value {
+/// Guards against rewrites that balloon instead of shrinking
+#[derive(Args, Clone, Copy, Default)]
+pub struct SizeLimits {
+    /// Reject the rewrite if it is longer than this many tokens
+    #[arg(long)]
+    pub max_tokens: Option<usize>,
+    /// Reject the rewrite if it grows the file by more than this percentage
+    #[arg(long)]
+    pub max_growth_pct: Option<f64>,
+}
+
+impl SizeLimits {
+    /// Why `result` should be rejected, if it breaks a limit
+    pub fn violation(&self, result: &RewriteResult) -> Option<String> {
+        if let Some(max) = self.max_tokens
+            && result.tokens_after > max
+        {
+            return Some(format!(
+                "rewrite is {} tokens, over the --max-tokens limit of {max}",
+                result.tokens_after
+            ));
+        }
+        let growth = -tokens::pct_delta(result.saved(), result.tokens_before);
+        if let Some(max) = self.max_growth_pct
+            && growth > max
+        {
+            return Some(format!(
+                "rewrite grew the file by {growth:.1}%, over the --max-growth-pct limit of {max}%"
+            ));
+        }
+        None
+    }
+}
+
+/// Source language for `rewrite --language`
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Language {
+    #[default]
+    Rust,
+    Python,
+    Javascript,
+    Typescript,
+    Go,
+}
+
+impl Language {
+    /// Name used in the prompts and `templates::rewrite_prompt`
+    fn name(self) -> &'static str {
+        match self {
+            Self::Rust => "Rust",
+            Self::Python => "Python",
+            Self::Javascript => "JavaScript",
+            Self::Typescript => "TypeScript",
+            Self::Go => "Go",
+        }
+    }
+
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Rust => &["rs"],
+            Self::Python => &["py", "pyi"],
+            Self::Javascript => &["js", "mjs", "cjs", "jsx"],
+            Self::Typescript => &["ts", "mts", "cts", "tsx"],
+            Self::Go => &["go"],
+        }
+    }
+
+    fn matches(self, path: &Path) -> bool {
+        path.extension().and_then(|e| e.to_str()).is_some_and(|e| self.extensions().contains(&e))
+    }
+}
+
+/// What gets sent to the model and how
+#[derive(Args, Clone, Copy, Default)]
+pub struct RewriteMode {
+    /// If the file exceeds the model's context, rewrite it one function at a time
+    #[arg(long)]
+    pub split_large_files: bool,
+    /// Keep #[cfg(test)] blocks and #[test] functions out of the rewrite and re-append them verbatim
+    #[arg(long)]
+    pub preserve_tests: bool,
+    /// Language of the file; picks the rewrite rules and the accepted extensions
+    #[arg(long, value_enum, default_value = "rust")]
+    pub language: Language,
+    /// Rewrite the file whatever its extension
+    #[arg(long)]
+    pub no_language_check: bool,
+}
+
+/// Extra instructions for the rewrite prompt
+#[derive(Args, Clone, Default)]
+pub struct Constraints {
+    /// Extra rewriting constraint, e.g. "do not change public API signatures" (repeatable)
+    #[arg(long = "constraint", value_name = "TEXT")]
+    pub extra: Vec<String>,
+    /// Use only the --constraint rules instead of the default token-efficiency prompt
+    #[arg(long)]
+    pub no_default_constraints: bool,
+    /// Text or Markdown file of project rules to append to the prompt; `+++` TOML frontmatter may add constraints
+    #[arg(long, value_name = "PATH")]
+    pub rules_file: Option<String>,
+}
+
+/// A parsed `--rules-file`: `+++` TOML frontmatter settings and the free-text body
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Rules {
+    constraints: Vec<String>,
+    no_default_constraints: bool,
+    #[serde(skip)]
+    body: String,
+}
+
+impl Constraints {
+    /// System prompt for `language` with the constraints appended as a numbered list, followed
+    /// by the body of `--rules-file` (whose frontmatter can add constraints of its own)
+    pub fn prompt(&self, language: Language) -> Result<String> {
+        let rules = match &self.rules_file {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).with_context(|| {
+                    format!(
+                        "cannot read rules file {path}. Expected format:\n\n{}",
+                        templates::RULES_TEMPLATE
+                    )
+                })?;
+                parse_rules(&content).with_context(|| format!("invalid rules file {path}"))?
+            }
+            None => Rules::default(),
+        };
+        self.compose(&rules, language)
+    }
+
+    fn compose(&self, rules: &Rules, language: Language) -> Result<String> {
+        let name = language.name();
+        let list: String = self
+            .extra
+            .iter()
+            .chain(&rules.constraints)
+            .enumerate()
+            .map(|(i, c)| format!("\n{}. {c}", i + 1))
+            .collect();
+        let mut prompt = if self.no_default_constraints || rules.no_default_constraints {
+            if list.is_empty() {
+                bail!("no_default_constraints needs at least one constraint");
+            }
+            format!(
+                "Rewrite the given {name} code following these constraints:{list}\nReturn ONLY the rewritten {name} code. No markdown fences, no explanations."
+            )
+        } else if list.is_empty() {
+            templates::rewrite_prompt(name)
+        } else {
+            format!("{}\nAdditional constraints:{list}", templates::rewrite_prompt(name))
+        };
+        if !rules.body.is_empty() {
+            prompt.push_str(&format!("\nAdditional project rules:\n{}", rules.body));
+        }
+        Ok(prompt)
+    }
+}
+
+/// Split an optional `+++`-delimited TOML frontmatter off `content` and parse it into `Rules`
+fn parse_rules(content: &str) -> Result<Rules> {
+    let (mut rules, body) = match content
+        .strip_prefix("+++\n")
+        .and_then(|rest| rest.split_once("\n+++"))
+    {
+        Some((front, body)) => (toml::from_str(front).context("invalid TOML frontmatter")?, body),
+        None => (Rules::default(), content),
+    };
+    rules.body = body.trim().to_string();
+    Ok(rules)
+}
+
+pub(crate) fn explain_schema() -> serde_json::Value {
     json!({
         "type": "object",
         "properties": {
@@ -55,48 +229,214 @@ fn explain_schema() -> serde_json::Value {
     })
 }
 
-pub fn rewrite_file(file: &str, model: &str) -> Result<RewriteResult> {
+pub fn rewrite_file(file: &str, model: &str, fallback: Option<&str>) -> Result<RewriteResult> {
+    let (original, tokens_before) = read_source(file, Some(Language::Rust))?;
+    let model = tokens::route_model(model, fallback, tokens_before);
+    let raw = openrouter::chat(model, &templates::rewrite_prompt("Rust"), &original)?;
+    finish(model, original, tokens_before, &raw)
+}
+
+/// Like `rewrite_file` for several files, sending each routed model's files through one
+/// cached system prompt. Results come back in the order of `files`.
+pub fn rewrite_files(
+    files: &[&str],
+    model: &str,
+    fallback: Option<&str>,
+) -> Vec<Result<RewriteResult>> {
+    let mut results: Vec<Option<Result<RewriteResult>>> = Vec::with_capacity(files.len());
+    // (routed model, [(index into files, original, tokens_before)])
+    let mut by_model: Vec<(&str, Vec<Pending>)> = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        match read_source(file, Some(Language::Rust)) {
+            Ok((original, tokens_before)) => {
+                results.push(None);
+                let routed = tokens::route_model(model, fallback, tokens_before);
+                let entry = (i, original, tokens_before);
+                match by_model.iter_mut().find(|(m, _)| *m == routed) {
+                    Some((_, group)) => group.push(entry),
+                    None => by_model.push((routed, vec![entry])),
+                }
+            }
+            Err(e) => results.push(Some(Err(e))),
+        }
+    }
+
+    let prompt = templates::rewrite_prompt("Rust");
+    for (routed, group) in by_model {
+        let prompts: Vec<String> = group.iter().map(|(_, original, _)| original.clone()).collect();
+        let responses = openrouter::chat_with_system_cache(routed, &prompt, &prompts);
+        for ((i, original, tokens_before), raw) in group.into_iter().zip(responses) {
+            results[i] = Some(raw.and_then(|raw| finish(routed, original, tokens_before, &raw)));
+        }
+    }
+
+    // Every readable file got exactly one response above
+    results.into_iter().flatten().collect()
+}
+
+type Pending = (usize, String, usize);
+
+/// Validated file content and its token count; `language` is None to accept any extension
+fn read_source(file: &str, language: Option<Language>) -> Result<(String, usize)> {
     let path = Path::new(file);
     if !path.exists() {
         bail!("File not found: {file}")
     }
-    if path.extension().is_none_or(|ext| ext != "rs") {
-        bail!("Only .rs files are supported")
+    if let Some(language) = language
+        && !language.matches(path)
+    {
+        let extensions: Vec<String> =
+            language.extensions().iter().map(|e| format!(".{e}")).collect();
+        bail!("Only {} files are supported", extensions.join("/"))
     }
 
     let original = std::fs::read_to_string(path)?;
-    let bpe = o200k_base()?;
-    let tokens_before = bpe.encode_with_special_tokens(&original).len();
-    let lines_before = original.lines().count();
+    let tokens_before = tokens::count_tokens(&original)?;
+    Ok((original, tokens_before))
+}
 
-    let raw = openrouter::chat(model, REWRITE_PROMPT, &original)?;
-    let rewritten = tokens::strip_markdown_fences(&raw);
-    let tokens_after = bpe.encode_with_special_tokens(&rewritten).len();
-    let lines_after = rewritten.lines().count();
+fn finish(model: &str, original: String, tokens_before: usize, raw: &str) -> Result<RewriteResult> {
+    result(model, original, tokens_before, tokens::strip_markdown_fences(raw))
+}
 
+fn result(
+    model: &str,
+    original: String,
+    tokens_before: usize,
+    rewritten: String,
+) -> Result<RewriteResult> {
+    let tokens_after = tokens::count_tokens(&rewritten)?;
     Ok(RewriteResult {
+        model: model.to_string(),
+        lines_before: original.lines().count(),
+        lines_after: rewritten.lines().count(),
         original,
         rewritten,
         tokens_before,
         tokens_after,
-        lines_before,
-        lines_after,
     })
 }
 
-pub fn run(file: &str, model: &str) -> Result<()> {
-    println!("Sending {file} to {model} via OpenRouter...");
-    eprint!("  rewriting... ");
-    let result = rewrite_file(file, model)?;
+/// Rewrite `source` function by function, keeping only the functions that got smaller.
+/// Prints the savings of every function as it goes.
+fn rewrite_in_chunks(file: &str, source: &str, model: &str, prompt: &str) -> Result<String> {
+    let fns = super::deep::extract_functions(source);
+    if fns.is_empty() {
+        bail!("no functions found to split {file} at");
+    }
+
+    let bodies: Vec<String> = fns.iter().map(|f| f.body.clone()).collect();
+    let responses = openrouter::chat_with_system_cache(model, prompt, &bodies);
     eprintln!("done");
-    println!("  {} lines, {} tokens", result.lines_before, result.tokens_before);
 
-    let diff = result.saved();
-    let pct = if result.tokens_before > 0 {
-        (diff as f64 / result.tokens_before as f64) * 100.0
+    let mut chunks = Vec::with_capacity(fns.len());
+    for (f, raw) in fns.into_iter().zip(responses) {
+        let before = tokens::count_tokens(&f.body)?;
+        let label = format!("{} (line {})", f.name, f.line + 1);
+        let end = f.line + f.body.lines().count();
+        let body = match raw.map(|raw| tokens::strip_markdown_fences(&raw)) {
+            Ok(new) => {
+                let after = tokens::count_tokens(&new)?;
+                if after < before {
+                    println!("  {label:<40} {before:>6} → {after:<6} (-{})", before - after);
+                    new
+                } else {
+                    println!("  {label:<40} {before:>6}   no improvement, kept");
+                    f.body
+                }
+            }
+            Err(e) => {
+                println!("  {label:<40} {before:>6}   failed ({e}), kept");
+                f.body
+            }
+        };
+        chunks.push((f.line, end, body));
+    }
+
+    Ok(splice(source, &chunks))
+}
+
+/// Append the untouched test items after the rewritten code, separated by blank lines
+fn with_tests(rewritten: String, tests: &[&str]) -> String {
+    if tests.is_empty() {
+        return rewritten;
+    }
+    let mut out = rewritten.trim_end().to_string();
+    for item in tests {
+        out.push_str("\n\n");
+        out.push_str(item.trim_end());
+    }
+    out.push('\n');
+    out
+}
+
+/// Replace each `(start, end, text)` line range of `original` (sorted, non-overlapping)
+fn splice(original: &str, chunks: &[(usize, usize, String)]) -> String {
+    let lines: Vec<&str> = original.lines().collect();
+    let mut out = Vec::new();
+    let mut next = 0;
+    for (start, end, text) in chunks {
+        out.extend(lines[next..*start].iter().map(ToString::to_string));
+        out.push(text.clone());
+        next = *end;
+    }
+    out.extend(lines[next..].iter().map(ToString::to_string));
+    let mut spliced = out.join("\n");
+    if original.ends_with('\n') {
+        spliced.push('\n');
+    }
+    spliced
+}
+
+/// `mode.split_large_files` rewrites a file over `model`'s context limit one function at a time;
+/// `mode.preserve_tests` sends only the non-test code and re-appends the test items unchanged.
+/// Both need Rust; `mode.language` picks the prompt and, unless `no_language_check`, the extension.
+pub fn run(
+    file: &str,
+    model: &str,
+    fallback: Option<&str>,
+    backup: bool,
+    limits: SizeLimits,
+    constraints: &Constraints,
+    mode: RewriteMode,
+) -> Result<()> {
+    if mode.language != Language::Rust && (mode.split_large_files || mode.preserve_tests) {
+        bail!("--split-large-files and --preserve-tests only work on Rust files");
+    }
+    println!("Sending {file} to {model} via OpenRouter...");
+    let prompt = constraints.prompt(mode.language)?;
+    let check = (!mode.no_language_check).then_some(mode.language);
+    let (original, tokens_before) = read_source(file, check)?;
+    let split = mode.split_large_files && tokens_before > tokens::model_token_limit(model);
+    let (source, tests) = if mode.preserve_tests {
+        tokens::split_test_items(&original)
     } else {
-        0.0
+        (original.clone(), Vec::new())
     };
+    if mode.preserve_tests {
+        println!("  Preserving {} test block(s) verbatim", tests.len());
+    }
+
+    let (used, rewritten) = if split {
+        eprint!("  file exceeds {model}'s context, rewriting per function... ");
+        (model, rewrite_in_chunks(file, &source, model, &prompt)?)
+    } else {
+        eprint!("  rewriting... ");
+        let routed = tokens::route_model(model, fallback, tokens_before);
+        let raw = openrouter::chat(routed, &prompt, &source)?;
+        eprintln!("done");
+        (routed, tokens::strip_markdown_fences(&raw))
+    };
+    let rewritten = with_tests(rewritten, &tests);
+    let result = result(used, original, tokens_before, rewritten)?;
+    if result.model != model {
+        println!("  (exceeds {model} limit — used {})", result.model);
+    }
+    let model = result.model.as_str();
+    println!("  {} lines, {} tokens", result.lines_before, result.tokens_before);
+
+    let diff = result.saved();
+    let pct = tokens::pct_delta(diff, result.tokens_before);
 
     println!();
     println!("Result:");
@@ -111,13 +451,37 @@ pub fn run(file: &str, model: &str) -> Result<()> {
         println!("  No token change.");
     }
 
+    if let Some(reason) = limits.violation(&result) {
+        println!();
+        println!("Rejected: {reason}. Keeping the original.");
+        return Ok(());
+    }
+
+    if split {
+        if diff <= 0 {
+            println!();
+            println!("No net reduction. Keeping the original.");
+            return Ok(());
+        }
+        println!();
+        return match tokens::ask_accept("Accept? [y/n]")?.as_str() {
+            "y" | "Y" => write_checked(file, &result, backup),
+            _ => {
+                println!("Discarded.");
+                Ok(())
+            }
+        };
+    }
+
     println!();
     println!("Changes:");
     let explain_input =
         format!("ORIGINAL:\n{}\n\nREWRITTEN:\n{}", result.original, result.rewritten);
+    let explain_prompt =
+        format!("You are a {} code auditor. {EXPLAIN_PROMPT}", mode.language.name());
     match openrouter::chat_json::<ExplainResult>(
         model,
-        EXPLAIN_PROMPT,
+        &explain_prompt,
         &explain_input,
         "explain_result",
         explain_schema(),
@@ -133,19 +497,14 @@ pub fn run(file: &str, model: &str) -> Result<()> {
     println!();
     let input = tokens::ask_accept("Accept? [y/n/diff]")?;
 
-    let path = Path::new(file);
     match input.as_str() {
-        "y" | "Y" => {
-            std::fs::write(path, &result.rewritten)?;
-            println!("Written to {file}");
-        }
+        "y" | "Y" => write_rewrite(file, &result, backup)?,
         "diff" | "d" => {
             print_diff(&result.original, &result.rewritten);
             println!();
             let input2 = tokens::ask_accept("Accept? [y/n]")?;
             if matches!(input2.as_str(), "y" | "Y") {
-                std::fs::write(path, &result.rewritten)?;
-                println!("Written to {file}");
+                write_rewrite(file, &result, backup)?;
             } else {
                 println!("Discarded.");
             }
@@ -156,23 +515,194 @@ pub fn run(file: &str, model: &str) -> Result<()> {
     Ok(())
 }
 
-fn print_diff(original: &str, rewritten: &str) {
-    let old_lines: Vec<&str> = original.lines().collect();
-    let new_lines: Vec<&str> = rewritten.lines().collect();
-    let max = old_lines.len().max(new_lines.len());
+fn write_rewrite(file: &str, result: &RewriteResult, backup: bool) -> Result<()> {
+    if backup {
+        let orig = backup_path(file);
+        std::fs::write(&orig, &result.original)?;
+        println!("Backup saved to {orig}");
+    }
+    std::fs::write(file, &result.rewritten)?;
+    println!("Written to {file}");
+    Ok(())
+}
+
+/// `write_rewrite`, then put the original back if the spliced file no longer passes `cargo check`
+fn write_checked(file: &str, result: &RewriteResult, backup: bool) -> Result<()> {
+    write_rewrite(file, result, backup)?;
+    eprint!("  running cargo check... ");
+    if let Err(e) = super::refactor::cargo_check() {
+        eprintln!("failed");
+        std::fs::write(file, &result.original)?;
+        println!("Rejected: {e}. Restored the original {file}.");
+        return Ok(());
+    }
+    eprintln!("ok");
+    Ok(())
+}
+
+fn backup_path(file: &str) -> String {
+    format!("{file}.orig")
+}
+
+/// Move `<file>.orig` back over `<file>`
+pub fn restore(file: &str) -> Result<()> {
+    let orig = backup_path(file);
+    if !Path::new(&orig).exists() {
+        bail!("No backup found: {orig}");
+    }
+    std::fs::rename(&orig, file)?;
+    println!("Restored {file} from {orig}");
+    Ok(())
+}
 
+pub fn print_diff(original: &str, rewritten: &str) {
     crate::tokens::separator(70);
-    for i in 0..max {
-        let old = old_lines.get(i).copied().unwrap_or("");
-        let new = new_lines.get(i).copied().unwrap_or("");
-        if old != new {
-            if !old.is_empty() {
-                println!("- {old}");
-            }
-            if !new.is_empty() {
-                println!("+ {new}");
-            }
-        }
+    for line in diff_lines(original, rewritten) {
+        println!("{line}");
     }
     crate::tokens::separator(70);
 }
+
+/// `- old` and `+ new` lines that turn `original` into `rewritten`, in file order. Lines kept
+/// by the longest common subsequence (after the shared prefix and suffix) are left out.
+fn diff_lines(original: &str, rewritten: &str) -> Vec<String> {
+    let (old, new): (Vec<&str>, Vec<&str>) =
+        (original.lines().collect(), rewritten.lines().collect());
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    // lcs[i][j]: length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j, mut out) = (0, 0, Vec::new());
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("- {}", old[i]).trim_end().to_string());
+            i += 1;
+        } else {
+            out.push(format!("+ {}", new[j]).trim_end().to_string());
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraints(extra: &[&str], no_default: bool) -> Constraints {
+        Constraints {
+            extra: extra.iter().map(ToString::to_string).collect(),
+            no_default_constraints: no_default,
+            rules_file: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_lines_keeps_order() {
+        let before = "# Title\n\nIntro\nUsage\n";
+        let after = "# Title\n\n[![Token Efficiency](url)](link)\n\nIntro\nUsage v2\n";
+        assert_eq!(
+            diff_lines(before, after),
+            ["+ [![Token Efficiency](url)](link)", "+", "- Usage", "+ Usage v2"]
+        );
+        assert!(diff_lines(before, before).is_empty());
+    }
+
+    #[test]
+    fn test_with_tests_reappends_test_items() {
+        let src = "fn a() {}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn t() {}\n}\n";
+        let (production, tests) = tokens::split_test_items(src);
+        assert_eq!(tests.len(), 1);
+        assert_eq!(production.trim_end(), "fn a() {}");
+        assert_eq!(
+            with_tests("fn a(){}".to_string(), &tests),
+            "fn a(){}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn t() {}\n}\n"
+        );
+        assert_eq!(with_tests("fn a(){}".to_string(), &[]), "fn a(){}");
+    }
+
+    #[test]
+    fn test_constraints_appended_as_numbered_list() {
+        let rust = templates::rewrite_prompt("Rust");
+        assert_eq!(constraints(&[], false).prompt(Language::Rust).unwrap(), rust);
+        let prompt = constraints(&["keep doc comments", "no new deps"], false)
+            .prompt(Language::Rust)
+            .unwrap();
+        assert!(prompt.starts_with(&rust));
+        assert!(prompt.ends_with("\n1. keep doc comments\n2. no new deps"));
+    }
+
+    #[test]
+    fn test_language_prompt_and_extensions() {
+        let python = constraints(&[], false).prompt(Language::Python).unwrap();
+        assert!(python.starts_with("You are a Python code optimizer"));
+        assert!(python.contains("comprehensions") && !python.contains("Result/Option"));
+        assert!(Language::Typescript.matches(Path::new("web/app.tsx")));
+        assert!(!Language::Python.matches(Path::new("src/main.rs")));
+        let no_default = constraints(&["keep names"], true).prompt(Language::Go).unwrap();
+        assert!(no_default.starts_with("Rewrite the given Go code"));
+    }
+
+    #[test]
+    fn test_splice_replaces_line_ranges() {
+        let original = "use a;\nfn x() {\n    1\n}\n\nfn y() {}\n";
+        let chunks = [(1, 4, "fn x() { 1 }".to_string()), (5, 6, "fn y() {}".to_string())];
+        assert_eq!(splice(original, &chunks), "use a;\nfn x() { 1 }\n\nfn y() {}\n");
+        assert_eq!(splice("fn z() {}", &[]), "fn z() {}");
+    }
+
+    #[test]
+    fn test_no_default_constraints_replaces_prompt() {
+        let prompt = constraints(&["keep doc comments"], true).prompt(Language::Rust).unwrap();
+        assert!(!prompt.contains("token efficiency"));
+        assert!(prompt.contains("\n1. keep doc comments\n"));
+        assert!(constraints(&[], true).prompt(Language::Rust).is_err());
+    }
+
+    #[test]
+    fn test_rules_file_frontmatter_and_body() {
+        let rules = parse_rules(
+            "+++\nconstraints = [\n  \"keep doc comments\",\n  \"no new deps\",\n]\n+++\n\n- Use `tracing::info!`, not `println!`\n",
+        )
+        .unwrap();
+        assert_eq!(rules.constraints, ["keep doc comments", "no new deps"]);
+        assert!(!rules.no_default_constraints);
+        assert_eq!(rules.body, "- Use `tracing::info!`, not `println!`");
+
+        let prompt = constraints(&["be brief"], false).compose(&rules, Language::Rust).unwrap();
+        assert!(prompt.contains("\n1. be brief\n2. keep doc comments\n3. no new deps\n"));
+        assert!(
+            prompt.ends_with("Additional project rules:\n- Use `tracing::info!`, not `println!`")
+        );
+
+        assert_eq!(parse_rules("Plain rules only\n").unwrap().body, "Plain rules only");
+        assert!(parse_rules("+++\nstyle = \"x\"\n+++\n").is_err());
+        assert!(parse_rules(templates::RULES_TEMPLATE).is_ok());
+
+        let rules = parse_rules(
+            "+++\nconstraints = ['no \"magic\"', \"\"\"keep [a, ]\nas is\"\"\"] # why\nno_default_constraints = true\n+++\n",
+        )
+        .unwrap();
+        assert_eq!(rules.constraints, ["no \"magic\"", "keep [a, ]\nas is"]);
+        assert!(rules.no_default_constraints);
+    }
+}
@@ -1,9 +1,11 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::io::BufRead;
 use std::process::{Command, Stdio};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use clap::Args;
 use serde::Deserialize;
+use serde_json::{Value, json};
 
 use crate::tokens;
 
@@ -62,6 +64,8 @@ struct Diagnostic {
     level: String,
     code: Option<Code>,
     spans: Vec<Span>,
+    #[serde(default)]
+    children: Vec<Self>,
 }
 
 #[derive(Deserialize)]
@@ -72,20 +76,83 @@ struct Code {
 #[derive(Deserialize)]
 struct Span {
     file_name: String,
+    byte_start: usize,
+    byte_end: usize,
     line_start: u32,
     is_primary: bool,
+    suggested_replacement: Option<String>,
 }
 
 struct Hint {
     line: u32,
     lint: String,
     message: String,
+    /// Tokens clippy's suggested fix removes; `None` when clippy offers no replacement
+    saves: Option<usize>,
 }
 
-pub fn run(deep: bool) -> Result<()> {
+/// Tokens saved by applying every replacement clippy suggests for `diag`: tokens of each
+/// replaced span in `sources` minus tokens of its replacement. `None` without a suggestion.
+fn measured_savings(diag: &Diagnostic, sources: &HashMap<String, &str>) -> Result<Option<usize>> {
+    let mut saved = None;
+    for span in diag.children.iter().flat_map(|c| &c.spans) {
+        let Some(new) = &span.suggested_replacement else { continue };
+        let Some(old) = sources
+            .get(&normalize(&span.file_name))
+            .and_then(|src| src.get(span.byte_start..span.byte_end))
+        else {
+            continue;
+        };
+        let diff = tokens::count_tokens(old)?.saturating_sub(tokens::count_tokens(new)?);
+        *saved.get_or_insert(0) += diff;
+    }
+    Ok(saved)
+}
+
+/// Clippy lint groups silenced by `--auto-fix` so only the flagged lints get fixed
+const LINT_GROUPS: &[&str] =
+    &["clippy::all", "clippy::pedantic", "clippy::nursery", "clippy::restriction", "clippy::cargo"];
+
+/// Narrow the clippy hints to some lint categories, matched by substring of the lint name
+#[derive(Args, Clone, Default)]
+pub struct LintFilter {
+    /// Only show lints whose name contains this (repeatable, e.g. "redundant")
+    #[arg(long, value_name = "NAME")]
+    pub lint_only: Vec<String>,
+    /// Hide lints whose name contains this (repeatable)
+    #[arg(long, value_name = "NAME")]
+    pub exclude_lint: Vec<String>,
+}
+
+impl LintFilter {
+    fn matches(&self, lint: &str) -> bool {
+        (self.lint_only.is_empty() || self.lint_only.iter().any(|p| lint.contains(p.as_str())))
+            && !self.exclude_lint.iter().any(|p| lint.contains(p.as_str()))
+    }
+}
+
+/// Rule ID for blocks duplicated across files in SARIF output
+const CROSS_FILE_RULE: &str = "cargo-syntax/cross-file-duplicate";
+/// Rule ID for near-identical functions within one file in SARIF output
+const NEAR_DUPLICATE_RULE: &str = "cargo-syntax/near-duplicate";
+
+/// With `auto_fix`, apply clippy's fixes for just the lints that were shown.
+/// With `output_sarif`, also write every finding as a SARIF 2.1 log for code scanning.
+/// With `json` (which implies `deep`), print the hints and duplicates as one JSON object.
+pub fn run(
+    deep: bool,
+    auto_fix: bool,
+    deep_opts: super::deep::DeepOptions,
+    output_sarif: Option<&str>,
+    filter: &LintFilter,
+    json: bool,
+    by_impact: bool,
+) -> Result<()> {
     let stats = tokens::scan_project()?;
 
-    println!("Analyzing code for token-efficiency improvements...\n");
+    if !json {
+        println!("Analyzing code for token-efficiency improvements...\n");
+    }
 
     let mut args = vec![
         "clippy".to_string(),
@@ -105,6 +172,8 @@ pub fn run(deep: bool) -> Result<()> {
 
     let mut suggestions: HashMap<String, Vec<Hint>> = HashMap::new();
     let mut seen = HashSet::new();
+    let sources: HashMap<String, &str> =
+        stats.files.iter().map(|f| (normalize(&f.path), f.content.as_str())).collect();
 
     for line in output.stdout.lines() {
         let line = line?;
@@ -116,12 +185,15 @@ pub fn run(deep: bool) -> Result<()> {
         if diag.level != "warning" && diag.level != "error" {
             continue;
         }
-        let Some(code) = diag.code else { continue };
+        let Some(code) = &diag.code else { continue };
         if !code.code.starts_with("clippy::") {
             continue;
         }
 
         let lint = code.code.trim_start_matches("clippy::").to_string();
+        if !filter.matches(&lint) {
+            continue;
+        }
 
         let Some(span) = diag.spans.iter().find(|s| s.is_primary) else { continue };
 
@@ -135,38 +207,212 @@ pub fn run(deep: bool) -> Result<()> {
             continue;
         }
 
+        let saves = if by_impact { measured_savings(&diag, &sources)? } else { None };
         suggestions.entry(file).or_default().push(Hint {
             line: span.line_start,
             lint,
             message: diag.message,
+            saves,
         });
     }
 
-    if suggestions.is_empty() {
-        println!("No suggestions — code already follows token-efficient patterns.");
+    let mut sarif_results =
+        if output_sarif.is_some() { hint_results(&suggestions) } else { vec![] };
+    let lints: BTreeSet<String> = suggestions.values().flatten().map(|h| h.lint.clone()).collect();
+
+    if json {
+        let result = super::deep::analyze(&stats, deep_opts);
+        if output_sarif.is_some() {
+            sarif_results.extend(deep_results(&result, &stats));
+        }
+        let mut output = super::deep::results_json(&result, &stats);
+        output["clippy_hints"] = hints_json(&suggestions);
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        let hint_count = print_hints(suggestions, &stats, by_impact);
+
+        if auto_fix && !lints.is_empty() {
+            fix_lints(&lints, stats.total_tokens)?;
+        }
+
         if deep {
             println!();
-            let result = super::deep::run(&stats);
+            tokens::separator(70);
+            println!("Deep analysis (cross-file duplication)\n");
+            let result = super::deep::analyze(&stats, deep_opts);
+            if output_sarif.is_some() {
+                sarif_results.extend(deep_results(&result, &stats));
+            }
             if result.total_savings > 0 {
                 super::deep::print_results(&result, &stats);
             } else {
                 println!("Deep analysis: no cross-file duplicates found.");
             }
+
+            println!();
+            println!(
+                "Combined: {hint_count} clippy hint(s), ~{} tokens saveable from deduplication",
+                result.total_savings
+            );
         }
-        return Ok(());
     }
 
+    if let Some(path) = output_sarif {
+        let count = sarif_results.len();
+        let log = serde_json::to_string_pretty(&sarif_log(&sarif_results))?;
+        std::fs::write(path, log + "\n").with_context(|| format!("failed to write {path}"))?;
+        if json {
+            eprintln!("Wrote {count} SARIF result(s) to {path}");
+        } else {
+            println!("\nWrote {count} SARIF result(s) to {path}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Print hints grouped by file, or a note when there are none; returns the hint count
+fn print_hints(
+    suggestions: HashMap<String, Vec<Hint>>,
+    stats: &tokens::ProjectStats,
+    by_impact: bool,
+) -> usize {
+    if suggestions.is_empty() {
+        println!("No suggestions — code already follows token-efficient patterns.");
+        0
+    } else {
+        print_suggestions(suggestions, stats, by_impact)
+    }
+}
+
+/// `[{file, line, lint, message}]`, sorted by file and line
+fn hints_json(suggestions: &HashMap<String, Vec<Hint>>) -> Value {
+    let mut hints: Vec<(&String, &Hint)> =
+        suggestions.iter().flat_map(|(file, hints)| hints.iter().map(move |h| (file, h))).collect();
+    hints.sort_by_key(|(file, h)| (*file, h.line));
+    hints
+        .into_iter()
+        .map(|(file, h)| json!({ "file": file, "line": h.line, "lint": h.lint, "message": h.message }))
+        .collect()
+}
+
+fn hint_results(suggestions: &HashMap<String, Vec<Hint>>) -> Vec<Value> {
+    let mut results: Vec<Value> = suggestions
+        .iter()
+        .flat_map(|(file, hints)| {
+            hints.iter().map(move |h| {
+                let rule = format!("clippy::{}", h.lint);
+                sarif_result(&rule, "warning", &h.message, &[(file, h.line as usize)])
+            })
+        })
+        .collect();
+    results.sort_by_key(|r| r["locations"][0].to_string());
+    results
+}
+
+fn deep_results(result: &super::deep::DeepResult, stats: &tokens::ProjectStats) -> Vec<Value> {
+    let path = |fi: usize| normalize(&stats.files[fi].path);
+    let clusters = result.clusters.iter().map(|c| {
+        let span = c.occurrences[0].2 - c.occurrences[0].1 + 1;
+        let message = format!(
+            "{span}-line block duplicated in {} files (~{} tokens saveable)",
+            c.occurrences.len(),
+            super::deep::estimate_savings(c)
+        );
+        let locations: Vec<(String, usize)> =
+            c.occurrences.iter().map(|(fi, start, _)| (path(*fi), start + 1)).collect();
+        let locations: Vec<(&str, usize)> =
+            locations.iter().map(|(f, l)| (f.as_str(), *l)).collect();
+        sarif_result(CROSS_FILE_RULE, "note", &message, &locations)
+    });
+    let near = result.near_dupes.iter().map(|nd| {
+        let message = format!(
+            "`{}` ≈ `{}` (line {}), differ by ~{} tokens",
+            nd.fn_a.0,
+            nd.fn_b.0,
+            nd.fn_b.1 + 1,
+            nd.savings
+        );
+        sarif_result(NEAR_DUPLICATE_RULE, "note", &message, &[(&path(nd.file_idx), nd.fn_a.1 + 1)])
+    });
+    clusters.chain(near).collect()
+}
+
+/// One SARIF `result`; `locations` are (file, 1-based line)
+fn sarif_result(rule_id: &str, level: &str, message: &str, locations: &[(&str, usize)]) -> Value {
+    let locations: Vec<Value> = locations
+        .iter()
+        .map(|(file, line)| {
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": file },
+                    "region": { "startLine": line }
+                }
+            })
+        })
+        .collect();
+    json!({
+        "ruleId": rule_id,
+        "level": level,
+        "message": { "text": message },
+        "locations": locations
+    })
+}
+
+/// Wrap results in a SARIF 2.1 log, declaring each distinct rule once
+fn sarif_log(results: &[Value]) -> Value {
+    let rule_ids: BTreeSet<&str> = results.iter().filter_map(|r| r["ruleId"].as_str()).collect();
+    let rules: Vec<Value> = rule_ids
+        .into_iter()
+        .map(|id| match id.strip_prefix("clippy::") {
+            Some(lint) => json!({
+                "id": id,
+                "helpUri": format!("https://rust-lang.github.io/rust-clippy/master/index.html#{lint}")
+            }),
+            None => json!({ "id": id }),
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "cargo-syntax",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+/// Print hints grouped by file and return the number of hints
+fn print_suggestions(
+    suggestions: HashMap<String, Vec<Hint>>,
+    stats: &tokens::ProjectStats,
+    by_impact: bool,
+) -> usize {
     let ratio_map: HashMap<String, f64> =
         stats.files.iter().map(|f| (normalize(&f.path), f.ratio)).collect();
 
     let mut files: Vec<(String, Vec<Hint>)> = suggestions.into_iter().collect();
-    files.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+    if by_impact {
+        sort_by_impact(&mut files);
+    } else {
+        files.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+        for (_, hints) in &mut files {
+            hints.sort_by_key(|h| h.line);
+        }
+    }
 
     let total: usize = files.iter().map(|(_, v)| v.len()).sum();
     let file_count = files.len();
+    let mut total_savings = 0;
 
-    for (file, hints) in &mut files {
-        hints.sort_by_key(|h| h.line);
+    for (file, hints) in &files {
         let count = hints.len();
         let label = if count == 1 { "suggestion" } else { "suggestions" };
 
@@ -175,35 +421,83 @@ pub fn run(deep: bool) -> Result<()> {
             .find(|(k, _)| *k == file || k.ends_with(&format!("/{file}")))
             .map(|(_, v)| *v);
 
+        let savings = file_savings(hints);
+        total_savings += savings;
+        let impact = if by_impact { format!(", ~{savings} tokens") } else { String::new() };
+
         match ratio {
-            Some(r) => println!("{file}  ({count} {label}, T/L: {r:.1})"),
-            None => println!("{file}  ({count} {label})"),
+            Some(r) => println!("{file}  ({count} {label}{impact}, T/L: {r:.1})"),
+            None => println!("{file}  ({count} {label}{impact})"),
         }
 
         for hint in hints {
-            println!("  line {:>4}  {:<38}  {}", hint.line, hint.lint, hint.message);
+            if by_impact {
+                let saves = hint.saves.map_or_else(|| "-".to_string(), |n| format!("~{n}"));
+                println!("  line {:>4}  {:<38} {saves:>4}  {}", hint.line, hint.lint, hint.message);
+            } else {
+                println!("  line {:>4}  {:<38}  {}", hint.line, hint.lint, hint.message);
+            }
         }
         println!();
     }
 
     tokens::separator(70);
+    if by_impact {
+        println!("Measured savings: ~{total_savings} tokens if every suggested fix is applied");
+    }
     println!(
-        "{total} suggestion(s) across {file_count} file(s)\nRun `cargo syntax fix` to auto-apply all fixable suggestions."
+        "{total} suggestion(s) across {file_count} file(s)\nRun `cargo syntax suggest --auto-fix` to apply just these, or `cargo syntax fix` for all fixable warnings."
     );
 
-    if deep {
-        println!();
-        let result = super::deep::run(&stats);
-        if result.total_savings > 0 {
-            super::deep::print_results(&result, &stats);
-        } else {
-            println!("Deep analysis: no cross-file duplicates found.");
-        }
+    total
+}
+
+fn file_savings(hints: &[Hint]) -> usize {
+    hints.iter().filter_map(|h| h.saves).sum()
+}
+
+/// Largest measured savings first: files by their total, hints within each file by their own
+fn sort_by_impact(files: &mut [(String, Vec<Hint>)]) {
+    for (_, hints) in files.iter_mut() {
+        hints.sort_by_key(|h| (std::cmp::Reverse(h.saves), h.line));
     }
+    files.sort_by_key(|(file, hints)| (std::cmp::Reverse(file_savings(hints)), file.clone()));
+}
 
+/// Run `clippy --fix` with every other lint allowed, then report the token delta
+fn fix_lints(lints: &BTreeSet<String>, before: usize) -> Result<()> {
+    println!();
+    println!("Running clippy --fix for {} lint(s)...", lints.len());
+    Command::new("cargo")
+        .args(fix_args(lints))
+        .status()
+        .context("failed to run cargo clippy --fix")?;
+
+    let after = tokens::scan_project()?.total_tokens;
+    let diff = before as isize - after as isize;
+    println!("Tokens: {before} → {after} ({:+.1}%)", -tokens::pct_delta(diff, before));
     Ok(())
 }
 
+/// Allow every clippy group and rustc's `unused` lints, then re-warn just `lints`
+fn fix_args(lints: &BTreeSet<String>) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "clippy",
+        "--fix",
+        "--allow-dirty",
+        "--allow-no-vcs",
+        "--all-targets",
+        "--",
+        "-A",
+        "unused",
+    ]
+    .map(String::from)
+    .into();
+    args.extend(LINT_GROUPS.iter().flat_map(|&g| ["-A".to_string(), g.to_string()]));
+    args.extend(lints.iter().flat_map(|l| ["-W".to_string(), format!("clippy::{l}")]));
+    args
+}
+
 fn normalize(path: &str) -> String {
     path.replace('\\', "/").trim_start_matches("./").to_string()
 }
@@ -231,4 +525,98 @@ mod tests {
     fn test_normalize_dotslash_windows() {
         assert_eq!(normalize(".\\src\\main.rs"), "src/main.rs");
     }
+
+    #[test]
+    fn test_lint_filter() {
+        let filter = LintFilter {
+            lint_only: vec!["redundant".to_string(), "needless_return".to_string()],
+            exclude_lint: vec!["closure".to_string()],
+        };
+        assert!(filter.matches("redundant_clone"));
+        assert!(filter.matches("needless_return"));
+        assert!(!filter.matches("redundant_closure"));
+        assert!(!filter.matches("needless_borrow"));
+        assert!(LintFilter::default().matches("needless_borrow"));
+    }
+
+    #[test]
+    fn test_sarif_log_declares_each_rule_once() {
+        let results = vec![
+            sarif_result("clippy::needless_return", "warning", "unneeded `return`", &[("a.rs", 3)]),
+            sarif_result("clippy::needless_return", "warning", "unneeded `return`", &[("b.rs", 9)]),
+            sarif_result(CROSS_FILE_RULE, "note", "dup", &[("a.rs", 1), ("b.rs", 5)]),
+        ];
+        let log = sarif_log(&results);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 2);
+        let dup = &run["results"][2];
+        assert_eq!(dup["locations"][1]["physicalLocation"]["artifactLocation"]["uri"], "b.rs");
+        assert_eq!(dup["locations"][1]["physicalLocation"]["region"]["startLine"], 5);
+    }
+
+    #[test]
+    fn test_fix_args_warn_after_allow() {
+        let lints = BTreeSet::from(["needless_return".to_string()]);
+        let args = fix_args(&lints);
+        let allow_all = args.iter().position(|a| a == "clippy::all").unwrap();
+        let warn = args.iter().position(|a| a == "clippy::needless_return").unwrap();
+        assert!(allow_all < warn);
+        assert_eq!(args[warn - 1], "-W");
+    }
+
+    #[test]
+    fn test_sort_by_impact() {
+        let hint = |line, lint: &str, saves| Hint {
+            line,
+            lint: lint.to_string(),
+            message: String::new(),
+            saves,
+        };
+        let mut files = vec![
+            (
+                "a.rs".to_string(),
+                vec![hint(1, "needless_return", Some(2)), hint(2, "use_self", None)],
+            ),
+            (
+                "b.rs".to_string(),
+                vec![hint(3, "needless_borrow", Some(1)), hint(9, "manual_find", Some(12))],
+            ),
+        ];
+        sort_by_impact(&mut files);
+        assert_eq!(files[0].0, "b.rs");
+        assert_eq!(files[0].1[0].lint, "manual_find");
+        assert_eq!(file_savings(&files[1].1), 2);
+        assert_eq!(files[1].1[1].saves, None);
+    }
+
+    #[test]
+    fn test_measured_savings() {
+        let diag = |spans| Diagnostic {
+            message: String::new(),
+            level: "help".to_string(),
+            code: None,
+            spans,
+            children: vec![],
+        };
+        let span = |byte_end, replacement: Option<&str>| Span {
+            file_name: "./src/a.rs".to_string(),
+            byte_start: 0,
+            byte_end,
+            line_start: 1,
+            is_primary: true,
+            suggested_replacement: replacement.map(str::to_string),
+        };
+        let src = "return value + 1;";
+        let sources = HashMap::from([("src/a.rs".to_string(), src)]);
+
+        let mut warning = diag(vec![]);
+        warning.children = vec![diag(vec![span(src.len(), Some("value + 1"))])];
+        let expected =
+            tokens::count_tokens(src).unwrap() - tokens::count_tokens("value + 1").unwrap();
+        assert_eq!(measured_savings(&warning, &sources).unwrap(), Some(expected));
+
+        warning.children = vec![diag(vec![span(src.len(), None)])];
+        assert_eq!(measured_savings(&warning, &sources).unwrap(), None);
+    }
 }
@@ -1,23 +1,55 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use notify::{EventKind, RecursiveMode, Watcher};
 
 use crate::tokens;
 
-pub fn run(n: usize) -> Result<()> {
+/// Share of total tokens that marks the Pareto line in `--cumulative`
+const PARETO_PCT: f64 = 80.0;
+
+/// How often `--watch` checks for a terminal resize while no files change
+const POLL: Duration = Duration::from_millis(200);
+
+/// Editors save in several steps; events closer together than this become one redraw
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+pub fn run(n: usize, interactive: bool, cumulative: bool, watch: bool, model: &str) -> Result<()> {
+    if watch {
+        return watch_table(n);
+    }
     let stats = tokens::scan_project_sorted()?;
+    let show = print_table(&stats, n, cumulative);
+
+    if interactive {
+        pick_and_rewrite(&stats.files[..show], model)?;
+    }
 
+    Ok(())
+}
+
+/// Print the `n` heaviest files of `stats`, which must be sorted by tokens descending.
+/// Returns how many rows were shown.
+pub fn print_table(stats: &tokens::ProjectStats, n: usize, cumulative: bool) -> usize {
     let show = n.min(stats.files.len());
 
     println!("Top {show} most token-heavy files:");
     println!();
-    println!(
-        "{:<4} {:<50} {:>6} {:>8} {:>6} {:>7}",
-        "#", "File", "Lines", "Tokens", "T/L", "% Tot"
-    );
-    println!("{}", "-".repeat(84));
+    let width = if cumulative { 92 } else { 84 };
+    print!("{:<4} {:<50} {:>6} {:>8} {:>6} {:>7}", "#", "File", "Lines", "Tokens", "T/L", "% Tot");
+    println!("{}", if cumulative { "   Cum %" } else { "" });
+    println!("{}", "-".repeat(width));
 
+    let mut running = 0;
     for (i, f) in stats.files.iter().take(show).enumerate() {
         let pct = tokens::pct(f.tokens, stats.total_tokens);
-        println!(
+        print!(
             "{:<4} {:<50} {:>6} {:>8} {:>5.1} {:>6.1}%",
             i + 1,
             f.path,
@@ -26,13 +58,200 @@ pub fn run(n: usize) -> Result<()> {
             f.ratio,
             pct
         );
+        if !cumulative {
+            println!();
+            continue;
+        }
+
+        let before = tokens::pct(running, stats.total_tokens);
+        running += f.tokens;
+        let after = tokens::pct(running, stats.total_tokens);
+        println!(" {after:>6.1}%");
+        if before < PARETO_PCT && after >= PARETO_PCT {
+            let label = format!(" {PARETO_PCT:.0}% of tokens in {} file(s) ", i + 1);
+            println!("{label:-^width$}");
+        }
     }
 
     let top_tokens: usize = stats.files.iter().take(show).map(|f| f.tokens).sum();
     let top_pct = tokens::pct(top_tokens, stats.total_tokens);
 
-    println!("{}", "-".repeat(84));
+    println!("{}", "-".repeat(width));
     println!("Top {show} = {top_tokens} tokens ({top_pct:.1}% of {} total)", stats.total_tokens);
+    show
+}
 
-    Ok(())
+/// Let the user cherry-pick files from the table to rewrite until they quit
+fn pick_and_rewrite(files: &[tokens::FileStats], model: &str) -> Result<()> {
+    loop {
+        println!();
+        let input = tokens::ask_accept("Enter file number to rewrite (or q to quit):")?;
+        if input.is_empty() || input.eq_ignore_ascii_case("q") {
+            return Ok(());
+        }
+
+        let Some(f) = input.parse::<usize>().ok().and_then(|i| files.get(i.checked_sub(1)?)) else {
+            println!("Invalid selection: {input} (expected 1-{})", files.len());
+            continue;
+        };
+
+        println!();
+        if let Err(e) = super::rewrite::run(
+            &f.path,
+            model,
+            None,
+            false,
+            super::rewrite::SizeLimits::default(),
+            &super::rewrite::Constraints::default(),
+            super::rewrite::RewriteMode::default(),
+        ) {
+            eprintln!("Rewrite of {} failed: {e:#}", f.path);
+        }
+    }
+}
+
+pub(super) enum Wake {
+    Changed,
+    Resized,
+}
+
+/// Redraw the table in place whenever a `.rs` file changes, until Ctrl-C
+fn watch_table(n: usize) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+    let resized = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGWINCH, Arc::clone(&resized))?;
+
+    let mut stats = tokens::scan_project_sorted()?;
+    let mut previous = HashMap::new();
+    let mut drawn = 0;
+    loop {
+        let table = render_watch(&stats, n, &previous);
+        if drawn > 0 {
+            // Back over the last render and clear everything below it
+            print!("\x1b[{drawn}A\x1b[J");
+        }
+        print!("{table}");
+        io::stdout().flush()?;
+        drawn = table.lines().count();
+
+        match wait_for_change(&rx, &resized, is_rs)? {
+            Wake::Changed => {
+                previous = stats.files.iter().map(|f| (f.path.clone(), f.tokens)).collect();
+                stats = tokens::scan_project_sorted()?;
+            }
+            Wake::Resized => {
+                // Wrapped lines make the cursor math unreliable, so start from a clean screen
+                print!("\x1b[2J\x1b[H");
+                drawn = 0;
+            }
+        }
+    }
+}
+
+/// Blocks until a watched file for which `relevant` holds changes, or the terminal is resized
+pub(super) fn wait_for_change(
+    rx: &Receiver<notify::Result<notify::Event>>,
+    resized: &AtomicBool,
+    relevant: fn(&Path) -> bool,
+) -> Result<Wake> {
+    loop {
+        if resized.swap(false, Ordering::Relaxed) {
+            return Ok(Wake::Resized);
+        }
+        match rx.recv_timeout(POLL) {
+            Ok(event) => {
+                if is_source_change(&event?, relevant) {
+                    while rx.recv_timeout(DEBOUNCE).is_ok() {}
+                    return Ok(Wake::Changed);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("File watcher stopped"),
+        }
+    }
+}
+
+/// Writes to `relevant` files outside `target/`; reads (including our own rescans) don't count
+fn is_source_change(event: &notify::Event, relevant: fn(&Path) -> bool) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|p| {
+            relevant(p) && !p.components().any(|c| c == Component::Normal("target".as_ref()))
+        })
+}
+
+pub(super) fn is_rs(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "rs")
+}
+
+/// The `n` heaviest files with each one's token change since `previous` (empty on the first render)
+fn render_watch(
+    stats: &tokens::ProjectStats,
+    n: usize,
+    previous: &HashMap<String, usize>,
+) -> String {
+    let show = n.min(stats.files.len());
+    let mut out = format!(
+        "Top {show} most token-heavy files (watching for changes, Ctrl-C to stop):\n\n\
+         {:<4} {:<50} {:>6} {:>8} {:>6} {:>7}\n{}\n",
+        "#",
+        "File",
+        "Lines",
+        "Tokens",
+        "T/L",
+        "Δ",
+        "-".repeat(84)
+    );
+    for (i, f) in stats.files.iter().take(show).enumerate() {
+        out.push_str(&format!(
+            "{:<4} {:<50} {:>6} {:>8} {:>5.1} {:>7}\n",
+            i + 1,
+            f.path,
+            f.lines,
+            f.tokens,
+            f.ratio,
+            token_delta(previous, &f.path, f.tokens)
+        ));
+    }
+    let before: usize = previous.values().sum();
+    let total = if previous.is_empty() {
+        String::new()
+    } else {
+        format!(" ({:+})", stats.total_tokens as i64 - before as i64)
+    };
+    out.push_str(&format!(
+        "{}\nProject total: {} tokens{total}\n",
+        "-".repeat(84),
+        stats.total_tokens
+    ));
+    out
+}
+
+/// Blank when unchanged or on the first render, `new` for files that weren't there before
+fn token_delta(previous: &HashMap<String, usize>, path: &str, tokens: usize) -> String {
+    if previous.is_empty() {
+        return String::new();
+    }
+    match previous.get(path) {
+        None => "new".into(),
+        Some(&before) if before == tokens => String::new(),
+        Some(&before) => format!("{:+}", tokens as i64 - before as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_token_delta() {
+        let previous = HashMap::from([("src/a.rs".to_string(), 100)]);
+        assert_eq!(token_delta(&previous, "src/a.rs", 120), "+20");
+        assert_eq!(token_delta(&previous, "src/a.rs", 90), "-10");
+        assert_eq!(token_delta(&previous, "src/a.rs", 100), "");
+        assert_eq!(token_delta(&previous, "src/b.rs", 5), "new");
+        assert_eq!(token_delta(&HashMap::new(), "src/a.rs", 100), "");
+    }
 }
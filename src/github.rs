@@ -0,0 +1,39 @@
+use anyhow::{Context, Result, bail};
+use serde_json::json;
+
+const API_URL: &str = "https://api.github.com";
+
+/// Post `body` as a comment on the pull request this Actions run belongs to
+pub fn post_pr_comment(body: &str) -> Result<()> {
+    let token = std::env::var("GITHUB_TOKEN")
+        .map_err(|_| anyhow::anyhow!("GITHUB_TOKEN not set — required for --pr-comment"))?;
+    let repo = std::env::var("GITHUB_REPOSITORY")
+        .context("GITHUB_REPOSITORY not set — --pr-comment must run inside GitHub Actions")?;
+    let pr = std::env::var("GITHUB_REF").ok().and_then(|r| pr_number(&r)).ok_or_else(|| {
+        anyhow::anyhow!("not a pull request build (GITHUB_REF is not refs/pull/<n>/...)")
+    })?;
+
+    let agent = ureq::Agent::new_with_config(
+        ureq::config::Config::builder().http_status_as_error(false).build(),
+    );
+
+    let mut response = agent
+        .post(&format!("{API_URL}/repos/{repo}/issues/{pr}/comments"))
+        .header("Authorization", &format!("Bearer {token}"))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "cargo-syntax")
+        .send_json(json!({ "body": body }))?;
+
+    let status = response.status();
+    if status != 201 {
+        let body = response.body_mut().read_to_string()?;
+        bail!("GitHub API error (HTTP {status}): {body}");
+    }
+
+    Ok(())
+}
+
+/// `refs/pull/42/merge` -> 42
+fn pr_number(git_ref: &str) -> Option<u64> {
+    git_ref.strip_prefix("refs/pull/")?.split('/').next()?.parse().ok()
+}
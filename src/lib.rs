@@ -1 +1,3 @@
+#[path = "commands/deep.rs"]
+pub mod deep;
 pub mod tokens;
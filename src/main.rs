@@ -1,10 +1,12 @@
 mod commands;
+mod github;
 mod openrouter;
 mod templates;
 pub mod tokens;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use regex::Regex;
 
 #[derive(Parser)]
 #[command(name = "cargo")]
@@ -26,36 +28,197 @@ enum Command {
     Init {
         /// Project name
         name: String,
+        /// Project type to scaffold
+        #[arg(long, value_enum, default_value = "minimal")]
+        template: commands::init::Template,
+        /// Don't generate CLAUDE.md
+        #[arg(long)]
+        no_claude: bool,
+        /// Generate .cursorrules instead of CLAUDE.md
+        #[arg(long)]
+        cursor: bool,
+        /// Create a Cargo workspace with crates in crates/ instead of a single crate
+        #[arg(long, conflicts_with = "template")]
+        workspace: bool,
+        /// Workspace crates to create (comma-separated); "cli" or "*-cli" become binaries
+        #[arg(long, value_delimiter = ',', default_value = "core,cli", requires = "workspace")]
+        members: Vec<String>,
     },
     /// Run strict clippy + fmt checks
-    Check,
+    Check {
+        /// Also run cargo test and cargo doc --no-deps
+        #[arg(long)]
+        strict: bool,
+        /// On failure, run clippy --fix and fmt, then check again
+        #[arg(long)]
+        fix: bool,
+    },
     /// Auto-fix clippy warnings and format code
-    Fix,
+    Fix {
+        /// Only report how many clippy warnings and formatting issues would be fixed
+        #[arg(long)]
+        check: bool,
+    },
     /// Audit token count and lines of code per file
-    Audit,
+    Audit {
+        /// Show code/comment/blank lines per file and comment token overhead
+        #[arg(long)]
+        tokei: bool,
+        /// Output style: table, compact (one line per file), csv, or json
+        #[arg(long, value_enum, default_value = "table")]
+        format: commands::audit::Format,
+        /// Deprecated: use --format json
+        #[arg(long, hide = true, conflicts_with = "format")]
+        json: bool,
+        /// Show the per-file token distribution (percentiles, min/max, std dev)
+        #[arg(long)]
+        percentiles: bool,
+        /// Aggregate tokens by module, directory, or primary git author
+        #[arg(long, value_enum)]
+        group_by: Option<commands::audit::GroupBy>,
+        /// Leave out #[cfg(test)] blocks, #[test] functions and tests/ from every count
+        #[arg(long, conflicts_with = "tests_only")]
+        exclude_tests: bool,
+        /// Count only test code (#[cfg(test)] blocks, #[test] functions and tests/)
+        #[arg(long)]
+        tests_only: bool,
+        /// After the table, also list the N heaviest files as `top` would (0 = off, table format only)
+        #[arg(long, value_name = "N", default_value_t = 0, conflicts_with = "json")]
+        top: usize,
+        /// Skip files marked `// @generated`, `// DO NOT EDIT` or `// This file is autogenerated`
+        #[arg(long)]
+        ignore_generated: bool,
+        /// Token count the grade is computed from
+        #[arg(long, value_enum, default_value = "total")]
+        grade_by: tokens::GradeBy,
+    },
     /// Generate a token efficiency badge for your README
-    Badge,
+    Badge {
+        /// Score the whole project instead of only src/
+        #[arg(long)]
+        all: bool,
+        /// Update the Token Efficiency badge in README.md, or insert one below the title
+        #[arg(long)]
+        update_readme: bool,
+        /// README to update instead of README.md
+        #[arg(long, requires = "update_readme", default_value = "README.md")]
+        readme: String,
+        /// Print only this format (default: Markdown, HTML and RST)
+        #[arg(long, value_enum, conflicts_with = "update_readme")]
+        format: Option<commands::badge::Format>,
+        /// Shields.io badge style, also used by --update-readme
+        #[arg(long, value_enum)]
+        shield_style: Option<commands::badge::ShieldStyle>,
+        /// Token count the grade is computed from
+        #[arg(long, value_enum, default_value = "total")]
+        grade_by: tokens::GradeBy,
+    },
     /// Apply token-efficient configs to an existing project
-    Apply,
+    Apply {
+        /// Overwrite all existing config files with the canonical templates
+        #[arg(long)]
+        force: bool,
+        /// Overwrite only these config files (comma-separated)
+        #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "force")]
+        force_only: Vec<commands::apply::ConfigFile>,
+        /// Overwrite without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Print every change as a diff without writing anything
+        #[arg(long)]
+        preview: bool,
+        /// Don't create or overwrite CLAUDE.md
+        #[arg(long)]
+        no_claude: bool,
+        /// Create .cursorrules instead of CLAUDE.md
+        #[arg(long)]
+        cursor: bool,
+        /// Only verify that the configs match the templates; exit 1 if any are out of date
+        #[arg(long, conflicts_with_all = ["force", "force_only", "yes", "preview"])]
+        check: bool,
+        /// Show how Cargo.toml and .gitignore would change, as diffs, without writing anything
+        #[arg(long, conflicts_with_all = ["force", "force_only", "yes", "preview", "check"])]
+        diff: bool,
+    },
     /// Show the N most token-heavy files
     Top {
         /// Number of files to show (default: 10)
         #[arg(default_value = "10")]
         n: usize,
+        /// Prompt for a file number from the table and rewrite it, until you quit
+        #[arg(long)]
+        interactive: bool,
+        /// Add a running total column and mark where 80% of tokens is reached
+        #[arg(long)]
+        cumulative: bool,
+        /// Keep the table on screen and redraw it whenever a .rs file changes
+        #[arg(long, conflicts_with_all = ["interactive", "cumulative"])]
+        watch: bool,
+        /// OpenRouter model for interactive rewrites (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
+        #[arg(long, requires = "interactive")]
+        model: Option<String>,
     },
     /// Analyze files and suggest token-efficiency improvements
     Suggest {
         /// Also detect cross-file code duplication (no API key needed)
         #[arg(long)]
         deep: bool,
+        /// Only show clippy hints (overrides an earlier --deep)
+        #[arg(long, overrides_with = "deep")]
+        no_deep: bool,
+        /// Apply clippy's fixes for only the lints shown, leaving every other lint alone
+        #[arg(long)]
+        auto_fix: bool,
+        /// Also write the findings as SARIF 2.1 JSON (for GitHub Code Scanning)
+        #[arg(long, value_name = "FILE")]
+        output_sarif: Option<String>,
+        #[command(flatten)]
+        lints: commands::suggest::LintFilter,
+        /// Print clippy hints and --deep duplicates as JSON (requires --deep)
+        #[arg(long, requires = "deep", conflicts_with_all = ["auto_fix", "no_deep"])]
+        json: bool,
+        /// Rank files and hints by estimated token savings instead of hint count
+        #[arg(long, conflicts_with = "json")]
+        by_impact: bool,
+        #[command(flatten)]
+        deep_opts: commands::deep::DeepOptions,
+    },
+    /// Detect duplicated code without an API key
+    Deep {
+        /// Compare functions against another project directory (e.g. "../other-crate")
+        #[arg(long)]
+        cross_project: Option<String>,
+        /// Print the duplicates as JSON for other tools
+        #[arg(long, conflicts_with = "cross_project")]
+        json: bool,
+        /// Also write the duplication graph as Graphviz DOT (render with `dot -Tsvg`)
+        #[arg(long, value_name = "FILE", conflicts_with = "cross_project")]
+        output_dot: Option<String>,
+        #[command(flatten)]
+        opts: commands::deep::DeepOptions,
     },
     /// AI-powered rewrite of a file for token efficiency (via OpenRouter)
     Rewrite {
-        /// Rust file to rewrite
+        /// File to rewrite (.rs unless --language or --no-language-check)
         file: String,
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
+        /// Model to use instead when the file exceeds the primary model's context
+        #[arg(long)]
+        model_fallback: Option<String>,
+        /// Save the original to <file>.orig before writing the rewrite
+        #[arg(long)]
+        inplace_backup: bool,
+        /// Restore <file> from <file>.orig instead of rewriting
+        #[arg(long)]
+        restore: bool,
+        #[command(flatten)]
+        limits: commands::rewrite::SizeLimits,
+        #[command(flatten)]
+        constraints: commands::rewrite::Constraints,
+        #[command(flatten)]
+        mode: commands::rewrite::RewriteMode,
     },
     /// AI-powered review of the top N most token-heavy files (via OpenRouter)
     Review {
@@ -65,6 +228,21 @@ enum Command {
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
+        /// Model to use instead when a file exceeds the primary model's context
+        #[arg(long)]
+        model_fallback: Option<String>,
+        /// Also write the review as a Markdown report to this file
+        #[arg(long)]
+        output: Option<String>,
+        /// Post the Markdown report as a PR comment (needs GITHUB_TOKEN, runs in GitHub Actions)
+        #[arg(long)]
+        pr_comment: bool,
+        /// Review up to N files concurrently (needs the `tokio` feature)
+        #[arg(long, default_value = "1")]
+        parallel: usize,
+        /// Review just this file, whatever its rank (skips the project scan)
+        #[arg(long, conflicts_with_all = ["n", "parallel"])]
+        file: Option<String>,
     },
     /// AI-powered review of uncommitted changes for token efficiency
     Diff {
@@ -79,6 +257,24 @@ enum Command {
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
+        /// Only review changed files matching this glob (e.g. "src/commands/*")
+        #[arg(long)]
+        files: Option<String>,
+        /// Print only the aggregate line (files changed, tokens added, suggestions)
+        #[arg(long, conflicts_with = "fix")]
+        summary: bool,
+        /// Exit with code 1 if any suggestions were made
+        #[arg(long)]
+        fail_on_suggestions: bool,
+        /// Only print the net token change per file, with no AI calls; exit 1 if the total grew
+        #[arg(long, conflicts_with_all = ["fix", "model", "summary", "fail_on_suggestions"])]
+        token_delta_only: bool,
+        /// Print the git diff with `## TOKEN: +N -M` before each hunk, no AI calls
+        #[arg(
+            long,
+            conflicts_with_all = ["fix", "model", "summary", "fail_on_suggestions", "token_delta_only"]
+        )]
+        annotate: bool,
     },
     /// AI-powered code explanation for onboarding and understanding
     Explain {
@@ -88,61 +284,141 @@ enum Command {
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
+        /// Describe every function in the file as a compact lookup table
+        #[arg(long)]
+        all_functions: bool,
+        /// Table style for --all-functions
+        #[arg(long, value_enum, default_value = "table", requires = "all_functions")]
+        format: commands::explain::Format,
+        /// Also explain this branch and summarize the architectural differences
+        #[arg(long, value_name = "BRANCH", conflicts_with = "all_functions")]
+        compare: Option<String>,
+        /// Write each file's explanation as Markdown under this directory (src/a.rs → DIR/src/a.md)
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["all_functions", "compare"])]
+        save_to: Option<String>,
+        /// Also write DIR/index.md linking to every explained file
+        #[arg(long, requires = "save_to")]
+        index: bool,
+        /// Regenerate the Overview and Key Modules sections of CLAUDE.md from the project
+        #[arg(long, conflicts_with_all = ["all_functions", "compare", "save_to"])]
+        update_claude_md: bool,
     },
     /// AI-powered cross-file refactoring analysis for duplication
     Refactor {
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
+        /// Only look for one kind of duplication
+        #[arg(long, value_enum)]
+        pattern: Option<commands::refactor::PatternKind>,
+        /// Generate the shared module and rewritten files for each pattern, and apply them on confirmation
+        #[arg(long)]
+        generate: bool,
+        /// Find duplicates locally with `deep` instead of calling the model (free, no API key)
+        #[arg(long, conflicts_with_all = ["generate", "model"])]
+        dry_run: bool,
     },
     /// Bulk AI-powered rewrite of the most token-heavy files
     Batch {
         /// Number of files to rewrite (default: 5)
         #[arg(default_value = "5")]
         n: usize,
-        /// Run cargo check + cargo test after each rewrite, rollback on failure
-        #[arg(long)]
-        validate: bool,
-        /// Auto-accept rewrites without prompting
-        #[arg(long)]
-        auto: bool,
+        #[command(flatten)]
+        opts: commands::batch::BatchOptions,
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
-    },
-    /// CI/CD mode: enforce token budgets and exit with status code
-    Ci {
-        /// Fail if total tokens exceed this limit
+        /// Model to use instead when a file exceeds the primary model's context
         #[arg(long)]
-        max_tokens: Option<usize>,
-        /// Fail if T/L ratio exceeds this value
+        model_fallback: Option<String>,
+        /// Only consider files whose path matches this regex
         #[arg(long)]
-        max_tl: Option<f64>,
-        /// Fail if efficiency grade is below this (A+, A, B, C, D)
+        file_filter: Option<Regex>,
+        /// Skip files whose path matches this regex
         #[arg(long)]
-        min_grade: Option<String>,
-        /// Output results as JSON
+        exclude_filter: Option<Regex>,
+        /// Skip files not modified since the last batch run that had no failures
         #[arg(long)]
-        json: bool,
+        skip_unchanged: bool,
+        /// Rewrite exactly these files instead of the top N
+        #[arg(long, num_args = 1.., value_name = "PATH", conflicts_with_all = ["n", "file_filter", "exclude_filter"])]
+        only_files: Vec<String>,
+        #[command(flatten)]
+        limits: commands::rewrite::SizeLimits,
+    },
+    /// CI/CD mode: enforce token budgets and exit with status code
+    Ci {
+        #[command(flatten)]
+        opts: commands::ci::CiOptions,
     },
     /// Show token efficiency trends over git history
     History {
         /// Number of commits to analyze (default: 10)
         #[arg(default_value = "10")]
         n: usize,
+        /// Show the token history of a single file instead of the whole project
+        #[arg(long)]
+        file: Option<String>,
+        /// Scan every commit since this tag (ignores n) and show per-version deltas
+        #[arg(long)]
+        since_tag: Option<String>,
+        /// Stop at this tag instead of HEAD
+        #[arg(long)]
+        until_tag: Option<String>,
+        #[command(flatten)]
+        filter: commands::history::CommitFilter,
+        #[command(flatten)]
+        store: commands::history::HistoryStore,
+        #[command(flatten)]
+        graph: commands::history::GraphOptions,
     },
     /// Compare token efficiency between current branch and another
     Compare {
         /// Branch to compare against (e.g. "main", "develop")
-        branch: String,
+        #[arg(required_unless_present = "all_branches")]
+        branch: Option<String>,
+        /// Dimension for the verdict: tokens, ratio (T/L), or grade
+        #[arg(long, value_enum, default_value = "tokens")]
+        metric: commands::compare::Metric,
+        /// List per-file token counts and deltas, largest change first
+        #[arg(long, visible_alias = "file-stats")]
+        diff_files: bool,
+        /// Hide files whose token count changed by less than this
+        #[arg(long, default_value = "0", requires = "diff_files")]
+        min_delta: usize,
+        /// Rank the current branch against every remote branch by T/L ratio
+        #[arg(long, conflicts_with_all = ["branch", "diff_files"])]
+        all_branches: bool,
+        /// With --all-branches, only show the N most and N least efficient branches
+        #[arg(long, value_name = "N", requires = "all_branches")]
+        top: Option<usize>,
     },
     /// AI-powered test generation for a Rust file
     GenerateTests {
         /// Rust file to generate tests for
-        file: String,
+        #[arg(required_unless_present = "module")]
+        file: Option<String>,
         /// Output file (default: tests/test_<name>.rs)
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "module")]
         output: Option<String>,
+        /// Generate tests for every .rs file in this directory (tests/test_<module>_<file>.rs)
+        #[arg(long, conflicts_with = "file")]
+        module: Option<String>,
+        /// Merge the module's tests into one tests/test_<module>.rs
+        #[arg(long, requires = "module")]
+        combine: bool,
+        /// Skip module files matching this glob (e.g. "mod.rs")
+        #[arg(long, requires = "module")]
+        exclude: Option<glob::Pattern>,
+        /// Print the generated tests without prompting or writing; exit 1 if none were generated
+        #[arg(long)]
+        dry_run: bool,
+        /// Exit 1 if the tests cover less than this % of public functions
+        #[arg(long, value_name = "PCT", value_parser = clap::value_parser!(u8).range(0..=100))]
+        coverage_threshold: Option<u8>,
+        /// Generate proptest property-based tests of invariants instead of example-based tests
+        #[arg(long)]
+        property: bool,
         /// OpenRouter model (default: deepseek/deepseek-chat, override with CARGO_SYNTAX_MODEL)
         #[arg(long)]
         model: Option<String>,
@@ -151,54 +427,298 @@ enum Command {
     Models {
         /// Filter models by name or ID (e.g. "deepseek", "claude", "gemini")
         search: Option<String>,
+        /// Rewrite this file with the recommended models and compare speed, savings, and cost
+        #[arg(long)]
+        benchmark: Option<String>,
+        /// Number of recommended models to benchmark (default: 3)
+        #[arg(long, default_value = "3", requires = "benchmark")]
+        count: usize,
+        /// Show pricing, limits, and supported features of one model
+        #[arg(long, conflicts_with_all = ["search", "benchmark"])]
+        details: Option<String>,
+        /// Show the cost of N input tokens (plus N/4 output) per model, cheapest first
+        #[arg(long, value_name = "TOKENS", conflicts_with_all = ["benchmark", "details"])]
+        cost_estimate: Option<usize>,
+        #[command(flatten)]
+        price: commands::models::PriceFilter,
+        /// Ignore the cached model list (.syntax_cache/models.json) and fetch a fresh one
+        #[arg(long)]
+        refresh: bool,
+        /// Pin rust-toolchain.toml to the latest stable Rust release instead of listing models
+        #[arg(long, conflicts_with_all = ["search", "benchmark", "details", "cost_estimate", "refresh"])]
+        update_toolchain: bool,
     },
 }
 
+/// Log to stderr, filtered by `CARGO_SYNTAX_LOG` or `RUST_LOG` (e.g. `debug`); silent by default
+fn init_logging() {
+    let filter = std::env::var("CARGO_SYNTAX_LOG")
+        .or_else(|_| std::env::var("RUST_LOG"))
+        .unwrap_or_else(|_| "off".to_string());
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// `audit --json` and `ci --json` predate `--format`; they still work but point to the new flag
+fn warn_json_deprecated() {
+    eprintln!("Warning: --json is deprecated, use --format json");
+}
+
 fn main() -> Result<()> {
+    init_logging();
     let Cli::Syntax(args) = Cli::parse();
 
     match args.command {
-        Command::Init { name } => commands::init::run(&name),
-        Command::Check => commands::check::run(),
-        Command::Fix => commands::fix::run(),
-        Command::Audit => commands::audit::run(),
-        Command::Badge => commands::badge::run(),
-        Command::Apply => commands::apply::run(),
-        Command::Top { n } => commands::top::run(n),
-        Command::Suggest { deep } => commands::suggest::run(deep),
-        Command::Rewrite { file, model } => {
+        Command::Init { name, workspace: true, members, no_claude, cursor, .. } => {
+            commands::init::run_workspace(&name, &members, no_claude, cursor)
+        }
+        Command::Init { name, template, no_claude, cursor, .. } => {
+            commands::init::run(&name, template, no_claude, cursor)
+        }
+        Command::Check { strict, fix } => commands::check::run(strict, fix),
+        Command::Fix { check } => commands::fix::run(check),
+        Command::Audit {
+            tokei,
+            format,
+            json,
+            percentiles,
+            group_by,
+            exclude_tests,
+            tests_only,
+            top,
+            ignore_generated,
+            grade_by,
+        } => {
+            let scope = if tests_only {
+                Some(tokens::CodeScope::Tests)
+            } else {
+                exclude_tests.then_some(tokens::CodeScope::Production)
+            };
+            let format = if json {
+                warn_json_deprecated();
+                commands::audit::Format::Json
+            } else {
+                format
+            };
+            let selection = commands::audit::Selection { scope, ignore_generated };
+            commands::audit::run(tokei, format, percentiles, group_by, selection, top, grade_by)
+        }
+        Command::Badge { all, update_readme, readme, format, shield_style, grade_by } => {
+            commands::badge::run(
+                all,
+                update_readme.then_some(readme.as_str()),
+                format,
+                shield_style,
+                grade_by,
+            )
+        }
+        Command::Apply { check: true, .. } => commands::apply::check(),
+        Command::Apply { diff: true, .. } => commands::apply::diff(),
+        Command::Apply { force, force_only, yes, preview, no_claude, cursor, .. } => {
+            commands::apply::run(force, &force_only, yes, preview, no_claude, cursor)
+        }
+        Command::Top { n, interactive, cumulative, watch, model } => {
+            let model = model.unwrap_or_else(tokens::default_model);
+            commands::top::run(n, interactive, cumulative, watch, &model)
+        }
+        Command::Suggest {
+            deep,
+            no_deep,
+            auto_fix,
+            output_sarif,
+            lints,
+            json,
+            by_impact,
+            deep_opts,
+        } => commands::suggest::run(
+            deep && !no_deep,
+            auto_fix,
+            deep_opts,
+            output_sarif.as_deref(),
+            &lints,
+            json,
+            by_impact,
+        ),
+        Command::Deep { cross_project, json, output_dot, opts } => {
+            commands::deep::run(cross_project.as_deref(), opts, json, output_dot.as_deref())
+        }
+        Command::Rewrite { file, restore: true, .. } => commands::rewrite::restore(&file),
+        Command::Rewrite {
+            file,
+            model,
+            model_fallback,
+            inplace_backup,
+            limits,
+            constraints,
+            mode,
+            ..
+        } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::rewrite::run(&file, &model)
+            commands::rewrite::run(
+                &file,
+                &model,
+                model_fallback.as_deref(),
+                inplace_backup,
+                limits,
+                &constraints,
+                mode,
+            )
         }
-        Command::Review { n, model } => {
+        Command::Review { n, model, model_fallback, output, pr_comment, parallel, file } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::review::run(n, &model)
+            commands::review::run(
+                n,
+                &model,
+                model_fallback.as_deref(),
+                output.as_deref(),
+                pr_comment,
+                parallel,
+                file.as_deref(),
+            )
+        }
+        Command::Diff { range, staged, files, token_delta_only: true, .. } => {
+            commands::diff::token_delta(range.as_deref(), staged, files.as_deref())
         }
-        Command::Diff { range, staged, fix, model } => {
+        Command::Diff { range, staged, files, annotate: true, .. } => {
+            commands::diff::annotate(range.as_deref(), staged, files.as_deref())
+        }
+        Command::Diff {
+            range, staged, fix, model, files, summary, fail_on_suggestions, ..
+        } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::diff::run(range.as_deref(), staged, fix, &model)
+            commands::diff::run(
+                range.as_deref(),
+                staged,
+                fix,
+                &model,
+                files.as_deref(),
+                summary,
+                fail_on_suggestions,
+            )
+        }
+        Command::Explain { model, update_claude_md: true, .. } => {
+            commands::explain::update_claude_md(&model.unwrap_or_else(tokens::default_model))
         }
-        Command::Explain { path, model } => {
+        Command::Explain {
+            path, model, all_functions, format, compare, save_to, index, ..
+        } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::explain::run(&path, &model)
+            commands::explain::run(
+                &path,
+                &model,
+                all_functions,
+                format,
+                compare.as_deref(),
+                save_to.as_deref(),
+                index,
+            )
         }
-        Command::Refactor { model } => {
+        Command::Refactor { pattern, dry_run: true, .. } => commands::refactor::dry_run(pattern),
+        Command::Refactor { model, pattern, generate, .. } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::refactor::run(&model)
+            commands::refactor::run(&model, pattern, generate)
         }
-        Command::Batch { n, validate, auto, model } => {
+        Command::Batch {
+            n,
+            opts,
+            model,
+            model_fallback,
+            file_filter,
+            exclude_filter,
+            skip_unchanged,
+            only_files,
+            limits,
+        } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::batch::run(n, validate, auto, &model)
+            let filter = commands::batch::PathFilter {
+                include: file_filter,
+                exclude: exclude_filter,
+                skip_unchanged,
+                only_files,
+            };
+            commands::batch::run(n, &opts, &model, model_fallback.as_deref(), &filter, limits)
         }
-        Command::Ci { max_tokens, max_tl, min_grade, json } => {
-            commands::ci::run(max_tokens, max_tl, min_grade.as_deref(), json)
+        Command::Ci { mut opts } => {
+            if opts.json {
+                warn_json_deprecated();
+                opts.format = commands::ci::Format::Json;
+            }
+            commands::ci::run(&opts)
         }
-        Command::History { n } => commands::history::run(n),
-        Command::Compare { branch } => commands::compare::run(&branch),
-        Command::GenerateTests { file, output, model } => {
+        Command::History { n, file, since_tag, until_tag, filter, store, graph } => {
+            commands::history::run(
+                n,
+                file.as_deref(),
+                since_tag.as_deref(),
+                until_tag.as_deref(),
+                &filter,
+                &store,
+                &graph,
+            )
+        }
+        Command::Compare { all_branches: true, top, .. } => {
+            commands::compare::run_all_branches(top)
+        }
+        Command::Compare { branch: Some(branch), metric, diff_files, min_delta, .. } => {
+            commands::compare::run(&branch, metric, diff_files, min_delta)
+        }
+        Command::Compare { branch: None, .. } => {
+            unreachable!("clap requires <BRANCH> or --all-branches")
+        }
+        Command::GenerateTests {
+            file,
+            output,
+            module,
+            combine,
+            exclude,
+            dry_run,
+            coverage_threshold,
+            property,
+            model,
+        } => {
             let model = model.unwrap_or_else(tokens::default_model);
-            commands::generate_tests::run(&file, output.as_deref(), &model)
+            match (module, file) {
+                (Some(dir), _) => commands::generate_tests::run_module(
+                    &dir,
+                    &model,
+                    combine,
+                    exclude.as_ref(),
+                    dry_run,
+                    coverage_threshold,
+                    property,
+                ),
+                (None, Some(file)) => commands::generate_tests::run(
+                    &file,
+                    output.as_deref(),
+                    &model,
+                    dry_run,
+                    coverage_threshold,
+                    property,
+                ),
+                (None, None) => unreachable!("clap requires <FILE> or --module"),
+            }
+        }
+        Command::Models { update_toolchain: true, .. } => commands::models::update_toolchain(),
+        Command::Models {
+            search,
+            benchmark,
+            count,
+            details,
+            cost_estimate,
+            price,
+            refresh,
+            ..
+        } => {
+            if refresh {
+                openrouter::clear_models_cache()?;
+            }
+            match (details, benchmark) {
+                (Some(id), _) => commands::models::details(&id),
+                (None, Some(file)) => commands::models::benchmark(&file, count),
+                (None, None) => commands::models::run(search.as_deref(), cost_estimate, price),
+            }
         }
-        Command::Models { search } => commands::models::run(search.as_deref()),
     }
 }
@@ -1,9 +1,17 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tracing::{debug, info, warn};
 
 const BASE_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
 const MODELS_URL: &str = "https://openrouter.ai/api/v1/models";
+/// Last `list_models` response, reused until it is older than the TTL
+const MODELS_CACHE: &str = ".syntax_cache/models.json";
+/// Cache lifetime in hours when `CARGO_SYNTAX_MODELS_TTL` is unset
+const DEFAULT_MODELS_TTL_HOURS: u64 = 24;
+/// The primitive types of JSON Schema Draft-7
+const SCHEMA_TYPES: &[&str] =
+    &["object", "array", "string", "integer", "number", "boolean", "null"];
 
 #[derive(Serialize)]
 struct Request {
@@ -28,9 +36,34 @@ struct JsonSchemaWrapper {
     schema: Value,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 struct Message {
     role: String,
+    content: Content,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    /// Content parts, used to mark the system prompt with `cache_control`
+    Parts(Vec<Part>),
+}
+
+#[derive(Serialize)]
+struct Part {
+    r#type: &'static str,
+    text: String,
+    cache_control: CacheControl,
+}
+
+#[derive(Serialize)]
+struct CacheControl {
+    r#type: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
     content: String,
 }
 
@@ -42,7 +75,7 @@ struct Response {
 
 #[derive(Deserialize)]
 struct Choice {
-    message: Message,
+    message: ResponseMessage,
 }
 
 #[derive(Deserialize)]
@@ -50,26 +83,143 @@ struct ApiError {
     message: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Model {
     pub id: String,
     pub name: String,
+    pub description: Option<String>,
     pub context_length: Option<u64>,
     pub pricing: Option<Pricing>,
+    pub top_provider: Option<TopProvider>,
+    #[serde(default)]
+    pub supported_parameters: Vec<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Pricing {
     pub prompt: Option<String>,
     pub completion: Option<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct TopProvider {
+    pub context_length: Option<u64>,
+    pub max_completion_tokens: Option<u64>,
+    pub is_moderated: Option<bool>,
+}
+
+impl Model {
+    /// USD per input token
+    pub fn prompt_price(&self) -> Option<f64> {
+        self.pricing.as_ref()?.prompt.as_ref()?.parse().ok()
+    }
+
+    /// USD per output token
+    pub fn completion_price(&self) -> Option<f64> {
+        self.pricing.as_ref()?.completion.as_ref()?.parse().ok()
+    }
+
+    /// Input is free (`pricing.prompt` parses as 0), as for the `:free` variants
+    pub fn is_free(&self) -> bool {
+        self.prompt_price() == Some(0.0)
+    }
+
+    pub fn context_label(&self) -> String {
+        self.context_length.map_or_else(|| "—".to_string(), |c| c.to_string())
+    }
+
+    /// Input price per million tokens, e.g. `$0.3200`
+    pub fn input_cost_label(&self) -> String {
+        per_million_label(self.prompt_price())
+    }
+
+    /// Output price per million tokens, e.g. `$0.8900`
+    pub fn output_cost_label(&self) -> String {
+        per_million_label(self.completion_price())
+    }
+
+    /// Whether `chat_json`'s `response_format: json_schema` can be used with this model
+    pub fn supports_json_schema(&self) -> bool {
+        self.supports("structured_outputs") || self.supports("response_format")
+    }
+
+    pub fn supports(&self, parameter: &str) -> bool {
+        self.supported_parameters.iter().any(|p| p == parameter)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://openrouter.ai/{}", self.id)
+    }
+}
+
+fn per_million_label(price: Option<f64>) -> String {
+    price.map_or_else(|| "—".to_string(), |v| format!("${:.4}", v * 1e6))
+}
+
 #[derive(Deserialize)]
 struct ModelsResponse {
     data: Vec<Model>,
 }
 
+#[derive(Serialize, Deserialize)]
+struct ModelsCache {
+    /// Seconds since the Unix epoch
+    fetched_at: u64,
+    data: Vec<Model>,
+}
+
+/// All OpenRouter models, from `.syntax_cache/models.json` while it is younger than
+/// `CARGO_SYNTAX_MODELS_TTL` hours (default 24), otherwise fetched and cached again
 pub fn list_models() -> Result<Vec<Model>> {
+    let now = unix_now();
+    if let Some(cache) = read_models_cache()
+        && now.saturating_sub(cache.fetched_at) < models_ttl_hours() * 3600
+    {
+        debug!(age_secs = now - cache.fetched_at, "using cached model list");
+        return Ok(cache.data);
+    }
+
+    let cache = ModelsCache { fetched_at: now, data: fetch_models()? };
+    if let Err(e) = write_models_cache(&cache) {
+        warn!("could not write {MODELS_CACHE}: {e}");
+    }
+    Ok(cache.data)
+}
+
+/// Drop the cached model list so the next `list_models` call fetches a fresh one
+pub fn clear_models_cache() -> Result<()> {
+    match std::fs::remove_file(MODELS_CACHE) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn models_ttl_hours() -> u64 {
+    std::env::var("CARGO_SYNTAX_MODELS_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MODELS_TTL_HOURS)
+}
+
+fn read_models_cache() -> Option<ModelsCache> {
+    let content = std::fs::read_to_string(MODELS_CACHE).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_models_cache(cache: &ModelsCache) -> Result<()> {
+    if let Some(dir) = std::path::Path::new(MODELS_CACHE).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(MODELS_CACHE, serde_json::to_string(cache)?)?;
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn fetch_models() -> Result<Vec<Model>> {
+    info!("fetching model list from OpenRouter");
     let agent = ureq::Agent::new_with_config(
         ureq::config::Config::builder().http_status_as_error(false).build(),
     );
@@ -87,7 +237,25 @@ pub fn list_models() -> Result<Vec<Model>> {
 }
 
 pub fn chat(model: &str, system: &str, prompt: &str) -> Result<String> {
-    chat_with_options(model, system, prompt, None, None)
+    chat_with_options(model, system, prompt, None, None, false)
+}
+
+/// Send `prompts` one after another with the same `system` prompt. Models that need an
+/// explicit breakpoint (Anthropic, Gemini) get the system prompt marked for caching so later
+/// calls reuse it; for the rest this is plain sequential `chat` (OpenAI and DeepSeek cache
+/// repeated prefixes automatically).
+pub fn chat_with_system_cache(
+    model: &str,
+    system: &str,
+    prompts: &[String],
+) -> Vec<Result<String>> {
+    let cache = supports_cache_control(model);
+    prompts.iter().map(|p| chat_with_options(model, system, p, None, None, cache)).collect()
+}
+
+fn supports_cache_control(model: &str) -> bool {
+    let id = model.to_lowercase();
+    id.starts_with("anthropic/") || id.starts_with("google/gemini")
 }
 
 pub fn chat_json<T: serde::de::DeserializeOwned>(
@@ -97,40 +265,131 @@ pub fn chat_json<T: serde::de::DeserializeOwned>(
     schema_name: &str,
     schema: Value,
 ) -> Result<T> {
+    let loose = check_schema(&schema).with_context(|| format!("invalid schema `{schema_name}`"))?;
+    for path in loose {
+        eprintln!(
+            "Warning: schema `{schema_name}` sets additionalProperties: true at {path}, which breaks structured output on some models"
+        );
+    }
+
     let format = ResponseFormat {
         r#type: "json_schema".to_string(),
         json_schema: JsonSchemaWrapper { name: schema_name.to_string(), strict: true, schema },
     };
 
-    let raw = chat_with_options(model, system, prompt, Some(format), Some(4096))?;
+    let raw = chat_with_options(model, system, prompt, Some(format), Some(4096), false)?;
     serde_json::from_str(&raw)
         .map_err(|e| anyhow::anyhow!("Failed to parse structured response: {e}"))
 }
 
+/// Structural Draft-7 checks run before a schema is sent: every subschema is an object with
+/// known `type`s, `properties`/`items` hold schemas, and `required` names declared properties.
+/// Returns the paths of objects with `additionalProperties: true`.
+pub(crate) fn check_schema(schema: &Value) -> Result<Vec<String>> {
+    let mut loose = Vec::new();
+    check_subschema(schema, "$", &mut loose)?;
+    Ok(loose)
+}
+
+fn check_subschema(schema: &Value, path: &str, loose: &mut Vec<String>) -> Result<()> {
+    let Some(obj) = schema.as_object() else { bail!("{path}: schema must be an object") };
+
+    let types: Vec<&Value> = match obj.get("type") {
+        Some(Value::Array(types)) => types.iter().collect(),
+        Some(ty) => vec![ty],
+        None => vec![],
+    };
+    for ty in types {
+        if !ty.as_str().is_some_and(|t| SCHEMA_TYPES.contains(&t)) {
+            bail!("{path}: unknown type {ty}");
+        }
+    }
+    if obj.get("description").is_some_and(|d| !d.is_string()) {
+        bail!("{path}: description must be a string");
+    }
+
+    let properties = match obj.get("properties") {
+        Some(Value::Object(props)) => Some(props),
+        Some(_) => bail!("{path}: properties must be an object"),
+        None => None,
+    };
+    for (name, prop) in properties.into_iter().flatten() {
+        check_subschema(prop, &format!("{path}.{name}"), loose)?;
+    }
+
+    if let Some(required) = obj.get("required") {
+        let Some(required) = required.as_array() else {
+            bail!("{path}: required must be an array")
+        };
+        for name in required {
+            let Some(name) = name.as_str() else {
+                bail!("{path}: required entries must be strings")
+            };
+            if !properties.is_some_and(|props| props.contains_key(name)) {
+                bail!("{path}: required property `{name}` is not declared in properties");
+            }
+        }
+    }
+
+    match obj.get("additionalProperties") {
+        None | Some(Value::Bool(false)) => {}
+        Some(Value::Bool(true)) => loose.push(path.to_string()),
+        Some(extra) => check_subschema(extra, &format!("{path}.additionalProperties"), loose)?,
+    }
+
+    match obj.get("items") {
+        None => {}
+        Some(Value::Array(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                check_subschema(item, &format!("{path}[{i}]"), loose)?;
+            }
+        }
+        Some(items) => check_subschema(items, &format!("{path}[]"), loose)?,
+    }
+
+    if obj.get("enum").is_some_and(|e| e.as_array().is_none_or(Vec::is_empty)) {
+        bail!("{path}: enum must be a non-empty array");
+    }
+    Ok(())
+}
+
 fn chat_with_options(
     model: &str,
     system: &str,
     prompt: &str,
     response_format: Option<ResponseFormat>,
     max_tokens: Option<u32>,
+    cache_system: bool,
 ) -> Result<String> {
     let key = std::env::var("OPENROUTER_API_KEY").map_err(|_| {
         anyhow::anyhow!("OPENROUTER_API_KEY not set — get one at https://openrouter.ai/keys")
     })?;
 
+    let system = if cache_system {
+        Content::Parts(vec![Part {
+            r#type: "text",
+            text: system.to_string(),
+            cache_control: CacheControl { r#type: "ephemeral" },
+        }])
+    } else {
+        Content::Text(system.to_string())
+    };
     let body = Request {
         model: model.to_string(),
         messages: vec![
-            Message { role: "system".to_string(), content: system.to_string() },
-            Message { role: "user".to_string(), content: prompt.to_string() },
+            Message { role: "system".to_string(), content: system },
+            Message { role: "user".to_string(), content: Content::Text(prompt.to_string()) },
         ],
         response_format,
         max_tokens,
     };
 
+    debug!(model, request = %serde_json::to_string(&body).unwrap_or_default(), "sending chat request");
+
     let agent = ureq::Agent::new_with_config(
         ureq::config::Config::builder().http_status_as_error(false).build(),
     );
+    let started = std::time::Instant::now();
 
     let mut response = agent
         .post(BASE_URL)
@@ -139,19 +398,61 @@ fn chat_with_options(
         .send_json(&body)?;
 
     let status = response.status();
+    let raw = response.body_mut().read_to_string()?;
+    debug!(model, %status, response = %raw, "received chat response");
     if status != 200 {
-        let body = response.body_mut().read_to_string()?;
-        bail!("OpenRouter API error (HTTP {status}): {body}");
+        warn!(model, %status, "chat request failed");
+        bail!("OpenRouter API error (HTTP {status}): {raw}");
     }
 
-    let resp: Response = response.body_mut().read_json()?;
+    let resp: Response = serde_json::from_str(&raw)?;
 
     if let Some(err) = resp.error {
+        warn!(model, error = %err.message, "chat request returned an API error");
         bail!("OpenRouter API error: {}", err.message);
     }
 
+    info!(model, elapsed_ms = started.elapsed().as_millis() as u64, "chat request succeeded");
     resp.choices
         .and_then(|c| c.into_iter().next())
         .map(|c| c.message.content)
         .ok_or_else(|| anyhow::anyhow!("Empty response from OpenRouter"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{diff, explain, generate_tests, refactor, review, rewrite};
+    use clap::ValueEnum;
+    use serde_json::json;
+
+    #[test]
+    fn test_every_chat_json_schema_is_valid() {
+        let mut schemas = vec![
+            refactor::generate_schema(),
+            refactor::refactor_schema(None),
+            explain::file_schema(),
+            explain::project_schema(),
+            explain::compare_schema(),
+            explain::functions_schema(),
+            rewrite::explain_schema(),
+            generate_tests::coverage_schema(),
+            diff::diff_schema(),
+            review::review_schema(),
+        ];
+        schemas.extend(
+            refactor::PatternKind::value_variants()
+                .iter()
+                .map(|&p| refactor::refactor_schema(Some(p))),
+        );
+        for schema in &schemas {
+            assert_eq!(check_schema(schema).unwrap(), Vec::<String>::new(), "{schema}");
+        }
+
+        let loose = json!({ "type": "object", "properties": {}, "additionalProperties": true });
+        assert_eq!(check_schema(&loose).unwrap(), ["$"]);
+        let missing = json!({ "type": "object", "properties": {}, "required": ["x"] });
+        assert!(check_schema(&missing).is_err());
+        assert!(check_schema(&json!({ "type": "array", "items": { "type": "text" } })).is_err());
+    }
+}
@@ -66,6 +66,9 @@ pub const GITIGNORE: &str = r#"**/target
 .idea
 *.swp
 *.swo
+*.orig
+.syntax_cache/
+.syntax_batch_last_run.json
 .claude/settings.local.json
 "#;
 
@@ -98,3 +101,204 @@ pub const CLAUDE_MD: &str = r#"# Project Instructions
 - Rust 1.93 (pinned via rust-toolchain.toml)
 - Edition 2024
 "#;
+
+/// Token-efficiency rules per `rewrite --language`, keyed by the language's display name
+const REWRITE_RULES: &[(&str, &str)] = &[
+    (
+        "Rust",
+        r#"- Prefer iterator chains over manual loops - Use ? operator instead of match/unwrap on Result/Option - Inline format args (write `"{x}"` not `"{}", x`) - Remove redundant closures, borrows, lifetimes, clone calls - Use manual_let_else, matches!, and other idiomatic patterns - Collapse collapsible if/else blocks - Remove unnecessary type annotations - Remove comments that restate the code"#,
+    ),
+    (
+        "Python",
+        "- Prefer comprehensions and generator expressions over manual loops - Use f-strings instead of % or str.format - Use unpacking, enumerate and zip instead of index arithmetic - Use built-ins (any, all, sum, min, max) instead of hand-written loops - Drop else after return and needless pass statements - Remove comments that restate the code",
+    ),
+    (
+        "JavaScript",
+        "- Prefer array methods (map, filter, find, some) over manual loops - Use arrow functions, destructuring and template literals - Use optional chaining (?.) and nullish coalescing (??) instead of manual checks - Drop else after return - Remove comments that restate the code",
+    ),
+    (
+        "TypeScript",
+        "- Prefer array methods (map, filter, find, some) over manual loops - Use arrow functions, destructuring and template literals - Use optional chaining (?.) and nullish coalescing (??) instead of manual checks - Remove type annotations that inference already provides - Drop else after return - Remove comments that restate the code",
+    ),
+    (
+        "Go",
+        "- Use short variable declarations (:=) where allowed - Drop else after return - Remove redundant type conversions and unused named results - Prefer range loops over index loops - Remove comments that restate the code",
+    ),
+];
+
+/// Used for a language without its own entry in `REWRITE_RULES`
+const GENERIC_REWRITE_RULES: &str = "- Prefer the language's idiomatic built-ins over hand-written loops - Remove redundant code, conversions and annotations - Collapse nested conditionals - Remove comments that restate the code";
+
+/// `rewrite` system prompt for `language`, e.g. "Rust" or "Python"
+pub fn rewrite_prompt(language: &str) -> String {
+    let rules = REWRITE_RULES
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map_or(GENERIC_REWRITE_RULES, |(_, rules)| rules);
+    format!(
+        "You are a {language} code optimizer focused on token efficiency. Rewrite the given {language} code to minimize token count while preserving identical behavior. Apply these rules: {rules} Return ONLY the rewritten {language} code. No markdown fences, no explanations."
+    )
+}
+
+pub const CURSOR_RULES: &str = r#"You are an expert Rust developer working in this project.
+
+Code style:
+- Write minimal, idiomatic Rust — no unnecessary verbosity
+- Prefer iterator chains over manual loops
+- Use `?` operator instead of manual match/unwrap on Result/Option
+- Prefer `derive` macros over manual trait implementations
+- No comments unless logic is non-obvious
+- No doc comments on private items
+- Use short but descriptive variable names
+- Never use `dbg!()` or `todo!()` — they are denied by clippy
+
+Preferred crates (when applicable):
+- `anyhow` for application error handling (instead of custom error types)
+- `thiserror` for library error types (instead of manual `impl Display + Error`)
+- `serde` + `serde_json` for serialization (instead of manual parsing)
+- `clap` with derive for CLI args (instead of manual parsing)
+- `itertools` for complex iterator chains
+
+Before finishing a change:
+- Run `cargo clippy` — strict token-minimizing lints (see Cargo.toml)
+- Run `cargo fmt` — rustfmt, edition 2024
+- Or run `cargo syntax check` to do both
+
+Toolchain: Rust 1.93 (pinned via rust-toolchain.toml), edition 2024.
+"#;
+
+pub const GITHUB_ACTIONS_CI: &str = r#"name: Token Budget
+
+on:
+  push:
+    branches: [main, master]
+  pull_request:
+
+jobs:
+  token-audit:
+    name: Token Audit
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+
+      - uses: dtolnay/rust-toolchain@stable
+
+      - uses: Swatinem/rust-cache@v2
+
+      - name: Install cargo-syntax
+        run: cargo install --git https://github.com/syntaxai/cargo-syntax
+
+      - name: Token audit
+        run: cargo syntax ci --min-grade C --output-github
+"#;
+
+pub const MAIN_RS: &str = r#"fn main() {
+    println!("Hello, world!");
+}
+"#;
+
+pub const LIB_RS: &str = r#"pub fn add(left: u64, right: u64) -> u64 {
+    left + right
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(add(2, 2), 4);
+    }
+}
+"#;
+
+pub const CLI_MAIN_RS: &str = r#"use anyhow::Result;
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Name to greet
+    #[arg(default_value = "world")]
+    name: String,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    println!("Hello, {}!", cli.name);
+    Ok(())
+}
+"#;
+
+pub const ASYNC_MAIN_RS: &str = r#"use anyhow::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    println!("Hello, world!");
+    Ok(())
+}
+"#;
+
+pub const CLI_DEPS: &str = r#"clap = { version = "4", features = ["derive"] }
+anyhow = "1"
+"#;
+
+pub const ASYNC_DEPS: &str = r#"tokio = { version = "1", features = ["macros", "rt-multi-thread"] }
+anyhow = "1"
+"#;
+
+pub const WORKSPACE_TOML: &str = r#"[workspace]
+resolver = "3"
+members = ["crates/*"]
+"#;
+
+/// Appended to each member's Cargo.toml so it inherits `[workspace.lints.clippy]`
+pub const MEMBER_LINTS: &str = r#"
+[lints]
+workspace = true
+"#;
+
+pub const CLAUDE_MD_WORKSPACE: &str = r#"
+## Workspace Layout
+- The root `Cargo.toml` only holds `[workspace]` and the shared `[workspace.lints.clippy]`
+- Every crate lives in `crates/<name>` and inherits the lints with `[lints] workspace = true`
+- Put logic in library crates; binary crates stay thin and call into them
+- Run `cargo syntax check` from the root to cover every member
+"#;
+
+pub const CLAUDE_MD_LIBRARY: &str = r#"
+## Library Conventions
+- Keep the public API small — only `pub` what callers need
+- Return `thiserror` error types from public functions, not `anyhow`
+- One-line doc comment on every public item
+"#;
+
+pub const CLAUDE_MD_CLI: &str = r#"
+## CLI Conventions
+- Define arguments with `clap` derive on a single `Cli` struct
+- Return `anyhow::Result` from `main` and propagate with `?`
+- Results go to stdout, progress and errors to stderr
+"#;
+
+pub const CLAUDE_MD_ASYNC: &str = r#"
+## Async Conventions
+- Entry point is `#[tokio::main]`
+- Prefer `tokio::task::JoinSet` over manual future bookkeeping
+- Never block inside async code — use `tokio::task::spawn_blocking`
+"#;
+
+/// Example `rewrite --rules-file`. The optional `+++` frontmatter takes `constraints` (an array
+/// of strings added to `--constraint`) and `no_default_constraints`; the rest is appended to the
+/// prompt as-is.
+pub const RULES_TEMPLATE: &str = r#"+++
+constraints = [
+    "do not change public API signatures",
+    "keep doc comments on public items",
+]
+no_default_constraints = false
++++
+
+# Project rules
+- Log with `tracing::info!`, never `println!`
+- Return `anyhow::Result` from fallible functions
+"#;
@@ -1,8 +1,12 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use std::sync::OnceLock;
+
 use anyhow::Result;
-use serde::Deserialize;
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tiktoken_rs::o200k_base;
-use walkdir::WalkDir;
+use tiktoken_rs::{CoreBPE, o200k_base};
 
 pub struct FileStats {
     pub path: String,
@@ -10,95 +14,491 @@ pub struct FileStats {
     pub lines: usize,
     pub tokens: usize,
     pub ratio: f64,
+    pub code_lines: usize,
+    pub comment_lines: usize,
+    pub blank_lines: usize,
+    /// Tokens of the file with its comment lines removed
+    pub effective_tokens: usize,
 }
 
 pub struct ProjectStats {
     pub files: Vec<FileStats>,
     pub total_lines: usize,
     pub total_tokens: usize,
+    /// Sum of `FileStats::effective_tokens`
+    pub effective_tokens: usize,
     pub code_lines: usize,
     pub comment_lines: usize,
     pub blank_lines: usize,
 }
 
-pub fn rust_file_walker() -> impl Iterator<Item = walkdir::DirEntry> {
-    WalkDir::new(".")
-        .into_iter()
+pub fn rust_file_walker() -> impl Iterator<Item = ignore::DirEntry> {
+    rust_file_walker_at(Path::new("."))
+}
+
+/// Walk .rs files under `root`, skipping anything matched by `.gitignore`, `.git/info/exclude`,
+/// the global gitignore or `.syntaxignore`. Hidden files are included; `target/` and `.git/`
+/// are always skipped, even in projects without a `.gitignore`.
+pub fn rust_file_walker_at(root: &Path) -> impl Iterator<Item = ignore::DirEntry> {
+    WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .add_custom_ignore_filename(".syntaxignore")
+        .filter_entry(|e| e.file_name() != "target" && e.file_name() != ".git")
+        .build()
         .filter_map(Result::ok)
-        .filter(|e| !e.path().components().any(|c| c.as_os_str() == "target"))
+        .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
         .filter(|e| e.path().extension().is_some_and(|ext| ext == "rs"))
 }
 
 pub fn scan_project() -> Result<ProjectStats> {
-    let bpe = o200k_base()?;
-    let mut files = Vec::new();
-    let mut total_lines = 0;
-    let mut total_tokens = 0;
-    let mut code_lines = 0;
-    let mut comment_lines = 0;
-    let mut blank_lines = 0;
+    scan_project_at(Path::new("."))
+}
 
-    for entry in rust_file_walker() {
+/// Scan a project rooted at `root`; file paths are reported relative to it
+pub fn scan_project_at(root: &Path) -> Result<ProjectStats> {
+    scan_project_with_walker(root, rust_file_walker_at(root))
+}
+
+/// Measure the files yielded by `walker`, reporting paths relative to `root`.
+/// Unreadable files are skipped with a warning.
+pub fn scan_project_with_walker(
+    root: &Path,
+    walker: impl Iterator<Item = ignore::DirEntry>,
+) -> Result<ProjectStats> {
+    let sources = walker.filter_map(|entry| {
         let file_path = entry.path();
-        let content = match std::fs::read_to_string(file_path) {
-            Ok(c) => c,
+        match std::fs::read_to_string(file_path) {
+            Ok(content) => {
+                let display = file_path.strip_prefix(root).unwrap_or(file_path);
+                Some((display.display().to_string(), content))
+            }
             Err(e) => {
                 eprintln!("Warning: skipping {} ({})", file_path.display(), e);
-                continue;
+                None
             }
-        };
+        }
+    });
+    scan_sources(sources)
+}
 
-        let tokens = bpe.encode_with_special_tokens(&content).len();
-        let lines = content.lines().count();
-        let ratio = ratio(tokens, lines);
+/// Build `ProjectStats` from `(path, content)` pairs without touching the filesystem
+pub fn scan_sources(sources: impl IntoIterator<Item = (String, String)>) -> Result<ProjectStats> {
+    let bpe = bpe()?;
+    let files = sources.into_iter().map(|(path, content)| measure(bpe, path, content)).collect();
+    Ok(ProjectStats::from_files(files))
+}
+
+/// T/L at the A/B grade boundary; `rank_score` treats denser files as more compressible
+const RANK_BASE_RATIO: f64 = 7.0;
 
-        let (code, comments, blanks) = count_line_types(&content);
-        code_lines += code;
-        comment_lines += comments;
-        blank_lines += blanks;
-        total_lines += lines;
-        total_tokens += tokens;
+/// Orderings for `sort_by`, all heaviest first
+impl FileStats {
+    pub fn cmp_by_tokens(a: &Self, b: &Self) -> Ordering {
+        b.tokens.cmp(&a.tokens)
+    }
+
+    pub fn cmp_by_ratio(a: &Self, b: &Self) -> Ordering {
+        b.ratio.total_cmp(&a.ratio)
+    }
 
-        let display = file_path.strip_prefix(".").unwrap_or(file_path).display().to_string();
-        files.push(FileStats { path: display, content, lines, tokens, ratio });
+    pub fn cmp_by_lines(a: &Self, b: &Self) -> Ordering {
+        b.lines.cmp(&a.lines)
     }
 
-    Ok(ProjectStats { files, total_lines, total_tokens, code_lines, comment_lines, blank_lines })
+    pub fn cmp_by_rank(a: &Self, b: &Self) -> Ordering {
+        b.rank_score().total_cmp(&a.rank_score())
+    }
+
+    /// Rewrite priority: tokens, weighted up for dense code (T/L above 7) and for
+    /// comment-heavy files, where a rewrite has the most to trim
+    pub fn rank_score(&self) -> f64 {
+        let commented = self.code_lines + self.comment_lines;
+        let comment_share =
+            if commented > 0 { self.comment_lines as f64 / commented as f64 } else { 0.0 };
+        self.tokens as f64 * (self.ratio / RANK_BASE_RATIO) * (1.0 + comment_share / 2.0)
+    }
 }
 
-pub fn count_tokens(content: &str) -> Result<usize> {
-    let bpe = o200k_base()?;
-    Ok(bpe.encode_with_special_tokens(content).len())
+fn measure(bpe: &CoreBPE, path: String, content: String) -> FileStats {
+    let tokens = bpe.encode_with_special_tokens(&content).len();
+    let lines = content.lines().count();
+    let (code_lines, comment_lines, blank_lines) = count_line_types(&content);
+    let effective_tokens = if comment_lines == 0 {
+        tokens
+    } else {
+        bpe.encode_with_special_tokens(&without_comment_lines(&content)).len()
+    };
+    FileStats {
+        path,
+        ratio: ratio(tokens, lines),
+        content,
+        lines,
+        tokens,
+        code_lines,
+        comment_lines,
+        blank_lines,
+        effective_tokens,
+    }
 }
 
-fn count_line_types(content: &str) -> (usize, usize, usize) {
-    let mut code = 0;
-    let mut comments = 0;
-    let mut blanks = 0;
-    let mut in_block_comment = false;
+/// `content` minus the lines `classify_lines` marks as comments
+fn without_comment_lines(content: &str) -> String {
+    let mut kept: String = classify_lines(content)
+        .into_iter()
+        .filter(|(kind, _)| *kind != LineKind::Comment)
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if content.ends_with('\n') {
+        kept.push('\n');
+    }
+    kept
+}
+
+/// Which token count drives the efficiency grade (`--grade-by`)
+#[derive(Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GradeBy {
+    /// Every token, comments included
+    #[default]
+    Total,
+    /// Tokens without the estimated comment tokens, so documentation isn't penalized
+    Effective,
+}
+
+/// Which part of each file `ProjectStats::scoped` keeps
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CodeScope {
+    /// Everything except `#[cfg(test)]` items and `#[test]` functions
+    Production,
+    /// Only `#[cfg(test)]` items, `#[test]` functions, and files under `tests/`
+    Tests,
+}
 
-    for line in content.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            blanks += 1;
-        } else if in_block_comment {
-            comments += 1;
-            if trimmed.contains("*/") {
-                in_block_comment = false;
+impl ProjectStats {
+    fn from_files(files: Vec<FileStats>) -> Self {
+        Self {
+            total_lines: files.iter().map(|f| f.lines).sum(),
+            total_tokens: files.iter().map(|f| f.tokens).sum(),
+            effective_tokens: files.iter().map(|f| f.effective_tokens).sum(),
+            code_lines: files.iter().map(|f| f.code_lines).sum(),
+            comment_lines: files.iter().map(|f| f.comment_lines).sum(),
+            blank_lines: files.iter().map(|f| f.blank_lines).sum(),
+            files,
+        }
+    }
+
+    /// T/L the grade is computed from: all tokens over all lines, or effective tokens over the
+    /// non-comment lines, so comments neither lower nor raise the grade
+    pub fn grade_ratio(&self, by: GradeBy) -> f64 {
+        match by {
+            GradeBy::Total => ratio(self.total_tokens, self.total_lines),
+            GradeBy::Effective => {
+                ratio(self.effective_tokens, self.total_lines - self.comment_lines)
             }
-        } else if trimmed.starts_with("//") {
-            comments += 1;
-        } else if trimmed.starts_with("/*") {
-            comments += 1;
-            if !trimmed.contains("*/") {
-                in_block_comment = true;
+        }
+    }
+
+    /// Drop generated files (see `is_generated`), returning the rest and the dropped paths
+    pub fn without_generated(self) -> (Self, Vec<String>) {
+        let (generated, files): (Vec<_>, Vec<_>) =
+            self.files.into_iter().partition(|f| is_generated(&f.content));
+        (Self::from_files(files), generated.into_iter().map(|f| f.path).collect())
+    }
+
+    /// Re-measure every file keeping only its production or test code; files left empty are dropped
+    pub fn scoped(&self, scope: CodeScope) -> Result<Self> {
+        let bpe = bpe()?;
+        let files = self
+            .files
+            .iter()
+            .filter_map(|f| {
+                let (production, tests) = if is_test_file(&f.path) {
+                    (String::new(), f.content.clone())
+                } else {
+                    split_tests(&f.content)
+                };
+                let content = if scope == CodeScope::Tests { tests } else { production };
+                (!content.trim().is_empty()).then(|| measure(bpe, f.path.clone(), content))
+            })
+            .collect();
+        Ok(Self::from_files(files))
+    }
+}
+
+/// Markers that flag a file as generated when they appear in its first few lines
+const GENERATED_MARKERS: &[&str] = &["@generated", "DO NOT EDIT", "This file is autogenerated"];
+
+/// Whether one of the first 5 lines is a `//` comment with a generated-code marker
+pub fn is_generated(content: &str) -> bool {
+    content.lines().take(5).any(|line| {
+        line.trim_start()
+            .strip_prefix("//")
+            .is_some_and(|comment| GENERATED_MARKERS.iter().any(|m| comment.contains(m)))
+    })
+}
+
+/// Integration tests: anything under a `tests/` directory
+fn is_test_file(path: &str) -> bool {
+    Path::new(path).components().any(|c| c.as_os_str() == "tests")
+}
+
+/// Split `content` into (production, test) code. Test code is every item starting with a
+/// `#[cfg(test)]`, `#[test]` or `#[tokio::test]` line, up to its closing brace or semicolon.
+pub fn split_tests(content: &str) -> (String, String) {
+    let (production, items) = split_test_items(content);
+    (production, items.concat())
+}
+
+/// Like `split_tests`, but returns each test item separately, in source order
+pub fn split_test_items(content: &str) -> (String, Vec<&str>) {
+    let mut production = String::new();
+    let mut items = Vec::new();
+    let mut rest = content;
+    while let Some(start) = find_test_attr(rest) {
+        production.push_str(&rest[..start]);
+        let len = item_end(&rest[start..]).unwrap_or(rest.len() - start);
+        items.push(&rest[start..start + len]);
+        rest = &rest[start + len..];
+    }
+    production.push_str(rest);
+    (production, items)
+}
+
+/// Byte offset of the first line that opens with a test attribute
+fn find_test_attr(s: &str) -> Option<usize> {
+    let mut offset = 0;
+    for line in s.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if ["#[cfg(test)]", "#[test]", "#[tokio::test"].iter().any(|a| trimmed.starts_with(a)) {
+            return Some(offset);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Length of the item at the start of `s`, through the end of its last line, or `None` if it
+/// never closes. Skips brackets inside strings, chars and comments.
+pub(crate) fn item_end(s: &str) -> Option<usize> {
+    let b = s.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'/' if b.get(i + 1) == Some(&b'/') => {
+                i += s[i..].find('\n').unwrap_or(s.len() - i);
+                continue;
             }
-        } else {
-            code += 1;
+            b'/' if b.get(i + 1) == Some(&b'*') => {
+                i += s[i..].find("*/").map_or(s.len() - i, |e| e + 2);
+                continue;
+            }
+            b'r' if matches!(b.get(i + 1), Some(b'"' | b'#')) && !is_ident(b, i) => {
+                let hashes = b[i + 1..].iter().take_while(|&&c| c == b'#').count();
+                if b.get(i + 1 + hashes) == Some(&b'"') {
+                    let close = format!("\"{}", "#".repeat(hashes));
+                    let body = i + 2 + hashes;
+                    i = s[body..].find(&close).map_or(s.len(), |e| body + e + close.len());
+                    continue;
+                }
+            }
+            b'"' => {
+                i += 1;
+                while i < b.len() && b[i] != b'"' {
+                    i += if b[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'\'' if b.get(i + 1) == Some(&b'\\') && i + 3 <= b.len() => {
+                i += 3 + s[i + 3..].find('\'').unwrap_or(s.len() - i - 3);
+            }
+            b'\'' if b.get(i + 2) == Some(&b'\'') => i += 2,
+            b'{' | b'(' | b'[' => depth += 1,
+            b')' | b']' => depth = depth.saturating_sub(1),
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Some(line_end(s, i));
+                }
+            }
+            b';' if depth == 0 => return Some(line_end(s, i)),
+            _ => {}
         }
+        i += 1;
+    }
+    None
+}
+
+fn line_end(s: &str, i: usize) -> usize {
+    s[i..].find('\n').map_or(s.len(), |e| i + e + 1)
+}
+
+fn is_ident(b: &[u8], i: usize) -> bool {
+    i > 0 && (b[i - 1].is_ascii_alphanumeric() || b[i - 1] == b'_')
+}
+
+/// Tokens in `src/` only, so tests/ and examples/ don't skew the score
+pub fn count_src_tokens() -> Result<usize> {
+    let bpe = bpe()?;
+    Ok(src_contents().map(|c| bpe.encode_with_special_tokens(&c).len()).sum())
+}
+
+/// Lines in `src/` only, the counterpart of `count_src_tokens`
+pub fn count_src_lines() -> Result<usize> {
+    Ok(src_contents().map(|c| c.lines().count()).sum())
+}
+
+fn src_contents() -> impl Iterator<Item = String> {
+    rust_file_walker_at(Path::new("src")).filter_map(|e| std::fs::read_to_string(e.path()).ok())
+}
+
+pub fn count_tokens(content: &str) -> Result<usize> {
+    Ok(bpe()?.encode_with_special_tokens(content).len())
+}
+
+/// `count_tokens` for each of `contents`, in order
+pub fn count_tokens_batch(contents: &[&str]) -> Result<Vec<usize>> {
+    let bpe = bpe()?;
+    Ok(contents.iter().map(|c| bpe.encode_with_special_tokens(c).len()).collect())
+}
+
+/// The o200k_base encoder, built on first use and shared by every count after that
+fn bpe() -> Result<&'static CoreBPE> {
+    static BPE: OnceLock<CoreBPE> = OnceLock::new();
+    if let Some(bpe) = BPE.get() {
+        return Ok(bpe);
     }
+    let bpe = o200k_base()?;
+    Ok(BPE.get_or_init(|| bpe))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineKind {
+    Code,
+    Comment,
+    Blank,
+}
+
+/// Classify each line as code, comment (line or block), or blank
+pub fn classify_lines(content: &str) -> Vec<(LineKind, &str)> {
+    let mut in_block_comment = false;
 
-    (code, comments, blanks)
+    content
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            let kind = if trimmed.is_empty() {
+                LineKind::Blank
+            } else if in_block_comment {
+                if trimmed.contains("*/") {
+                    in_block_comment = false;
+                }
+                LineKind::Comment
+            } else if trimmed.starts_with("//") {
+                LineKind::Comment
+            } else if trimmed.starts_with("/*") {
+                if !trimmed.contains("*/") {
+                    in_block_comment = true;
+                }
+                LineKind::Comment
+            } else {
+                LineKind::Code
+            };
+            (kind, line)
+        })
+        .collect()
+}
+
+fn count_line_types(content: &str) -> (usize, usize, usize) {
+    let kinds = classify_lines(content);
+    (
+        count_kind(&kinds, LineKind::Code),
+        count_kind(&kinds, LineKind::Comment),
+        count_kind(&kinds, LineKind::Blank),
+    )
+}
+
+fn count_kind(kinds: &[(LineKind, &str)], kind: LineKind) -> usize {
+    kinds.iter().filter(|(k, _)| *k == kind).count()
+}
+
+/// Per-file code/comment/blank split, with code and comments tokenized separately
+#[derive(Serialize)]
+pub struct LineBreakdown {
+    pub code: usize,
+    pub comments: usize,
+    pub blanks: usize,
+    pub code_tokens: usize,
+    pub comment_tokens: usize,
+}
+
+impl LineBreakdown {
+    /// Comment tokens per code token; high values mark good rewrite targets
+    pub fn comment_overhead(&self) -> f64 {
+        ratio(self.comment_tokens, self.code_tokens)
+    }
+}
+
+pub fn line_breakdown(content: &str) -> Result<LineBreakdown> {
+    let kinds = classify_lines(content);
+    let join = |kind| {
+        kinds.iter().filter(|(k, _)| *k == kind).map(|(_, l)| *l).collect::<Vec<_>>().join("\n")
+    };
+
+    Ok(LineBreakdown {
+        code: count_kind(&kinds, LineKind::Code),
+        comments: count_kind(&kinds, LineKind::Comment),
+        blanks: count_kind(&kinds, LineKind::Blank),
+        code_tokens: count_tokens(&join(LineKind::Code))?,
+        comment_tokens: count_tokens(&join(LineKind::Comment))?,
+    })
+}
+
+/// Spread of per-file token counts, to tell a flat project from a long-tailed one
+#[derive(Serialize)]
+pub struct Distribution {
+    pub min: usize,
+    pub p50: usize,
+    pub p75: usize,
+    pub p90: usize,
+    pub p95: usize,
+    pub p99: usize,
+    pub max: usize,
+    pub stddev: f64,
+}
+
+impl Distribution {
+    /// Compact one-liner, e.g. `min=12 p50=120 p75=280 ... max=1400 σ=320`
+    pub fn summary(&self) -> String {
+        format!(
+            "min={} p50={} p75={} p90={} p95={} p99={} max={} σ={:.0}",
+            self.min, self.p50, self.p75, self.p90, self.p95, self.p99, self.max, self.stddev
+        )
+    }
+}
+
+/// Nearest-rank percentiles and population standard deviation of `counts`
+pub fn distribution(counts: &[usize]) -> Distribution {
+    let mut sorted = counts.to_vec();
+    sorted.sort_unstable();
+    let n = sorted.len();
+    let pct = |p: usize| {
+        if n == 0 { 0 } else { sorted[(p * n).div_ceil(100).clamp(1, n) - 1] }
+    };
+
+    let mean = sorted.iter().sum::<usize>() as f64 / n.max(1) as f64;
+    let variance = sorted.iter().map(|&c| (c as f64 - mean).powi(2)).sum::<f64>() / n.max(1) as f64;
+
+    Distribution {
+        min: sorted.first().copied().unwrap_or(0),
+        p50: pct(50),
+        p75: pct(75),
+        p90: pct(90),
+        p95: pct(95),
+        p99: pct(99),
+        max: sorted.last().copied().unwrap_or(0),
+        stddev: variance.sqrt(),
+    }
 }
 
 pub fn efficiency_grade(ratio: f64) -> (&'static str, &'static str, &'static str) {
@@ -127,6 +527,34 @@ pub fn default_model() -> String {
     std::env::var("CARGO_SYNTAX_MODEL").unwrap_or_else(|_| "deepseek/deepseek-chat".to_string())
 }
 
+/// Files larger than this are not sent to models with an unknown context limit
+pub const DEFAULT_MAX_TOKENS: usize = 20_000;
+
+pub fn model_context_limit(model: &str) -> Option<usize> {
+    let id = model.to_lowercase();
+    if id.contains("gemini") || id.contains("claude-sonnet-4") || id.contains("claude-opus") {
+        Some(100_000)
+    } else if id.contains("gpt-4o") || id.contains("gpt-4.1") {
+        Some(80_000)
+    } else if id.contains("deepseek") || id.contains("qwen") {
+        Some(30_000)
+    } else {
+        None
+    }
+}
+
+pub fn model_token_limit(model: &str) -> usize {
+    model_context_limit(model).unwrap_or(DEFAULT_MAX_TOKENS)
+}
+
+/// Use `fallback` when a `file_tokens`-sized prompt would overflow `model`
+pub fn route_model<'a>(model: &'a str, fallback: Option<&'a str>, file_tokens: usize) -> &'a str {
+    match fallback {
+        Some(fb) if file_tokens > model_token_limit(model) => fb,
+        _ => model,
+    }
+}
+
 pub fn ask_accept(prompt: &str) -> Result<String> {
     use std::io::{self, BufRead, Write};
     print!("{prompt} ");
@@ -149,6 +577,43 @@ pub fn strip_markdown_fences(s: &str) -> String {
     s.trim().to_string()
 }
 
+/// Every fenced code block in `s`, in order. Blocks tagged with a non-Rust language
+/// (e.g. `toml`, `json`) come back as empty strings; unfenced input is one block.
+/// Fences must start a line and close with at least as many backticks, so doc-comment
+/// examples (`/// ````) inside a block stay part of it.
+pub fn strip_all_markdown_fences(s: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(usize, bool, Vec<&str>)> = None;
+
+    for line in s.lines() {
+        let fence = line.trim_start().bytes().take_while(|&b| b == b'`').count();
+        match &mut open {
+            None if fence >= 3 => {
+                let lang = line.trim_start()[fence..].trim();
+                open = Some((fence, matches!(lang, "" | "rust" | "rs"), Vec::new()));
+            }
+            None => {}
+            Some((len, is_rust, body)) => {
+                if fence >= *len && line.trim().len() == fence {
+                    blocks.push(if *is_rust {
+                        body.join("\n").trim().to_string()
+                    } else {
+                        String::new()
+                    });
+                    open = None;
+                } else {
+                    body.push(line);
+                }
+            }
+        }
+    }
+    if let Some((_, is_rust, body)) = open {
+        blocks.push(if is_rust { body.join("\n").trim().to_string() } else { String::new() });
+    }
+
+    if blocks.is_empty() { vec![s.trim().to_string()] } else { blocks }
+}
+
 pub fn build_manifest(stats: &ProjectStats) -> String {
     let mut manifest = String::new();
     for f in &stats.files {
@@ -159,6 +624,50 @@ pub fn build_manifest(stats: &ProjectStats) -> String {
     manifest
 }
 
+/// Like `build_manifest`, but cap each file at `max_tokens_per_file` tokens and stop adding
+/// files once `max_total_tokens` is reached. Files are cut at line boundaries.
+pub fn build_manifest_truncated(
+    stats: &ProjectStats,
+    max_tokens_per_file: usize,
+    max_total_tokens: usize,
+) -> Result<String> {
+    let bpe = bpe()?;
+    let mut manifest = String::new();
+    let mut used = 0;
+
+    for (i, f) in stats.files.iter().enumerate() {
+        if used >= max_total_tokens {
+            let rest = stats.files.len() - i;
+            manifest.push_str(&format!("// ... [truncated: {rest} more file(s) omitted]\n"));
+            break;
+        }
+
+        manifest.push_str(&format!("=== {} ({} tokens) ===\n", f.path, f.tokens));
+        let budget = max_tokens_per_file.min(max_total_tokens - used);
+        if f.tokens <= budget {
+            manifest.push_str(&f.content);
+            used += f.tokens;
+        } else {
+            let mut kept = 0;
+            for line in f.content.lines() {
+                let cost = bpe.encode_with_special_tokens(line).len() + 1;
+                if kept + cost > budget {
+                    break;
+                }
+                manifest.push_str(line);
+                manifest.push('\n');
+                kept += cost;
+            }
+            manifest.push_str("// ... [truncated]");
+            // A file cut short by the overall budget uses it up
+            used = if budget < max_tokens_per_file { max_total_tokens } else { used + kept };
+        }
+        manifest.push_str("\n\n");
+    }
+
+    Ok(manifest)
+}
+
 pub fn read_rs_file(file: &str) -> Result<(String, usize, usize)> {
     use std::path::Path;
     let path = Path::new(file);
@@ -174,14 +683,48 @@ pub fn read_rs_file(file: &str) -> Result<(String, usize, usize)> {
     Ok((content, tokens, lines))
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct RevStats {
     pub files: usize,
     pub tokens: usize,
     pub lines: usize,
 }
 
+impl RevStats {
+    /// Totals of a scanned working tree, for comparing it with a git rev
+    pub fn of_project(stats: &ProjectStats) -> Self {
+        Self { files: stats.files.len(), tokens: stats.total_tokens, lines: stats.total_lines }
+    }
+}
+
+/// Change from one `RevStats` to another, signed as `after - before`
+pub struct RevStatsDelta {
+    pub file_delta: isize,
+    pub token_delta: isize,
+    pub line_delta: isize,
+    /// Tokens in `before`, the base of `token_pct_change`
+    pub base_tokens: usize,
+}
+
+impl RevStatsDelta {
+    /// Signed percentage change in tokens (0.0 when `before` had none)
+    pub fn token_pct_change(&self) -> f64 {
+        pct_delta(self.token_delta, self.base_tokens)
+    }
+}
+
+pub fn diff_rev_stats(before: RevStats, after: RevStats) -> RevStatsDelta {
+    let delta = |a: usize, b: usize| b as isize - a as isize;
+    RevStatsDelta {
+        file_delta: delta(before.files, after.files),
+        token_delta: delta(before.tokens, after.tokens),
+        line_delta: delta(before.lines, after.lines),
+        base_tokens: before.tokens,
+    }
+}
+
 pub fn count_rev_tokens(rev: &str) -> Result<RevStats> {
-    let bpe = o200k_base()?;
+    let bpe = bpe()?;
     let rs_files = git_list_rs_files(rev)?;
     let mut total_tokens = 0;
     let mut total_lines = 0;
@@ -198,10 +741,11 @@ pub fn count_rev_tokens(rev: &str) -> Result<RevStats> {
 
 pub fn scan_project_sorted() -> Result<ProjectStats> {
     let mut stats = scan_project()?;
-    stats.files.sort_by(|a, b| b.tokens.cmp(&a.tokens));
+    stats.files.sort_by(FileStats::cmp_by_tokens);
     Ok(stats)
 }
 
+/// Signed percentage change of `delta` relative to `base` (0.0 when `base` is 0)
 pub fn pct_delta(delta: isize, base: usize) -> f64 {
     if base > 0 { (delta as f64 / base as f64) * 100.0 } else { 0.0 }
 }
@@ -213,6 +757,8 @@ pub struct Suggestion {
     pub tokens_saved: u32,
 }
 
+/// JSON schema for one `Suggestion`, shared by the review and diff structured prompts.
+/// `rewrite` reports applied changes without a location, so it keeps its own schema.
 pub fn suggestion_items_schema() -> serde_json::Value {
     json!({
         "type": "object",
@@ -240,6 +786,30 @@ pub fn git_list_rs_files(rev: &str) -> Result<Vec<String>> {
         .collect())
 }
 
+/// Tokens on added and removed `.rs` lines in `git diff <range>`
+pub fn git_diff_token_count(range: &str) -> Result<(usize, usize)> {
+    use std::process::Command;
+    let output = Command::new("git").args(["diff", range, "--", "*.rs"]).output()?;
+    if !output.status.success() {
+        anyhow::bail!("git diff failed for {range}");
+    }
+    diff_token_count(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// (added, removed) tokens in unified diff text, ignoring the `+++`/`---` file headers
+pub fn diff_token_count(diff: &str) -> Result<(usize, usize)> {
+    let bpe = bpe()?;
+    let side = |sign: char, header: &str| {
+        let text: Vec<&str> = diff
+            .lines()
+            .filter(|l| !l.starts_with(header))
+            .filter_map(|l| l.strip_prefix(sign))
+            .collect();
+        bpe.encode_with_special_tokens(&text.join("\n")).len()
+    };
+    Ok((side('+', "+++ "), side('-', "--- ")))
+}
+
 /// Read a file's content at a specific git ref
 pub fn git_show_file(rev: &str, file: &str) -> Result<String> {
     use std::process::Command;
@@ -249,3 +819,20 @@ pub fn git_show_file(rev: &str, file: &str) -> Result<String> {
     }
     Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
+
+/// Git blob SHA-1 of `content`, as `git hash-object` computes it (nothing is written)
+pub fn git_hash_object(content: &str) -> Result<String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    let mut child = Command::new("git")
+        .args(["hash-object", "--stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("stdin is piped").write_all(content.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("git hash-object failed");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}